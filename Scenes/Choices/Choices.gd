@@ -55,7 +55,7 @@ func choice_picked_handler(_choice_str):
 	self.visible = false
 	
 func default_choice_clicked_handler(current_choice, default_choice):
-	if current_choice == "Choose Proton":
+	if current_choice in ["Choose Proton", "Launch Options", "Reset Setup"]:
 		return
 
 	if default_choice != current_choice:
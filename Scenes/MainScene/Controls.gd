@@ -8,6 +8,8 @@ signal mode_changed
 signal default_choice_selected
 # warning-ignore:unused_signal
 signal simulate_button
+# warning-ignore:unused_signal
+signal secondary_changed
 
 var last_choice
 var last_mode = "choice"
@@ -29,6 +31,8 @@ func _ready():
 	# warning-ignore:return_value_discarded
 	connect("simulate_button", Callable(self, "simulate_button_handler"))
 	# warning-ignore:return_value_discarded
+	connect("secondary_changed", Callable(self, "secondary_changed_handler"))
+	# warning-ignore:return_value_discarded
 	ControllerIcons.connect("input_type_changed", Callable(self, "_on_input_type_changed"))
 	
 func signal_handler(choice_data):
@@ -69,6 +73,9 @@ func mode_changed_handler(new_mode, new_mode_id):
 		ok_button.visible = true
 		ok_button.disabled = false
 		secondary_button.text = "Paste from Clipboard"
+	elif new_mode == "dialog":
+		ok_button.visible = true
+		ok_button.disabled = false
 		
 	if cancel_button.visible and !ok_button.visible:
 		cancel_button.size_flags_horizontal |= SIZE_EXPAND
@@ -76,6 +83,11 @@ func mode_changed_handler(new_mode, new_mode_id):
 	else:
 		cancel_button.size_flags_horizontal = SIZE_SHRINK_END
 		
+func secondary_changed_handler(text):
+	secondary_button.visible = text != ""
+	secondary_button.disabled = false
+	secondary_button.text = text
+	
 func default_choice_selected_handler(new_default_choice):
 	last_default_choice = new_default_choice
 	
@@ -102,6 +114,14 @@ func _on_OkButton_pressed():
 		var input_value = last_mode_id + get_node("../Prompt/TextEdit").text
 		get_node("../../LuxClient").question_confirmed(input_value)
 		get_node("../Prompt").emit_signal("hide_prompt")
+	elif last_mode == "dialog":
+		var prompt = get_node("../Prompt")
+		var answer = {"dialog": prompt.last_prompt.dialog, "value": prompt.get_dialog_value()}
+		var validation_error = get_node("../../LuxClient").dialog_answered(JSON.stringify(answer))
+		if validation_error:
+			prompt.show_validation_error(validation_error)
+		else:
+			prompt.emit_signal("hide_prompt")
 	elif last_mode == "error":
 		_on_CancelButton_pressed()
 	elif last_mode == "default_choice":
@@ -125,3 +145,5 @@ func _on_SecondaryButton_pressed():
 		get_node("../Choices").emit_signal("default_choice_clicked", last_choice, last_default_choice)
 	elif last_mode == "input":
 		get_node("../Prompt").emit_signal("clipboard_paste")
+	elif last_mode == "dialog":
+		get_node("../Prompt").dialog_secondary_action()
@@ -12,6 +12,9 @@ signal clipboard_paste
 @onready var prompt_label = get_node("PromptLabel")
 @onready var prompt_rich_text = get_node("PromptRichText")
 @onready var text_edit = get_node("TextEdit")
+@onready var choice_list = get_node("ChoiceList")
+@onready var check_box = get_node("CheckBox")
+@onready var file_dialog = get_node("FileDialog")
 @onready var timer = get_node("Timer")
 
 var DEFAULT_TIMER_START = 4
@@ -50,6 +53,7 @@ func show_prompt_handler(data_str):
 func show_prompts_handler(prompts):
 	last_prompts = prompts.prompt_items
 	last_prompts_id = prompts.prompt_id
+	last_prompt_index = 0
 	process_show_prompt(last_prompts[last_prompt_index])
 	last_prompt_index += 1
 	
@@ -77,11 +81,39 @@ func process_show_prompt(prompt):
 		change_label_for_timer()
 		timer.start()
 	
-	if prompt.prompt_type == "input":
+	var dialog_type = null
+	if prompt.prompt_type == "dialog":
+		dialog_type = prompt.dialog.dialog_type
+	
+	if prompt.prompt_type == "input" or dialog_type in ["input", "file", "directory"]:
+		if prompt.get("default_value"):
+			text_edit.text = prompt.default_value
+		else:
+			text_edit.text = ''
 		text_edit.visible = true
 	else:
 		text_edit.visible = false
-		
+	
+	choice_list.visible = dialog_type == "choice"
+	if dialog_type == "choice":
+		choice_list.clear()
+		for choice in prompt.dialog.choices:
+			choice_list.add_item(choice)
+			if choice == prompt.get("default_value"):
+				choice_list.select(choice_list.item_count - 1)
+	
+	check_box.visible = dialog_type == "yes_no"
+	if dialog_type == "yes_no":
+		check_box.text = prompt.title
+		check_box.button_pressed = prompt.get("default_value") == "true"
+	
+	if dialog_type in ["file", "directory"]:
+		get_node("../Controls").emit_signal("secondary_changed", "Browse")
+	elif dialog_type == "input":
+		get_node("../Controls").emit_signal("secondary_changed", "Paste from Clipboard")
+	elif dialog_type:
+		get_node("../Controls").emit_signal("secondary_changed", "")
+	
 	
 	self.visible = true
 
@@ -118,3 +150,30 @@ func _on_Timer_timeout():
 			get_node("../Controls").emit_signal("simulate_button", "ok")
 	
 	change_label_for_timer()
+
+func get_dialog_value():
+	var dialog_type = last_prompt.dialog.dialog_type
+	if dialog_type == "choice":
+		if choice_list.selected < 0:
+			return ''
+		return choice_list.get_item_text(choice_list.selected)
+	elif dialog_type == "yes_no":
+		return "true" if check_box.button_pressed else "false"
+	return text_edit.text
+
+func show_validation_error(message):
+	prompt_label.text = last_prompt.label + "\n\n" + message
+
+func dialog_secondary_action():
+	var dialog_type = last_prompt.dialog.dialog_type
+	if dialog_type == "file":
+		file_dialog.file_mode = FileDialog.FILE_MODE_OPEN_FILE
+		file_dialog.popup_centered_ratio(0.8)
+	elif dialog_type == "directory":
+		file_dialog.file_mode = FileDialog.FILE_MODE_OPEN_DIR
+		file_dialog.popup_centered_ratio(0.8)
+	elif dialog_type == "input":
+		clipboard_paste_handler()
+
+func _on_FileDialog_selected(path):
+	text_edit.text = path
@@ -8,6 +8,13 @@ fi
 
 DIR="$( cd "$( dirname "${BASH_SOURCE[0]}" )" >/dev/null 2>&1 && pwd )"
 
+case "$1" in
+  list-games|list-engines|list-versions|pin|unpin|rollback|prefix-info|prefix-reset|prefix-backup|launch-options|reset-setup)
+    "$DIR/luxtorpeda.x86_64" --headless --quiet -- "$@"
+    exit $?
+    ;;
+esac
+
 if [[ $2 == *"iscriptevaluator.exe"* ]]; then
   echo "ignoring iscriptevaluator.exe"
   exit 0
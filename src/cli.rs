@@ -0,0 +1,599 @@
+use serde::Serialize;
+use std::io;
+use std::io::Error;
+use std::path::Path;
+
+use crate::launch_profile::LaunchProfile;
+use crate::package;
+use crate::package_metadata::{GameFilter, PackageMetadata};
+use crate::prefix;
+use crate::prefix::PrefixKind;
+
+const CLI_COMMANDS: [&str; 11] = [
+    "list-games",
+    "list-engines",
+    "list-versions",
+    "pin",
+    "unpin",
+    "rollback",
+    "prefix-info",
+    "prefix-reset",
+    "prefix-backup",
+    "launch-options",
+    "reset-setup",
+];
+
+#[derive(Serialize, Debug)]
+struct GameListing {
+    app_id: String,
+    game_name: String,
+    engines: Vec<String>,
+    cloud_supported: bool,
+    controller_supported: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct EngineListing {
+    engine_name: String,
+    version: String,
+    license: String,
+    author: String,
+    controller_supported: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct VersionListing {
+    version: String,
+    installed: bool,
+    pinned: bool,
+}
+
+pub fn usage() {
+    println!("       lux list-games [--engine <name>] [--name <text>] [--cloud] [--controller] [--notice <key>] [--json]");
+    println!("       lux list-engines <app_id> [--json]");
+    println!("       lux list-versions <app_id> [--json]");
+    println!("       lux pin <app_id> [version]");
+    println!("       lux unpin <app_id>");
+    println!("       lux rollback <app_id> <version>");
+    println!("       lux prefix-info <app_id> [--wine]");
+    println!("       lux prefix-reset <app_id> [--wine] --yes");
+    println!("       lux prefix-backup <app_id> [--wine] [destination.tar.gz]");
+    println!("       lux launch-options <app_id> [<options> | --clear] [--working-dir <path>]");
+    println!("       lux reset-setup <app_id>");
+}
+
+/// Verbs Steam uses to launch a game. Anything after them belongs to the game.
+const RUN_VERBS: [&str; 3] = ["run", "wait-before-run", "waitforexitandrun"];
+
+/// Returns the command line arguments starting at the luxtorpeda command, if one was given.
+///
+/// The command is either the first argument, or the argument right after a `--` separator so
+/// that arguments meant for Godot (such as `--headless` or `--rendering-driver vulkan`) can be
+/// placed before it. A separator following a run verb belongs to the game and is ignored.
+pub fn find_cli_args<'a>(args: &'a [&'a str]) -> Option<&'a [&'a str]> {
+    let idx = if args.get(1).is_some_and(|arg| CLI_COMMANDS.contains(arg)) {
+        1
+    } else {
+        args.iter()
+            .skip(1)
+            .take_while(|arg| !RUN_VERBS.contains(arg))
+            .position(|arg| *arg == "--")?
+            + 2
+    };
+    let cli_args = args.get(idx..)?;
+    if cli_args
+        .first()
+        .is_some_and(|cmd| CLI_COMMANDS.contains(cmd))
+    {
+        Some(cli_args)
+    } else {
+        None
+    }
+}
+
+/// Runs a luxtorpeda command line command and returns the process exit code.
+pub fn run(cli_args: &[&str]) -> i32 {
+    let cmd = cli_args[0];
+    let cmd_args = &cli_args[1..];
+
+    let result = match cmd {
+        "list-games" => list_games(cmd_args),
+        "list-engines" => list_engines(cmd_args),
+        "list-versions" => list_versions(cmd_args),
+        "pin" => pin(cmd_args),
+        "unpin" => unpin(cmd_args),
+        "rollback" => rollback(cmd_args),
+        "prefix-info" => prefix_info(cmd_args),
+        "prefix-reset" => prefix_reset(cmd_args),
+        "prefix-backup" => prefix_backup(cmd_args),
+        "launch-options" => launch_options(cmd_args),
+        "reset-setup" => reset_setup(cmd_args),
+        _ => Err(Error::other(format!("unknown command {}", cmd))),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("lux {}: {}", cmd, err);
+            1
+        }
+    }
+}
+
+fn list_games(cmd_args: &[&str]) -> io::Result<()> {
+    let mut filter: GameFilter = Default::default();
+    let mut json_output = false;
+
+    let mut args_iter = cmd_args.iter();
+    while let Some(arg) = args_iter.next() {
+        match *arg {
+            "--engine" => filter.engine_name = Some(option_value(arg, args_iter.next())?),
+            "--name" => filter.name = Some(option_value(arg, args_iter.next())?),
+            "--notice" => filter.notice_key = Some(option_value(arg, args_iter.next())?),
+            "--cloud" => filter.cloud_supported = true,
+            "--controller" => filter.controller_supported = true,
+            "--json" => json_output = true,
+            _ => return Err(Error::other(format!("unknown option {}", arg))),
+        }
+    }
+
+    let package_metadata = load_package_metadata()?;
+    let listings: Vec<GameListing> = package_metadata
+        .find_games(&filter)
+        .into_iter()
+        .map(|game| GameListing {
+            app_id: game.app_id.to_string(),
+            game_name: game.game_name.to_string(),
+            engines: game.engine_names(),
+            cloud_supported: game.cloud_available && game.cloud_supported,
+            controller_supported: package_metadata.game_has_controller_support(game),
+        })
+        .collect();
+
+    if json_output {
+        return print_json(&listings);
+    }
+
+    let rows: Vec<Vec<String>> = listings
+        .iter()
+        .map(|listing| {
+            vec![
+                listing.app_id.to_string(),
+                listing.game_name.to_string(),
+                listing.engines.join(", "),
+                yes_no(listing.cloud_supported),
+                yes_no(listing.controller_supported),
+            ]
+        })
+        .collect();
+    print_table(&["APP ID", "NAME", "ENGINES", "CLOUD", "CONTROLLER"], &rows);
+    println!("{} games found", rows.len());
+
+    Ok(())
+}
+
+fn list_engines(cmd_args: &[&str]) -> io::Result<()> {
+    let mut app_id: Option<&str> = None;
+    let mut json_output = false;
+
+    for arg in cmd_args {
+        match *arg {
+            "--json" => json_output = true,
+            _ if app_id.is_none() && !arg.starts_with('-') => app_id = Some(*arg),
+            _ => return Err(Error::other(format!("unknown option {}", arg))),
+        }
+    }
+
+    let Some(app_id) = app_id else {
+        return Err(Error::other("app_id is required"));
+    };
+
+    let package_metadata = load_package_metadata()?;
+    if package_metadata.find_game_by_app_id(app_id).is_none() {
+        return Err(Error::other(format!("no game found for app id {}", app_id)));
+    }
+
+    let listings: Vec<EngineListing> = package_metadata
+        .find_engines_for_app_id(app_id)
+        .into_iter()
+        .map(|engine| EngineListing {
+            controller_supported: engine.controller_supported
+                || engine.controller_supported_manual_game,
+            engine_name: engine.engine_name,
+            version: engine.version,
+            license: engine.license,
+            author: engine.author,
+        })
+        .collect();
+
+    if json_output {
+        return print_json(&listings);
+    }
+
+    let rows: Vec<Vec<String>> = listings
+        .iter()
+        .map(|listing| {
+            vec![
+                listing.engine_name.to_string(),
+                listing.version.to_string(),
+                listing.license.to_string(),
+                listing.author.to_string(),
+                yes_no(listing.controller_supported),
+            ]
+        })
+        .collect();
+    print_table(
+        &["ENGINE", "VERSION", "LICENSE", "AUTHOR", "CONTROLLER"],
+        &rows,
+    );
+
+    Ok(())
+}
+
+fn list_versions(cmd_args: &[&str]) -> io::Result<()> {
+    let mut app_id: Option<&str> = None;
+    let mut json_output = false;
+
+    for arg in cmd_args {
+        match *arg {
+            "--json" => json_output = true,
+            _ if app_id.is_none() && !arg.starts_with('-') => app_id = Some(*arg),
+            _ => return Err(Error::other(format!("unknown option {}", arg))),
+        }
+    }
+
+    let Some(app_id) = app_id else {
+        return Err(Error::other("app_id is required"));
+    };
+    let installed_engine = find_installed_engine(app_id)?;
+
+    let mut versions = installed_engine.archived_versions.clone();
+    if !versions.contains(&installed_engine.version) {
+        versions.push(installed_engine.version.to_string());
+    }
+
+    let listings: Vec<VersionListing> = versions
+        .into_iter()
+        .rev()
+        .map(|version| VersionListing {
+            installed: version == installed_engine.version,
+            pinned: Some(&version) == installed_engine.pinned_version.as_ref(),
+            version,
+        })
+        .collect();
+
+    if json_output {
+        return print_json(&listings);
+    }
+
+    println!("{}", installed_engine.engine_name);
+    let rows: Vec<Vec<String>> = listings
+        .iter()
+        .map(|listing| {
+            vec![
+                listing.version.to_string(),
+                yes_no(listing.installed),
+                yes_no(listing.pinned),
+            ]
+        })
+        .collect();
+    print_table(&["VERSION", "INSTALLED", "PINNED"], &rows);
+
+    if let Some(rollback_version) = &installed_engine.rollback_version {
+        println!(
+            "Rollback to {} will be installed on the next launch",
+            rollback_version
+        );
+    }
+
+    Ok(())
+}
+
+fn pin(cmd_args: &[&str]) -> io::Result<()> {
+    let (app_id, version) = match cmd_args {
+        [app_id] => (*app_id, None),
+        [app_id, version] => (*app_id, Some(*version)),
+        _ => return Err(Error::other("expected <app_id> [version]")),
+    };
+    let installed_engine = find_installed_engine(app_id)?;
+
+    package::pin_engine_version(app_id, version)?;
+
+    let version = version.unwrap_or(&installed_engine.version);
+    println!("{} pinned to {}", installed_engine.engine_name, version);
+    if version != installed_engine.version {
+        println!("Version {} will be installed on the next launch", version);
+    }
+
+    Ok(())
+}
+
+fn unpin(cmd_args: &[&str]) -> io::Result<()> {
+    let [app_id] = cmd_args else {
+        return Err(Error::other("expected <app_id>"));
+    };
+    let installed_engine = find_installed_engine(app_id)?;
+
+    package::unpin_engine_version(app_id)?;
+    println!("{} is no longer pinned", installed_engine.engine_name);
+
+    Ok(())
+}
+
+fn rollback(cmd_args: &[&str]) -> io::Result<()> {
+    let [app_id, version] = cmd_args else {
+        return Err(Error::other("expected <app_id> <version>"));
+    };
+    let installed_engine = find_installed_engine(app_id)?;
+
+    if *version == installed_engine.version {
+        return Err(Error::other(format!(
+            "version {} is already installed",
+            version
+        )));
+    }
+
+    package::pin_engine_version(app_id, Some(version))?;
+    println!(
+        "{} will be rolled back to {} and pinned on the next launch",
+        installed_engine.engine_name, version
+    );
+
+    Ok(())
+}
+
+struct PrefixArgs<'a> {
+    app_id: &'a str,
+    kind: PrefixKind,
+    confirmed: bool,
+    destination: Option<&'a str>,
+}
+
+fn parse_prefix_args<'a>(cmd_args: &[&'a str]) -> io::Result<PrefixArgs<'a>> {
+    let mut app_id: Option<&str> = None;
+    let mut prefix_args = PrefixArgs {
+        app_id: "",
+        kind: PrefixKind::Proton,
+        confirmed: false,
+        destination: None,
+    };
+
+    for arg in cmd_args {
+        match *arg {
+            "--wine" => prefix_args.kind = PrefixKind::Wine,
+            "--yes" => prefix_args.confirmed = true,
+            _ if app_id.is_none() && !arg.starts_with('-') => app_id = Some(*arg),
+            _ if prefix_args.destination.is_none() && !arg.starts_with('-') => {
+                prefix_args.destination = Some(*arg)
+            }
+            _ => return Err(Error::other(format!("unknown option {}", arg))),
+        }
+    }
+
+    let Some(app_id) = app_id else {
+        return Err(Error::other("app_id is required"));
+    };
+    prefix_args.app_id = app_id;
+
+    Ok(prefix_args)
+}
+
+fn prefix_info(cmd_args: &[&str]) -> io::Result<()> {
+    let prefix_args = parse_prefix_args(cmd_args)?;
+    let info = prefix::prefix_info(prefix_args.kind, prefix_args.app_id)?;
+
+    println!("Path: {}", info.path.display());
+    if !info.exists {
+        println!("The prefix has not been created yet");
+        return Ok(());
+    }
+
+    println!(
+        "Size: {:.1} MiB",
+        info.size_in_bytes as f64 / (1024.0 * 1024.0)
+    );
+    if let Some(modified) = info.modified {
+        println!("Last changed: {}", modified.format("%Y-%m-%d %H:%M:%S"));
+    }
+    if let Some(proton_version) = info.proton_version {
+        println!("Proton version: {}", proton_version);
+    }
+
+    Ok(())
+}
+
+fn prefix_reset(cmd_args: &[&str]) -> io::Result<()> {
+    let prefix_args = parse_prefix_args(cmd_args)?;
+    let path = prefix::prefix_path(prefix_args.kind, prefix_args.app_id)?;
+
+    if !prefix_args.confirmed {
+        return Err(Error::other(format!(
+            "this removes {} and everything in it, run again with --yes to confirm",
+            path.display()
+        )));
+    }
+
+    prefix::reset_prefix(prefix_args.kind, prefix_args.app_id)?;
+    println!(
+        "Removed {}, it will be created again on the next launch",
+        path.display()
+    );
+
+    Ok(())
+}
+
+fn prefix_backup(cmd_args: &[&str]) -> io::Result<()> {
+    let prefix_args = parse_prefix_args(cmd_args)?;
+    let destination = prefix_args.destination.map(Path::new);
+
+    let backup_path = prefix::backup_prefix(prefix_args.kind, prefix_args.app_id, destination)?;
+    println!("Backed up to {}", backup_path.display());
+
+    Ok(())
+}
+
+fn launch_options(cmd_args: &[&str]) -> io::Result<()> {
+    let [app_id, options @ ..] = cmd_args else {
+        return Err(Error::other("expected <app_id>"));
+    };
+    let mut launch_profile = LaunchProfile::from_file(app_id);
+
+    let mut clear = false;
+    let mut launch_options: Option<&str> = None;
+    let mut working_dir: Option<String> = None;
+    let mut args_iter = options.iter();
+    while let Some(arg) = args_iter.next() {
+        match *arg {
+            "--clear" => clear = true,
+            "--working-dir" => working_dir = Some(option_value(arg, args_iter.next())?),
+            _ if launch_options.is_none() => launch_options = Some(arg),
+            _ => {
+                return Err(Error::other(
+                    "expected the launch options as one quoted argument",
+                ))
+            }
+        }
+    }
+
+    if clear {
+        launch_profile = Default::default();
+    }
+    if let Some(launch_options) = launch_options {
+        launch_profile.update_from_launch_options(launch_options);
+    }
+    if let Some(working_dir) = &working_dir {
+        launch_profile.set_working_dir(working_dir);
+    }
+    if clear || launch_options.is_some() || working_dir.is_some() {
+        launch_profile.save(app_id)?;
+    }
+
+    if let Some(working_dir) = &launch_profile.working_dir {
+        println!("Working directory: {}", working_dir);
+    }
+    if launch_profile.is_empty() {
+        println!("No launch options set for {}", app_id);
+    } else {
+        println!("{}", launch_profile.to_launch_options());
+    }
+
+    Ok(())
+}
+
+fn reset_setup(cmd_args: &[&str]) -> io::Result<()> {
+    let [app_id] = cmd_args else {
+        return Err(Error::other("expected <app_id>"));
+    };
+
+    package::reset_setup(app_id)?;
+    println!("Setup of {} runs again on the next launch", app_id);
+
+    Ok(())
+}
+
+fn find_installed_engine(app_id: &str) -> io::Result<package::InstalledEngine> {
+    package::find_installed_engine(app_id).ok_or_else(|| {
+        Error::other(format!(
+            "no installed engine found for app id {}, launch the game with luxtorpeda first",
+            app_id
+        ))
+    })
+}
+
+fn load_package_metadata() -> io::Result<PackageMetadata> {
+    let package_metadata = PackageMetadata::from_packages_file();
+    if package_metadata.games.is_empty() {
+        return Err(Error::other(
+            "no package metadata found, launch a game with luxtorpeda once to download it",
+        ));
+    }
+    Ok(package_metadata)
+}
+
+fn option_value(option: &str, value: Option<&&str>) -> io::Result<String> {
+    match value {
+        Some(value) => Ok(value.to_string()),
+        None => Err(Error::other(format!("{} requires a value", option))),
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    if value {
+        "yes".to_string()
+    } else {
+        "no".to_string()
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) -> io::Result<()> {
+    let json_str = serde_json::to_string_pretty(value).map_err(Error::other)?;
+    println!("{}", json_str);
+    Ok(())
+}
+
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in rows {
+        for (idx, cell) in row.iter().enumerate() {
+            widths[idx] = widths[idx].max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: Vec<&str>| {
+        cells
+            .iter()
+            .enumerate()
+            .map(|(idx, cell)| format!("{:width$}", cell, width = widths[idx]))
+            .collect::<Vec<String>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    println!("{}", format_row(headers.to_vec()));
+    for row in rows {
+        println!("{}", format_row(row.iter().map(|x| x.as_str()).collect()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_command_as_first_argument() {
+        let args = ["lux", "pin", "123", "1.0"];
+        assert_eq!(find_cli_args(&args), Some(&args[1..]));
+    }
+
+    #[test]
+    fn cli_command_after_separator() {
+        let args = [
+            "lux",
+            "--headless",
+            "--rendering-driver",
+            "pin",
+            "--",
+            "list-games",
+            "--json",
+        ];
+        assert_eq!(find_cli_args(&args), Some(&args[5..]));
+
+        // Only the argument right after the separator is taken as the command.
+        let args = ["lux", "--headless", "--", "--json", "list-games"];
+        assert_eq!(find_cli_args(&args), None);
+    }
+
+    #[test]
+    fn game_arguments_named_like_commands() {
+        for args in [
+            vec!["lux", "run", "game.exe", "pin"],
+            vec!["lux", "waitforexitandrun", "game.exe", "rollback", "2"],
+            vec!["lux", "wait-before-run", "game.exe", "--", "list-games"],
+            vec!["lux", "--headless", "run", "game.exe", "--", "reset-setup"],
+            vec!["lux", "--headless", "list-games"],
+        ] {
+            assert_eq!(find_cli_args(&args), None, "{:?}", args);
+        }
+    }
+}
@@ -21,9 +21,13 @@ use godot::prelude::*;
 
 use crate::command;
 use crate::config;
+use crate::engine_report;
+use crate::launch_profile::LaunchProfile;
 use crate::package;
 use crate::package_metadata;
-use crate::proton_handler::{find_tool_by_name, list_proton_tools, Tool};
+use crate::process;
+use crate::proton_handler::{check_tool_constraints, find_tool_by_name, list_proton_tools, Tool};
+use crate::runner;
 use crate::user_env;
 
 #[derive(GodotClass)]
@@ -33,9 +37,27 @@ pub struct LuxClient {
     last_downloads: std::option::Option<Vec<package_metadata::DownloadItem>>,
     last_choice: std::option::Option<String>,
     proton_tools: std::option::Option<Vec<Tool>>,
+    engine_update: std::option::Option<package::EngineUpdate>,
+    engine_version_selection: bool,
+    pending_choice_data: std::option::Option<String>,
+    engine_update_resolved: bool,
+    skip_install: bool,
+    proton_constraints_checked: bool,
+    quit_after_cancel: bool,
+    editing_launch_options: bool,
     base: Base<Node>,
 }
 
+static ENGINE_UPDATE_CHOICE: &str = "Update Engine";
+static ENGINE_SKIP_UPDATE_CHOICE: &str = "Skip This Update";
+static ENGINE_PIN_VERSION_CHOICE: &str = "Keep Current Version";
+static ENGINE_CONTINUE_CHOICE: &str = "Continue With Installed Version";
+static ENGINE_ROLLBACK_CHOICE_PREFIX: &str = "Roll Back to ";
+static LAUNCH_OPTIONS_PROMPT_ID: &str = "allpromptslaunchoptions";
+static LAUNCH_OPTIONS_KEY: &str = "launch_options";
+static LAUNCH_OPTIONS_WORKING_DIR_KEY: &str = "working_dir";
+static RESET_SETUP_PROMPT_ID: &str = "resetsetupconfirm";
+
 #[derive(Default, Serialize, Deserialize, Debug)]
 pub struct StatusObj {
     pub label: std::option::Option<String>,
@@ -53,6 +75,15 @@ pub struct PromptRequestData {
     pub title: String,
     pub prompt_id: String,
     pub rich_text: std::option::Option<String>,
+    pub default_value: std::option::Option<String>,
+    pub dialog: std::option::Option<package_metadata::SetupDialog>,
+}
+
+// Sent by the prompt when a setup dialog is answered, with the dialog it was shown for.
+#[derive(Serialize, Deserialize, Debug)]
+struct DialogAnswer {
+    dialog: package_metadata::SetupDialog,
+    value: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -75,6 +106,14 @@ impl INode for LuxClient {
             last_downloads: None,
             last_choice: None,
             proton_tools: None,
+            engine_update: None,
+            engine_version_selection: false,
+            pending_choice_data: None,
+            engine_update_resolved: false,
+            skip_install: false,
+            proton_constraints_checked: false,
+            quit_after_cancel: false,
+            editing_launch_options: false,
             base,
         }
     }
@@ -90,6 +129,14 @@ impl INode for LuxClient {
     }
 
     fn physics_process(&mut self, _delta: f64) {
+        if self.quit_after_cancel {
+            if !process::is_stopping() {
+                self.quit_after_cancel = false;
+                Self::exit_client();
+            }
+            return;
+        }
+
         if let Some(receiver) = &self.receiver {
             if let Ok(new_data) = receiver.try_recv() {
                 if new_data == "quit_client" {
@@ -160,13 +207,52 @@ impl LuxClient {
         info!("working dir: {:?}", env::current_dir());
         info!("tool dir: {:?}", user_env::tool_dir());
 
-        match package_metadata::PackageMetadata::update_packages_json() {
-            Ok(()) => {}
+        let source_results = match package_metadata::PackageMetadata::update_packages_json() {
+            Ok(source_results) => source_results,
             Err(err) => {
                 return Err(err);
             }
         };
 
+        let source_warnings: Vec<String> = source_results
+            .iter()
+            .filter_map(|source_result| source_result.warning_message())
+            .collect();
+
+        // Reports of engines that exited after the client closed on launch are shown first.
+        let mut startup_prompts = match engine_report::report_path(&app_id) {
+            Ok(report_path) => engine_report::take_pending_report(&app_id, &report_path),
+            Err(err) => {
+                error!("init report_path err: {:?}", err);
+                Vec::new()
+            }
+        };
+
+        if !source_warnings.is_empty() {
+            startup_prompts.push(PromptRequestData {
+                label: Some("Some additional package sources could not be updated.".to_string()),
+                prompt_type: "question".to_string(),
+                title: "Package Source Warning".to_string(),
+                prompt_id: "remotepackageswarning".to_string(),
+                rich_text: Some(source_warnings.join("\n")),
+                default_value: None,
+                dialog: None,
+            });
+        }
+
+        if !startup_prompts.is_empty() {
+            let status_obj = StatusObj {
+                prompt_items: Some(PromptItemsData {
+                    prompt_items: startup_prompts,
+                    prompt_id: "allpromptsstartup".to_string(),
+                }),
+                ..Default::default()
+            };
+            let status_str = serde_json::to_string(&status_obj).unwrap();
+            self.emit_signal("Container/Progress", "progress_change", &status_str);
+            return Ok(());
+        }
+
         match self.ask_for_engine_choice(app_id.as_str()) {
             Ok(()) => {}
             Err(err) => {
@@ -180,29 +266,71 @@ impl LuxClient {
     fn show_proton(&mut self) -> io::Result<()> {
         let mut choices: Vec<package_metadata::SimpleEngineChoice> = vec![];
 
+        let wine_binary = runner::find_wine_binary(runner::WINE_RUNNER);
+
         if let Some(steam_path) = user_env::steam_install_path() {
-            let tools = list_proton_tools(&steam_path).expect("Cannot find Proton tools");
-            self.proton_tools = Some(tools.clone());
-
-            for tool in tools {
-                choices.insert(
-                    0,
-                    package_metadata::SimpleEngineChoice {
-                        name: tool.display_name,
-                        notices: Vec::new(),
-                    },
-                );
+            let discovery = list_proton_tools(&steam_path);
+            if discovery.tools.is_empty() && wine_binary.is_none() {
+                return Err(Error::other(std::format!(
+                    "No Proton versions were found.\n{}",
+                    discovery.diagnostics_message()
+                )));
             }
+            self.proton_tools = Some(discovery.tools.clone());
 
-            let choices_str = serde_json::to_string(&choices).unwrap();
+            let app_id = user_env::steam_app_id();
+            let proton_constraints = package::get_game_info(&app_id)
+                .ok()
+                .and_then(|game_info| game_info.proton_constraints);
 
-            self.emit_signal(
-                "Container/Choices",
-                "choices_found",
-                &choices_str.to_string(),
-            );
+            for tool in discovery.tools {
+                let mut notices = vec![std::format!("Vendor: {}", tool.vendor)];
+                if let Some(version) = &tool.version {
+                    notices.push(std::format!("Version: {}", version));
+                }
+                if tool.official {
+                    notices.push("Official Proton release installed through Steam".to_string());
+                }
+                notices.push(std::format!("Location: {}", tool.install_path.display()));
+                notices.push(std::format!("Found in: {}", tool.source));
+                if let Some(proton_constraints) = &proton_constraints {
+                    if let Some(reason) = check_tool_constraints(&tool, proton_constraints) {
+                        notices.push(std::format!("Not supported by this game: {}", reason));
+                    }
+                }
+
+                choices.push(package_metadata::SimpleEngineChoice {
+                    name: tool.display_name,
+                    notices,
+                });
+            }
         }
 
+        if let Some(wine_binary) = wine_binary {
+            choices.push(package_metadata::SimpleEngineChoice {
+                name: runner::WINE_CHOICE_NAME.to_string(),
+                notices: vec![
+                    std::format!(
+                        "Runs the game with {} instead of Proton",
+                        wine_binary.display()
+                    ),
+                    "Uses a separate wine prefix for each game".to_string(),
+                ],
+            });
+        }
+
+        if choices.is_empty() {
+            return Err(Error::other("No Proton or Wine versions were found."));
+        }
+
+        let choices_str = serde_json::to_string(&choices).unwrap();
+
+        self.emit_signal(
+            "Container/Choices",
+            "choices_found",
+            &choices_str.to_string(),
+        );
+
         Ok(())
     }
 
@@ -215,7 +343,17 @@ impl LuxClient {
         };
 
         if game_info.choices.is_some() {
-            let choices = game_info.choices_with_notices();
+            let mut choices = game_info.choices_with_notices();
+            if package::SetupState::from_file(app_id)
+                .is_some_and(|setup_state| setup_state.completed_at.is_some())
+            {
+                choices.push(package_metadata::SimpleEngineChoice {
+                    name: package_metadata::RESET_SETUP_CHOICE.to_string(),
+                    notices: vec![
+                        "Runs the setup of this game again on the next launch.".to_string()
+                    ],
+                });
+            }
 
             let check_default_choice_file_path =
                 package::place_config_file(app_id, "default_engine_choice.txt")?;
@@ -257,6 +395,8 @@ impl LuxClient {
                         title: "Default Choice Confirmation".to_string(),
                         prompt_id: "defaultchoiceconfirm".to_string(),
                         rich_text: None,
+                        default_value: None,
+                        dialog: None,
                     };
                     let prompt_request_str = serde_json::to_string(&prompt_request).unwrap();
 
@@ -312,6 +452,17 @@ impl LuxClient {
 
         let data_str = data.try_to::<String>().unwrap();
 
+        if self.engine_version_selection {
+            self.engine_version_selection = false;
+            let choice_obj: ChoiceData = serde_json::from_str(&data_str).unwrap();
+            let version_choice = choice_obj.engine_choice.unwrap_or_default();
+            self.process_engine_version_choice(&app_id, &version_choice);
+
+            let choice_data = self.pending_choice_data.take().unwrap_or_default();
+            self.choice_picked(Variant::from(choice_data));
+            return;
+        }
+
         if !data_str.is_empty() {
             let choice_obj: ChoiceData = serde_json::from_str(&data_str).unwrap();
             let is_in_proton_selection = self.last_choice == Some("Choose Proton".to_string());
@@ -330,8 +481,16 @@ impl LuxClient {
                         default_choice_file
                             .write_all(default_choice.as_bytes())
                             .unwrap();
+                    } else if default_choice == runner::WINE_CHOICE_NAME {
+                        info!(
+                            "wine can not be the default for all games, using it for {}",
+                            app_id
+                        );
                     } else {
                         info!("default proton version requested for {}", default_choice);
+                        if let Err(err) = runner::save_user_runner_choice(&app_id, None) {
+                            error!("save_user_runner_choice err: {:?}", err);
+                        }
                         let xdg_dirs = xdg::BaseDirectories::with_prefix("luxtorpeda");
                         let default_proton_file_path = xdg_dirs
                             .place_config_file("default_proton_choice.txt")
@@ -340,7 +499,7 @@ impl LuxClient {
                             File::create(default_proton_file_path).unwrap();
 
                         if let Some(proton_tools) = &self.proton_tools {
-                            let Some(proton) = find_tool_by_name(&proton_tools, &default_choice)
+                            let Some(proton) = find_tool_by_name(proton_tools, &default_choice)
                             else {
                                 return;
                             };
@@ -356,17 +515,33 @@ impl LuxClient {
                     }
                 }
 
+                if is_in_proton_selection && engine_choice == runner::WINE_CHOICE_NAME {
+                    info!("user picked wine for {}", app_id);
+                    if let Err(err) =
+                        runner::save_user_runner_choice(&app_id, Some(runner::WINE_RUNNER))
+                    {
+                        error!("save_user_runner_choice err: {:?}", err);
+                    }
+
+                    let _ = self.ask_for_engine_choice(app_id.as_str());
+                    self.last_choice = Some("".to_string());
+                    return;
+                }
+
                 if is_in_proton_selection {
                     info!(
                         "user picked proton version {} for {}",
                         engine_choice, app_id
                     );
+                    if let Err(err) = runner::save_user_runner_choice(&app_id, None) {
+                        error!("save_user_runner_choice err: {:?}", err);
+                    }
                     let proton_choice_file_path =
                         package::place_config_file(&app_id, "proton_choice.txt").unwrap();
                     let mut proton_choice_file = File::create(proton_choice_file_path).unwrap();
 
                     if let Some(proton_tools) = &self.proton_tools {
-                        let Some(proton) = find_tool_by_name(&proton_tools, &engine_choice) else {
+                        let Some(proton) = find_tool_by_name(proton_tools, &engine_choice) else {
                             return;
                         };
 
@@ -382,8 +557,23 @@ impl LuxClient {
 
                 self.last_choice = Some(engine_choice.clone());
 
+                if engine_choice == package_metadata::LAUNCH_OPTIONS_CHOICE {
+                    self.last_choice = Some("".to_string());
+                    self.show_launch_options(&app_id);
+                    return;
+                }
+
+                if engine_choice == package_metadata::RESET_SETUP_CHOICE {
+                    self.last_choice = Some("".to_string());
+                    self.show_reset_setup();
+                    return;
+                }
+
                 if engine_choice == "Choose Proton" {
-                    let _ = self.show_proton();
+                    if let Err(err) = self.show_proton() {
+                        error!("show_proton err: {:?}", err);
+                        self.show_error(err);
+                    }
                     return;
                 }
 
@@ -400,6 +590,80 @@ impl LuxClient {
             }
         }
 
+        if !self.engine_update_resolved {
+            match package::check_engine_update(&app_id, &game_info) {
+                package::EngineUpdateCheck::UpdateAvailable(engine_update) => {
+                    info!(
+                        "engine update available for {}: {} -> {}",
+                        engine_update.engine_name,
+                        engine_update.installed_version,
+                        engine_update.available_version
+                    );
+                    self.pending_choice_data = Some(data_str);
+                    self.show_engine_versions(&app_id, Some(engine_update));
+                    return;
+                }
+                package::EngineUpdateCheck::Pinned(engine_update) => {
+                    info!(
+                        "{} is pinned to version {}, skipping update to {}",
+                        engine_update.engine_name,
+                        engine_update.installed_version,
+                        engine_update.available_version
+                    );
+                    self.skip_install = true;
+                }
+                package::EngineUpdateCheck::UpToDate => {
+                    let choose_engine_version = env::var(package::LUX_CHOOSE_ENGINE_VERSION)
+                        .map(|val| val == "1")
+                        .unwrap_or(false);
+                    if choose_engine_version
+                        && package::find_pending_rollback(&app_id, &game_info).is_none()
+                        && !package::find_rollback_versions(&app_id).is_empty()
+                    {
+                        info!("engine version choice requested because of env");
+                        self.pending_choice_data = Some(data_str);
+                        self.show_engine_versions(&app_id, None);
+                        return;
+                    }
+                }
+            }
+            self.engine_update_resolved = true;
+        }
+
+        if !self.proton_constraints_checked {
+            self.proton_constraints_checked = true;
+            if let Some(warning) = command::find_proton_constraint_warning(&game_info) {
+                info!("proton constraint warning: {}", warning);
+                self.pending_choice_data = Some(data_str);
+
+                let prompt_request = PromptRequestData {
+                    label: Some(std::format!(
+                        "{} The game may not work correctly, continue anyway?",
+                        warning
+                    )),
+                    prompt_type: "question".to_string(),
+                    title: "Unsupported Proton Version".to_string(),
+                    prompt_id: "protonconstraintwarning".to_string(),
+                    rich_text: None,
+                    default_value: None,
+                    dialog: None,
+                };
+                let prompt_request_str = serde_json::to_string(&prompt_request).unwrap();
+
+                self.emit_signal(
+                    "Container/Prompt",
+                    "show_prompt",
+                    &prompt_request_str.to_string(),
+                );
+                return;
+            }
+        }
+
+        if self.skip_install || package::find_pending_rollback(&app_id, &game_info).is_some() {
+            self.run_game(false);
+            return;
+        }
+
         let downloads = package::json_to_downloads(app_id.as_str(), &game_info).unwrap();
 
         if downloads.is_empty() {
@@ -417,6 +681,8 @@ impl LuxClient {
                 title: "License Warning".to_string(),
                 prompt_id: "confirmlicensedownload".to_string(),
                 rich_text: None,
+                default_value: None,
+                dialog: None,
             };
             let prompt_request_str = serde_json::to_string(&prompt_request).unwrap();
 
@@ -430,29 +696,259 @@ impl LuxClient {
         }
     }
 
+    fn show_engine_versions(
+        &mut self,
+        app_id: &str,
+        engine_update: std::option::Option<package::EngineUpdate>,
+    ) {
+        let mut choices: Vec<package_metadata::SimpleEngineChoice> = vec![];
+
+        if let Some(engine_update) = &engine_update {
+            let version_notices = vec![
+                std::format!("Installed version: {}", engine_update.installed_version),
+                std::format!("Available version: {}", engine_update.available_version),
+            ];
+
+            for choice_name in [
+                ENGINE_UPDATE_CHOICE,
+                ENGINE_SKIP_UPDATE_CHOICE,
+                ENGINE_PIN_VERSION_CHOICE,
+            ] {
+                choices.push(package_metadata::SimpleEngineChoice {
+                    name: choice_name.to_string(),
+                    notices: version_notices.clone(),
+                });
+            }
+        } else {
+            let mut notices = vec![];
+            if let Some(installed_engine) = package::find_installed_engine(app_id) {
+                notices.push(std::format!(
+                    "Installed version: {}",
+                    installed_engine.version
+                ));
+            }
+
+            choices.push(package_metadata::SimpleEngineChoice {
+                name: ENGINE_CONTINUE_CHOICE.to_string(),
+                notices,
+            });
+        }
+
+        for version in package::find_rollback_versions(app_id) {
+            choices.push(package_metadata::SimpleEngineChoice {
+                name: std::format!("{}{}", ENGINE_ROLLBACK_CHOICE_PREFIX, version),
+                notices: vec![std::format!(
+                    "Reinstalls the kept files of version {} and pins the game to it.",
+                    version
+                )],
+            });
+        }
+
+        self.engine_update = engine_update;
+        self.engine_version_selection = true;
+
+        let choices_str = serde_json::to_string(&choices).unwrap();
+        self.emit_signal(
+            "Container/Choices",
+            "choices_found",
+            &choices_str.to_string(),
+        );
+    }
+
+    fn show_reset_setup(&mut self) {
+        let prompt_request = PromptRequestData {
+            label: Some(
+                "The setup of this game runs again on the next launch. Answers to setup questions are kept."
+                    .to_string(),
+            ),
+            prompt_type: "question".to_string(),
+            title: "Reset Setup".to_string(),
+            prompt_id: RESET_SETUP_PROMPT_ID.to_string(),
+            rich_text: None,
+            default_value: None,
+            dialog: None,
+        };
+        let prompt_request_str = serde_json::to_string(&prompt_request).unwrap();
+
+        self.emit_signal(
+            "Container/Prompt",
+            "show_prompt",
+            &prompt_request_str.to_string(),
+        );
+    }
+
+    fn show_launch_options(&mut self, app_id: &str) {
+        let launch_profile = LaunchProfile::from_file(app_id);
+
+        let dialog_prompt = |dialog: package_metadata::SetupDialog| PromptRequestData {
+            label: Some(dialog.label.clone()),
+            prompt_type: "dialog".to_string(),
+            title: dialog.title.clone(),
+            prompt_id: dialog.key.clone(),
+            rich_text: None,
+            default_value: dialog.default.clone(),
+            dialog: Some(dialog),
+        };
+        let prompt_items = PromptItemsData {
+            prompt_items: vec![
+                dialog_prompt(package_metadata::SetupDialog {
+                    dialog_type: "input".to_string(),
+                    title: "Launch Options".to_string(),
+                    label: "Launch options for this game, in the same format as Steam. Leave empty to clear."
+                        .to_string(),
+                    key: LAUNCH_OPTIONS_KEY.to_string(),
+                    default: Some(launch_profile.to_launch_options()),
+                    ..Default::default()
+                }),
+                dialog_prompt(package_metadata::SetupDialog {
+                    dialog_type: "directory".to_string(),
+                    title: "Working Directory".to_string(),
+                    label: "Directory the engine is started in, instead of the one set by the package. Leave empty to clear."
+                        .to_string(),
+                    key: LAUNCH_OPTIONS_WORKING_DIR_KEY.to_string(),
+                    default: launch_profile.working_dir.clone(),
+                    ..Default::default()
+                }),
+            ],
+            prompt_id: LAUNCH_OPTIONS_PROMPT_ID.to_string(),
+        };
+        let status_obj = StatusObj {
+            prompt_items: Some(prompt_items),
+            ..Default::default()
+        };
+        let status_str = serde_json::to_string(&status_obj).unwrap();
+
+        self.editing_launch_options = true;
+        self.emit_signal("Container/Progress", "progress_change", &status_str);
+    }
+
+    // Applies an answer from the launch options prompts to the launch profile of the game.
+    fn launch_options_answered(&mut self, answer: &DialogAnswer) -> Result<(), String> {
+        let app_id = user_env::steam_app_id();
+        let mut launch_profile = LaunchProfile::from_file(&app_id);
+        let key = answer.dialog.key.as_str();
+
+        if key == LAUNCH_OPTIONS_KEY {
+            launch_profile.update_from_launch_options(&answer.value);
+            info!(
+                "launch options for {} set to: {}",
+                app_id,
+                launch_profile.to_launch_options()
+            );
+        } else if key == LAUNCH_OPTIONS_WORKING_DIR_KEY {
+            if !answer.value.trim().is_empty() {
+                answer.dialog.validate(answer.value.trim())?;
+            }
+            launch_profile.set_working_dir(&answer.value);
+            info!(
+                "launch options working directory for {} set to: {:?}",
+                app_id, launch_profile.working_dir
+            );
+        } else {
+            error!("launch_options_answered unknown key: {}", key);
+            return Ok(());
+        }
+
+        if let Err(err) = launch_profile.save(&app_id) {
+            error!("launch_profile save err: {:?}", err);
+            return Err(std::format!(
+                "The launch options could not be saved: {}",
+                err
+            ));
+        }
+        Ok(())
+    }
+
+    fn process_engine_version_choice(&mut self, app_id: &str, version_choice: &str) {
+        self.engine_update_resolved = true;
+        let engine_update = self.engine_update.take();
+
+        if let Some(version) = version_choice.strip_prefix(ENGINE_ROLLBACK_CHOICE_PREFIX) {
+            info!("rolling back engine to version {}", version);
+            if let Err(err) = package::pin_engine_version(app_id, Some(version)) {
+                error!("pin_engine_version err: {:?}", err);
+            }
+        } else if version_choice == ENGINE_SKIP_UPDATE_CHOICE {
+            info!("skipping engine update for this launch");
+            self.skip_install = true;
+        } else if version_choice == ENGINE_PIN_VERSION_CHOICE {
+            if let Err(err) = package::pin_engine_version(app_id, None) {
+                error!("pin_engine_version err: {:?}", err);
+            }
+            self.skip_install = true;
+        } else if let Some(engine_update) = engine_update {
+            info!(
+                "updating {} to version {}",
+                engine_update.engine_name, engine_update.available_version
+            );
+        }
+    }
+
+    // Returns an empty string when the answer is accepted, otherwise the message to show.
+    #[func]
+    fn dialog_answered(&mut self, data: Variant) -> GString {
+        let data = data.try_to::<String>().unwrap_or_default();
+        let answer = match serde_json::from_str::<DialogAnswer>(&data) {
+            Ok(answer) => answer,
+            Err(err) => {
+                error!("dialog_answered parse err: {:?}", err);
+                return GString::from("The answer could not be read.");
+            }
+        };
+        if self.editing_launch_options {
+            return match self.launch_options_answered(&answer) {
+                Ok(()) => GString::new(),
+                Err(message) => GString::from(message.as_str()),
+            };
+        }
+
+        let key = &answer.dialog.key;
+
+        if let Err(message) = answer.dialog.validate(&answer.value) {
+            info!("dialog answer for key {} not accepted: {}", key, message);
+            return GString::from(message.as_str());
+        }
+
+        info!(
+            "found dialog entry response for key: {} with value: {}",
+            key, answer.value
+        );
+        if !key.is_empty() {
+            env::set_var(std::format!("DIALOGRESPONSE_{}", key), &answer.value);
+            if let Err(err) =
+                package::save_dialog_answer(&user_env::steam_app_id(), key, &answer.value)
+            {
+                error!("save_dialog_answer err: {:?}", err);
+            }
+        }
+
+        GString::new()
+    }
+
     #[func]
     fn question_confirmed(&mut self, data: Variant) {
         let mode_id = data.try_to::<String>().unwrap();
         info!("question_confirmed with mode: {}", mode_id);
 
-        if mode_id == "confirmlicensedownload" {
+        if mode_id == LAUNCH_OPTIONS_PROMPT_ID {
+            self.editing_launch_options = false;
+            let args: &[Variant] = &[];
+            self.base_mut().call_deferred("resume_engine_choice", args);
+        } else if mode_id == RESET_SETUP_PROMPT_ID {
+            let app_id = user_env::steam_app_id();
+            info!("resetting setup for {}", app_id);
+            if let Err(err) = package::reset_setup(&app_id) {
+                error!("reset_setup err: {:?}", err);
+            }
+
+            let args: &[Variant] = &[];
+            self.base_mut().call_deferred("resume_engine_choice", args);
+        } else if mode_id == "confirmlicensedownload" {
             self.emit_signal("Container/Progress", "show_progress", "");
             self.process_download();
-        } else if mode_id.contains("dialogentryconfirm") {
-            let mode_split = mode_id.split("%%");
-            let mode_items = mode_split.collect::<Vec<&str>>();
-            if mode_items.len() == 3 {
-                let key = mode_items[1];
-                let text_input = mode_items[2];
-
-                info!(
-                    "found dialog entry response for key: {} with value: {}",
-                    key, text_input
-                );
-                if !key.is_empty() {
-                    env::set_var(std::format!("DIALOGRESPONSE_{}", key), text_input);
-                }
-            }
+        } else if mode_id == "protonconstraintwarning" {
+            let args: &[Variant] = &[];
+            self.base_mut().call_deferred("resume_choice_picked", args);
         } else if mode_id.contains("allprompts") {
             let mode_split = mode_id.split("allprompts");
             let mode_items = mode_split.collect::<Vec<&str>>();
@@ -461,6 +957,12 @@ impl LuxClient {
                 if mode_id == "setup" {
                     self.emit_signal("Container/Progress", "show_progress", "");
                     self.run_game(true);
+                } else if mode_id == "enginereport" {
+                    Self::exit_client();
+                } else if mode_id == "startup" {
+                    // Deferred so the prompt is hidden before the next screen is shown.
+                    let args: &[Variant] = &[];
+                    self.base_mut().call_deferred("resume_engine_choice", args);
                 }
             }
         } else if mode_id.contains("cancel%%") {
@@ -487,10 +989,40 @@ impl LuxClient {
                     }
                 }
             }
-            Self::exit_client();
+
+            // Stops setup commands or the engine still running, with everything they started.
+            // The client quits once they are gone.
+            process::cancel_all();
+            if process::is_stopping() {
+                let status_obj = StatusObj {
+                    label: Some("Stopping running processes".to_string()),
+                    ..Default::default()
+                };
+                let status_str = serde_json::to_string(&status_obj).unwrap();
+                self.emit_signal("Container/Progress", "show_progress", "");
+                self.emit_signal("Container/Progress", "progress_change", &status_str);
+                self.quit_after_cancel = true;
+            } else {
+                Self::exit_client();
+            }
         }
     }
 
+    #[func]
+    fn resume_engine_choice(&mut self) {
+        let app_id = user_env::steam_app_id();
+        if let Err(err) = self.ask_for_engine_choice(app_id.as_str()) {
+            error!("resume_engine_choice ask err: {:?}", err);
+            self.show_error(err);
+        }
+    }
+
+    #[func]
+    fn resume_choice_picked(&mut self) {
+        let choice_data = self.pending_choice_data.take().unwrap_or_default();
+        self.choice_picked(Variant::from(choice_data));
+    }
+
     #[func]
     fn clear_default_choice(&mut self) {
         let app_id = user_env::steam_app_id();
@@ -626,6 +1158,11 @@ impl LuxClient {
         let total_size = res.content_length().unwrap_or(0);
 
         let dest_file = package::place_cached_file(cache_dir, &info.file)?;
+        // The cached file can be hard linked into a kept engine version, so replace it
+        // instead of truncating the shared file.
+        if dest_file.exists() {
+            fs::remove_file(&dest_file)?;
+        }
         let mut dest = fs::File::create(dest_file)?;
         let mut downloaded: u64 = 0;
         let mut stream = res.bytes_stream();
@@ -694,6 +1231,8 @@ impl LuxClient {
             engine_choice = choice.to_string();
         }
 
+        let skip_install = self.skip_install;
+
         let (sender, receiver) = channel();
         self.receiver = Some(receiver);
 
@@ -704,22 +1243,27 @@ impl LuxClient {
 
             let sender_err = sender.clone();
 
-            let game_info =
-                match command::run(cmd_args, engine_choice, &sender, after_setup_question_mode) {
-                    Ok(game_info) => game_info,
-                    Err(err) => {
-                        error!("command::run err: {:?}", err);
+            let game_info = match command::run(
+                cmd_args,
+                engine_choice,
+                &sender,
+                after_setup_question_mode,
+                skip_install,
+            ) {
+                Ok(game_info) => game_info,
+                Err(err) => {
+                    error!("command::run err: {:?}", err);
 
-                        let status_obj = StatusObj {
-                            error: Some(err.to_string()),
-                            ..Default::default()
-                        };
-                        let status_str = serde_json::to_string(&status_obj).unwrap();
-                        sender_err.send(status_str).unwrap();
+                    let status_obj = StatusObj {
+                        error: Some(err.to_string()),
+                        ..Default::default()
+                    };
+                    let status_str = serde_json::to_string(&status_obj).unwrap();
+                    sender_err.send(status_str).unwrap();
 
-                        return;
-                    }
-                };
+                    return;
+                }
+            };
 
             if let Some(app_ids_deps) = &game_info.app_ids_deps {
                 let status_obj = StatusObj {
@@ -756,7 +1300,9 @@ impl LuxClient {
             }
 
             if let Some(setup_info) = &game_info.setup {
-                if !after_setup_question_mode && !package::is_setup_complete(setup_info) {
+                if !after_setup_question_mode
+                    && !package::is_setup_complete(setup_info, game_info.choice_name())
+                {
                     match command::process_setup_details(setup_info) {
                         Ok(setup_details) => {
                             info!("setup details ready: {:?}", setup_details);
@@ -2,7 +2,6 @@ extern crate hex;
 extern crate json;
 extern crate reqwest;
 
-use iso9660::{DirectoryEntry, ISO9660Reader, ISODirectory, ISO9660};
 use regex::Regex;
 use std::env;
 use std::ffi::OsStr;
@@ -13,19 +12,34 @@ use std::io::BufRead;
 use std::io::BufReader;
 use std::io::Error;
 use std::io::Read;
+use std::io::Seek;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
+use std::time::Duration;
 use walkdir::WalkDir;
 
+use crate::cli;
 use crate::client;
 use crate::config;
+use crate::cue_sheet::CueSheet;
+use crate::disc_image::{DiscImage, DISC_IMAGE_EXTENSIONS};
+use crate::engine_report;
+use crate::engine_report::EngineOutcome;
 use crate::godot_logger;
+use crate::installer;
+use crate::installer::{FileFilter, InstallerType};
+use crate::iso::IsoImage;
+use crate::launch_profile::LaunchProfile;
 use crate::package;
 use crate::package::place_state_file;
 use crate::package_metadata;
+use crate::process;
+use crate::process::OutputProcess;
 use crate::proton_handler;
+use crate::runner;
+use crate::template::TemplateResolver;
 use crate::user_env;
 
 extern crate log;
@@ -51,28 +65,40 @@ static LUX_STEAM_CLOUD: &str = "LUX_STEAM_CLOUD";
 
 pub fn usage() {
     println!("usage: lux [run | wait-before-run | manual-download] <exe | app_id> [<exe_args>]");
+    cli::usage();
 }
 
+/// Returns the engine command and its arguments for the original command line, with template
+/// variables such as `${GAME_DIR}` resolved.
 pub fn find_game_command(
     info: &package_metadata::Game,
     args: &[&str],
-) -> Option<(String, Vec<String>)> {
+) -> io::Result<Option<(String, Vec<String>)>> {
     let orig_cmd = args.join(" ");
 
+    let mut game_command = None;
     if let Some(command) = &info.command {
-        return Some((command.to_string(), info.command_args.clone()));
-    }
-
-    if let Some(cmds) = &info.commands {
+        game_command = Some((command, &info.command_args));
+    } else if let Some(cmds) = &info.commands {
         for new_cmd in cmds {
             let re = Regex::new(&new_cmd.command_name).unwrap();
             if re.is_match(&orig_cmd) {
-                return Some((new_cmd.cmd.clone(), new_cmd.args.clone()));
+                game_command = Some((&new_cmd.cmd, &new_cmd.args));
+                break;
             }
         }
     }
 
-    None
+    let Some((cmd, cmd_args)) = game_command else {
+        return Ok(None);
+    };
+
+    let mut resolver = TemplateResolver::new();
+    let cmd = resolver.resolve(cmd);
+    let cmd_args = resolver.resolve_all(cmd_args);
+    resolver.finish("engine command")?;
+
+    Ok(Some((cmd, cmd_args)))
 }
 
 pub fn process_setup_details(
@@ -94,68 +120,128 @@ pub fn process_setup_details(
                 title: "Closed Source Engine EULA".to_string(),
                 prompt_id: "closedsourceengineeulaconfirm".to_string(),
                 rich_text: Some(file_str_milk.to_string()),
+                default_value: None,
+                dialog: None,
             };
             setup_items.push(prompt_request);
         }
     }
 
     if let Some(dialogs) = &setup_info.dialogs {
+        let dialog_answers = package::find_dialog_answers(&user_env::steam_app_id());
+
         for entry in dialogs {
-            if entry.dialog_type == "input" {
-                let prompt_request = client::PromptRequestData {
-                    label: Some(entry.label.to_string()),
-                    prompt_type: "input".to_string(),
-                    title: entry.title.to_string(),
-                    prompt_id: std::format!("dialogentryconfirm%%{}%%", entry.key).to_string(),
-                    rich_text: None,
-                };
-                setup_items.push(prompt_request);
+            if !package_metadata::SETUP_DIALOG_TYPES.contains(&entry.dialog_type.as_str()) {
+                return Err(Error::other(std::format!(
+                    "Setup dialog {} has the unknown type {}, expected one of: {}.",
+                    entry.key,
+                    entry.dialog_type,
+                    package_metadata::SETUP_DIALOG_TYPES.join(", ")
+                )));
             }
+
+            // A previous answer is offered before the package default.
+            let mut dialog = entry.clone();
+            if let Some(answer) = dialog_answers.get(&entry.key) {
+                dialog.default = Some(answer.to_string());
+            }
+
+            let prompt_request = client::PromptRequestData {
+                label: Some(entry.label.to_string()),
+                prompt_type: "dialog".to_string(),
+                title: entry.title.to_string(),
+                prompt_id: entry.key.to_string(),
+                rich_text: None,
+                default_value: dialog.default.clone(),
+                dialog: Some(dialog),
+            };
+            setup_items.push(prompt_request);
         }
     }
 
     Ok(setup_items)
 }
 
+// Copies the first lines of a cue sheet that has more entries than the bin file it comes with.
+fn read_cue_lines(original: &str, first_lines: usize) -> io::Result<String> {
+    let file = File::open(original).map_err(|err| {
+        error!("run_bchunk cue file open original failed {}", err);
+        Error::other(format!(
+            "run_bchunk failed, could not open cue sheet {} - {}",
+            original, err
+        ))
+    })?;
+
+    let mut cue_lines = String::new();
+    for line in BufReader::new(file).lines().take(first_lines) {
+        let line = line.map_err(|err| {
+            error!("run_bchunk cue file read original failed {}", err);
+            Error::other(format!(
+                "run_bchunk failed, could not read cue sheet {} - {}",
+                original, err
+            ))
+        })?;
+        cue_lines.push_str(&line);
+        cue_lines.push('\n');
+    }
+    Ok(cue_lines)
+}
+
+fn generate_cue_sheet(bin_path: &Path) -> io::Result<String> {
+    CueSheet::generate_for_bin(bin_path)
+        .map(|cue_sheet| cue_sheet.to_cue_string())
+        .map_err(|err| {
+            error!("run_bchunk cue file generate failed {}", err);
+            Error::other(format!("run_bchunk failed - {}", err))
+        })
+}
+
 fn run_bchunk(bchunk_info: &package_metadata::SetupBChunk) -> io::Result<()> {
-    if let Some(generate_cue_file) = &bchunk_info.generate_cue_file {
-        let file = match File::open(&generate_cue_file.original) {
-            Ok(f) => f,
-            Err(err) => {
-                error!("run_bchunk cue file open original failed {}", err);
-                return Err(Error::other(format!(
-                    "run_bchunk cue file failed - {}",
-                    err
-                )));
-            }
-        };
-        let reader = BufReader::new(file);
-
-        let mut idx = 0;
-        let mut cue_lines = String::new();
-        for line in reader.lines() {
-            match line {
-                Ok(line) => {
-                    cue_lines = format!("{}{}\n", cue_lines, line);
-                    idx += 1;
-                    if idx >= generate_cue_file.first_lines {
-                        break;
-                    }
-                }
-                Err(err) => {
-                    error!("run_bchunk cue file read original failed {}", err);
-                    return Err(Error::other(format!(
-                        "run_bchunk cue read failed - {}",
-                        err
-                    )));
-                }
-            }
+    let bin_path = Path::new(&bchunk_info.bin_file);
+    if !bin_path.exists() {
+        return Err(Error::other(format!(
+            "run_bchunk failed, bin file {} not found",
+            bchunk_info.bin_file
+        )));
+    }
+
+    let generated_cue = match &bchunk_info.generate_cue_file {
+        Some(generate_cue_file) if !generate_cue_file.original.is_empty() => Some(read_cue_lines(
+            &generate_cue_file.original,
+            generate_cue_file.first_lines,
+        )?),
+        Some(_) => Some(generate_cue_sheet(bin_path)?),
+        None if !Path::new(&bchunk_info.cue_file).exists() => {
+            info!(
+                "run_bchunk, no cue sheet at {}, generating one",
+                bchunk_info.cue_file
+            );
+            Some(generate_cue_sheet(bin_path)?)
         }
+        None => None,
+    };
 
+    if let Some(cue_contents) = generated_cue {
         let _ = fs::remove_file(&bchunk_info.cue_file);
-        fs::write(&bchunk_info.cue_file, cue_lines).unwrap();
+        fs::write(&bchunk_info.cue_file, cue_contents).map_err(|err| {
+            error!("run_bchunk cue file write failed {}", err);
+            Error::other(format!(
+                "run_bchunk failed, could not write cue sheet {} - {}",
+                bchunk_info.cue_file, err
+            ))
+        })?;
     }
 
+    CueSheet::from_file(Path::new(&bchunk_info.cue_file))
+        .and_then(|cue_sheet| cue_sheet.validate_for_bin(bin_path))
+        .map_err(|err| {
+            error!("run_bchunk cue sheet invalid {}", err);
+            Error::other(format!(
+                "run_bchunk failed, {} does not match {} - {}",
+                bchunk_info.cue_file, bchunk_info.bin_file, err
+            ))
+        })?;
+
     let args = rbchunk::Args {
         bin_file: bchunk_info.bin_file.to_string(),
         cue_file: bchunk_info.cue_file.to_string(),
@@ -178,70 +264,108 @@ fn run_bchunk(bchunk_info: &package_metadata::SetupBChunk) -> io::Result<()> {
     }
 }
 
-fn iso_extract_tree<T: ISO9660Reader>(
-    dir: &ISODirectory<T>,
-    path: String,
+// Returns the rest of the path when it starts with the prefix. Older packages were written against
+// upper case ISO9660 names, so the comparison ignores case.
+fn strip_iso_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = path.get(..prefix.len())?;
+    if head.eq_ignore_ascii_case(prefix) {
+        Some(&path[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+fn iso_extract_files<R: Read + Seek>(
+    iso: &mut IsoImage<R>,
     iso_extract_info: &package_metadata::SetupIsoExtract,
 ) -> io::Result<()> {
-    for entry_item in dir.contents() {
-        match entry_item {
-            Ok(entry) => match entry {
-                DirectoryEntry::Directory(dir) => {
-                    if dir.identifier == "." || dir.identifier == ".." {
-                        continue;
-                    }
-                    match iso_extract_tree(
-                        &dir,
-                        format!("{}/{}", path, dir.identifier),
-                        iso_extract_info,
-                    ) {
-                        Ok(()) => {}
-                        Err(err) => {
-                            error!("iso_extract_tree err: {:?}", err);
-                            return Err(Error::other("iso_extract_tree failed"));
-                        }
-                    }
-                }
-                DirectoryEntry::File(file) => {
-                    let mut file_path = format!("{}/{}", path, file.identifier);
-                    let mut should_extract = true;
+    for (iso_path, file) in iso.walk_files()? {
+        let file_path = if let Some(extract_prefix) = &iso_extract_info.extract_prefix {
+            let Some(rest) = strip_iso_prefix(&iso_path, extract_prefix) else {
+                info!("ignore iso file: {}", iso_path);
+                continue;
+            };
+            match &iso_extract_info.extract_to_prefix {
+                Some(extract_to_prefix) => format!("{}{}", extract_to_prefix, rest),
+                None => iso_path.to_string(),
+            }
+        } else if let Some(extract_to_prefix) = &iso_extract_info.extract_to_prefix {
+            format!("{}/{}", extract_to_prefix, iso_path)
+        } else {
+            iso_path.to_string()
+        };
 
-                    if let Some(extract_prefix) = &iso_extract_info.extract_prefix {
-                        if !file_path.starts_with(extract_prefix) {
-                            should_extract = false;
-                        }
+        // Paths in the image start at "/", the files are extracted below the current directory.
+        let new_path = PathBuf::from(file_path.trim_start_matches('/'));
+        info!("iso install: {:?}", &new_path);
 
-                        if should_extract {
-                            if let Some(extract_to_prefix) = &iso_extract_info.extract_to_prefix {
-                                file_path =
-                                    file_path.replacen(extract_prefix, extract_to_prefix, 1);
-                            }
-                        }
-                    } else if let Some(extract_to_prefix) = &iso_extract_info.extract_to_prefix {
-                        file_path = format!("{}/{}", extract_to_prefix, file_path);
-                    }
+        if let Some(parent) = new_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
 
-                    if should_extract {
-                        let new_path = PathBuf::from(file_path);
-                        info!("iso install: {:?}", &new_path);
+        let _ = fs::remove_file(&new_path);
+        let mut outfile = io::BufWriter::new(File::create(&new_path)?);
+        iso.copy_file(&file, &mut outfile)
+            .map_err(|err| Error::other(format!("iso extract of {} failed - {}", iso_path, err)))?;
+        outfile.flush()?;
+    }
 
-                        if new_path.parent().is_some() {
-                            fs::create_dir_all(new_path.parent().unwrap())?;
-                        }
+    Ok(())
+}
 
-                        let _ = fs::remove_file(&new_path);
-                        let mut outfile = fs::File::create(&new_path).unwrap();
-                        let mut contents = Vec::new();
-                        file.read().read_to_end(&mut contents).unwrap();
-                        outfile.write_all(contents.as_slice()).unwrap();
-                    } else {
-                        info!("ignore iso file: {}", file_path);
-                    }
-                }
-            },
-            Err(err) => {
-                error!("iso_extract_tree err: {:?}", err);
-                return Err(Error::other("iso_extract_tree failed"));
+fn find_disc_image(recursive_start_path: &str) -> Option<PathBuf> {
+    let mut found: Option<(usize, PathBuf)> = None;
+
+    for entry in WalkDir::new(recursive_start_path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let file_path = entry.path();
+        let file_extension = file_path
+            .extension()
+            .and_then(OsStr::to_str)
+            .unwrap_or("")
+            .to_lowercase();
+
+        let Some(priority) = DISC_IMAGE_EXTENSIONS
+            .iter()
+            .position(|extension| *extension == file_extension)
+        else {
+            continue;
+        };
+        if !matches!(&found, Some((found_priority, _)) if *found_priority <= priority) {
+            found = Some((priority, file_path.to_path_buf()));
+        }
+    }
+
+    found.map(|(_, file_path)| file_path)
+}
+
+fn extract_audio_tracks(
+    disc_image: &DiscImage,
+    audio_info: &package_metadata::SetupAudioTracks,
+) -> io::Result<()> {
+    let destination = Path::new(&audio_info.destination);
+    fs::create_dir_all(destination)?;
+
+    for track in disc_image.audio_tracks() {
+        let file_name = audio_info
+            .file_name
+            .replace("{track}", &format!("{:02}", track.number));
+        let track_path = destination.join(format!("{}.{}", file_name, audio_info.format));
+        info!(
+            "run_iso_extract, audio track {} to {:?}",
+            track.number, track_path
+        );
+
+        match audio_info.format.as_str() {
+            "wav" => track.write_wav(&track_path)?,
+            "ogg" => track.write_ogg(&track_path)?,
+            format => {
+                return Err(Error::other(format!(
+                    "run_iso_extract failed, unknown audio format {}",
+                    format
+                )))
             }
         }
     }
@@ -250,59 +374,81 @@ fn iso_extract_tree<T: ISO9660Reader>(
 }
 
 fn run_iso_extract(iso_extract_info: &package_metadata::SetupIsoExtract) -> io::Result<()> {
-    let mut iso_path = String::new();
+    let mut image_path: Option<PathBuf> = None;
     if let Some(file_path) = &iso_extract_info.file_path {
-        iso_path = (&file_path).to_string();
+        image_path = Some(PathBuf::from(file_path));
     } else if let Some(recursive_start_path) = &iso_extract_info.recursive_start_path {
         info!(
             "run_iso_extract, recursive check starting at {}",
             &recursive_start_path
         );
-
-        for entry in WalkDir::new(recursive_start_path)
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            let file_path = entry.path();
-            let file_extension = file_path.extension().and_then(OsStr::to_str).unwrap_or("");
-
-            if file_extension == "iso" {
-                info!(
-                    "run_iso_extract, recursive check found iso at {}",
-                    file_path.display()
-                );
-                iso_path = file_path.to_str().unwrap().to_string();
-                break;
-            }
+        image_path = find_disc_image(recursive_start_path);
+        if let Some(image_path) = &image_path {
+            info!(
+                "run_iso_extract, recursive check found image at {}",
+                image_path.display()
+            );
         }
     }
 
-    if !iso_path.is_empty() {
-        match std::fs::File::open(&iso_path) {
-            Ok(file) => match ISO9660::new(file) {
-                Ok(iso) => iso_extract_tree(&iso.root, "".to_string(), iso_extract_info),
-                Err(err) => {
-                    error!("run_iso_extract iso read err: {}", err);
-                    Err(Error::other("run_iso_extract failed, iso read error"))
-                }
-            },
-            Err(err) => {
-                error!("run_iso_extract file open err: {:?}", err);
-                Err(Error::other("run_iso_extract failed, file open error"))
-            }
-        }
-    } else {
+    let Some(image_path) = image_path else {
         info!("run_iso_extract, no file path found, continuing");
-        Ok(())
+        return Ok(());
+    };
+
+    let disc_image = DiscImage::open(&image_path).map_err(|err| {
+        error!("run_iso_extract image open err: {:?}", err);
+        Error::other(format!(
+            "run_iso_extract failed, could not open {} - {}",
+            image_path.display(),
+            err
+        ))
+    })?;
+
+    if disc_image.data_track().is_some() {
+        let mut iso = disc_image.open_data_track().map_err(|err| {
+            error!("run_iso_extract iso read err: {}", err);
+            Error::other(format!("run_iso_extract failed, iso read error - {}", err))
+        })?;
+        iso_extract_files(&mut iso, iso_extract_info)?;
+    } else {
+        info!("run_iso_extract, image has no data track");
+    }
+
+    if let Some(audio_info) = &iso_extract_info.audio_tracks {
+        extract_audio_tracks(&disc_image, audio_info)?;
     }
+
+    Ok(())
 }
 
-pub fn run_setup(
-    setup_info: &package_metadata::Setup,
+// The timeout of a setup command, falling back to the one set for the whole setup.
+fn setup_timeout(
+    command_timeout: Option<u64>,
+    game_info: &package_metadata::Game,
+) -> Option<Duration> {
+    command_timeout
+        .or_else(|| {
+            game_info
+                .setup
+                .as_ref()
+                .and_then(|setup| setup.timeout_in_seconds)
+        })
+        .map(Duration::from_secs)
+}
+
+fn run_setup_command(
+    command_info: &package_metadata::SetupCommand,
+    use_runner: bool,
     game_info: &package_metadata::Game,
     sender: &std::sync::mpsc::Sender<String>,
 ) -> io::Result<()> {
-    let command_str = setup_info.command.to_string();
+    let mut resolver = TemplateResolver::new();
+    let command_str = resolver.resolve(&command_info.command);
+    let command_args = resolver.resolve_all(&command_info.args);
+    let command_current_dir = resolver.resolve_option(&command_info.current_dir);
+    resolver.finish("setup command")?;
+
     info!("setup run: \"{}\"", command_str);
 
     let status_obj = client::StatusObj {
@@ -312,87 +458,289 @@ pub fn run_setup(
     let status_str = serde_json::to_string(&status_obj).unwrap();
     sender.send(status_str).unwrap();
 
-    if let Some(bchunk_info) = &setup_info.bchunk {
-        info!("setup run bchunk");
-        let status_obj = client::StatusObj {
-            log_line: Some("setup running bchunk".to_string()),
-            ..Default::default()
-        };
-        let status_str = serde_json::to_string(&status_obj).unwrap();
-        sender.send(status_str).unwrap();
+    let mut runner_args: Vec<String> = Vec::new();
+    let mut runner_envs: Vec<(String, String)> = Vec::new();
+    let mut commandline: String = command_str.clone();
 
-        match run_bchunk(bchunk_info) {
-            Ok(()) => {}
-            Err(err) => {
-                error!("command::run_bchunk err: {:?}", err);
-                return Err(err);
-            }
+    if use_runner {
+        if let Some(runner) = runner::find_runner(game_info)? {
+            info!("setup runner: {}", runner.name());
+            (commandline, runner_args) = runner.command(command_str);
+            runner_envs = runner.envs();
         }
     }
 
-    if let Some(iso_extract_info) = &setup_info.iso_extract {
-        info!("setup run iso_extract");
-        let status_obj = client::StatusObj {
-            log_line: Some("setup running iso extract".to_string()),
-            ..Default::default()
-        };
-        let status_str = serde_json::to_string(&status_obj).unwrap();
-        sender.send(status_str).unwrap();
+    let mut current_dir = env::current_dir().unwrap();
 
-        match run_iso_extract(iso_extract_info) {
-            Ok(()) => {}
-            Err(err) => {
-                error!("command::run_iso_extract err: {:?}", err);
-                return Err(err);
-            }
-        }
+    if let Some(command_current_dir) = command_current_dir {
+        current_dir = command_current_dir.into();
     }
 
-    let mut proton_args: Vec<String> = Vec::new();
-    let mut commandline: String = command_str.clone();
+    info!(
+        "setup cmd - commandline: {:?} runner_args: {:?} current_dir: {:?}",
+        commandline, runner_args, current_dir
+    );
 
-    if command_str.ends_with(".exe") {
-        if let Some(steam_path) = user_env::steam_install_path() {
-            if let Ok(tools) = proton_handler::list_proton_tools(&steam_path) {
-                if let Ok(proton_version) = get_proton_alias(&game_info) {
-                    if let Some(tool) = proton_handler::find_tool(&tools, &proton_version) {
-                        commandline = tool.commandline.clone();
-                        proton_args.push("waitforexitandrun".to_string());
-                        proton_args.push(command_str);
-                    } else {
-                        return Err(Error::other(std::format!(
-                            "Error finding the requested proton version of {}. Check to see if it is installed and try again.", proton_version
-                        )));
-                    }
-                }
+    let mut setup_cmd = Command::new(commandline);
+    setup_cmd
+        .args(&runner_args)
+        .args(&command_args)
+        .envs(runner_envs)
+        .current_dir(current_dir)
+        .env("LD_PRELOAD", "");
+    let timeout = setup_timeout(command_info.timeout_in_seconds, game_info);
+    let result = process::run_with_output(&mut setup_cmd, "setup", sender, timeout)?;
+
+    if !result.status.success() {
+        return Err(Error::other(result.failure_message("setup command")));
+    }
+
+    Ok(())
+}
+
+fn run_setup_archive_extract(
+    archive_info: &package_metadata::SetupArchiveExtract,
+) -> io::Result<()> {
+    let mut resolver = TemplateResolver::new();
+    let file = resolver.resolve(&archive_info.file);
+    let destination = resolver
+        .resolve_option(&archive_info.destination)
+        .unwrap_or_else(|| ".".to_string());
+    resolver.finish("archive extract step")?;
+
+    package::extract_archive(
+        Path::new(&file),
+        Path::new(&destination),
+        archive_info.strip_prefix.as_deref(),
+    )
+}
+
+fn run_setup_installer_extract(
+    installer_info: &package_metadata::SetupInstallerExtract,
+) -> io::Result<()> {
+    let mut resolver = TemplateResolver::new();
+    let file = resolver.resolve(&installer_info.file);
+    let destination = resolver
+        .resolve_option(&installer_info.destination)
+        .unwrap_or_else(|| ".".to_string());
+    resolver.finish("installer extract step")?;
+
+    let installer_type = installer_info
+        .installer_type
+        .as_deref()
+        .map(InstallerType::from_name)
+        .transpose()?;
+    let filter = FileFilter::new(
+        &installer_info.include,
+        &installer_info.exclude,
+        installer_info.strip_prefix.as_deref(),
+    )?;
+
+    let count = installer::extract_installer(
+        Path::new(&file),
+        installer_type,
+        Path::new(&destination),
+        &filter,
+    )?;
+    if count == 0 {
+        return Err(Error::other(std::format!(
+            "No files were extracted from {}, check the include and exclude filters",
+            file
+        )));
+    }
+    Ok(())
+}
+
+fn run_setup_copy(copy_info: &package_metadata::SetupCopy) -> io::Result<()> {
+    let mut resolver = TemplateResolver::new();
+    let source = PathBuf::from(resolver.resolve(&copy_info.source));
+    let mut destination = PathBuf::from(resolver.resolve(&copy_info.destination));
+    resolver.finish("copy step")?;
+
+    if !source.exists() {
+        return Err(Error::other(std::format!(
+            "{} does not exist",
+            source.display()
+        )));
+    }
+
+    // Like cp, copying into an existing directory keeps the source name.
+    if destination.is_dir() {
+        if let Some(source_name) = source.file_name() {
+            destination = destination.join(source_name);
+        }
+    }
+
+    if source.is_dir() {
+        for entry in WalkDir::new(&source) {
+            let entry = entry.map_err(Error::other)?;
+            let relative_path = entry.path().strip_prefix(&source).map_err(Error::other)?;
+            let new_path = destination.join(relative_path);
+            if entry.file_type().is_dir() {
+                fs::create_dir_all(&new_path)?;
+            } else {
+                info!("setup copy: {:?}", new_path);
+                fs::copy(entry.path(), &new_path)?;
             }
         }
+    } else {
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        info!("setup copy: {:?}", destination);
+        fs::copy(&source, &destination)?;
     }
 
-    let mut current_dir = env::current_dir().unwrap();
+    Ok(())
+}
 
-    if let Some(command_current_dir) = &setup_info.command_current_dir {
-        current_dir = command_current_dir.into();
+fn run_setup_delete(delete_info: &package_metadata::SetupDelete) -> io::Result<()> {
+    let mut resolver = TemplateResolver::new();
+    let path = PathBuf::from(resolver.resolve(&delete_info.path));
+    resolver.finish("delete step")?;
+
+    if path.is_dir() {
+        info!("setup delete directory: {:?}", path);
+        fs::remove_dir_all(&path)
+    } else if path.exists() {
+        info!("setup delete file: {:?}", path);
+        fs::remove_file(&path)
+    } else {
+        info!("setup delete: {:?} not found, continuing", path);
+        Ok(())
     }
+}
+
+fn run_setup_patch(
+    patch_info: &package_metadata::SetupPatch,
+    timeout: Option<Duration>,
+    sender: &std::sync::mpsc::Sender<String>,
+) -> io::Result<()> {
+    let mut resolver = TemplateResolver::new();
+    let patch_file = resolver.resolve(&patch_info.patch_file);
+    let directory = resolver
+        .resolve_option(&patch_info.directory)
+        .unwrap_or_else(|| ".".to_string());
+    resolver.finish("patch step")?;
+
+    let patch_path = fs::canonicalize(&patch_file)
+        .map_err(|err| Error::other(std::format!("{} could not be read: {}", patch_file, err)))?;
+    let Ok(patch_binary) = which::which("patch") else {
+        return Err(Error::other(
+            "The patch command is needed for this setup but could not be found. Install patch and try again.",
+        ));
+    };
 
     info!(
-        "setup cmd - commandline: {:?} proton_args: {:?} current_dir: {:?}",
-        commandline, proton_args, current_dir
+        "setup patch: applying {:?} in {} with -p{}",
+        patch_path, directory, patch_info.strip
     );
+    let mut patch_cmd = Command::new(patch_binary);
+    patch_cmd
+        .arg(std::format!("-p{}", patch_info.strip))
+        .arg("--batch")
+        .arg("-i")
+        .arg(&patch_path)
+        .current_dir(&directory);
+    let result = process::run_with_output(&mut patch_cmd, "patch", sender, timeout)?;
+
+    if !result.status.success() {
+        return Err(Error::other(result.failure_message("patch")));
+    }
 
-    let setup_cmd = Command::new(commandline)
-        .args(&proton_args)
-        .args(&setup_info.command_args)
-        .current_dir(current_dir)
-        .env("LD_PRELOAD", "")
-        .status()
-        .expect("failed to execute process");
+    Ok(())
+}
+
+fn run_setup_step(
+    step: &package_metadata::SetupStep,
+    game_info: &package_metadata::Game,
+    sender: &std::sync::mpsc::Sender<String>,
+) -> io::Result<()> {
+    match &step.action {
+        package_metadata::SetupAction::Command(command_info) => {
+            run_setup_command(command_info, false, game_info, sender)
+        }
+        package_metadata::SetupAction::RunExe(command_info) => {
+            run_setup_command(command_info, true, game_info, sender)
+        }
+        package_metadata::SetupAction::Bchunk(bchunk_info) => run_bchunk(bchunk_info),
+        package_metadata::SetupAction::IsoExtract(iso_extract_info) => {
+            run_iso_extract(iso_extract_info)
+        }
+        package_metadata::SetupAction::ArchiveExtract(archive_info) => {
+            run_setup_archive_extract(archive_info)
+        }
+        package_metadata::SetupAction::InstallerExtract(installer_info) => {
+            run_setup_installer_extract(installer_info)
+        }
+        package_metadata::SetupAction::Copy(copy_info) => run_setup_copy(copy_info),
+        package_metadata::SetupAction::Delete(delete_info) => run_setup_delete(delete_info),
+        package_metadata::SetupAction::Patch(patch_info) => {
+            run_setup_patch(patch_info, setup_timeout(None, game_info), sender)
+        }
+    }
+}
+
+/// Runs the setup steps in order. Completed steps are recorded, so after a failure the next
+/// launch continues from the step that failed instead of starting over.
+pub fn run_setup(
+    setup_info: &package_metadata::Setup,
+    game_info: &package_metadata::Game,
+    sender: &std::sync::mpsc::Sender<String>,
+) -> io::Result<()> {
+    let app_id = user_env::steam_app_id();
+    let steps = setup_info.find_steps();
+    let steps_json = serde_json::to_string(&steps).map_err(Error::other)?;
+    let steps_hash = package::generate_hash_from_string(&steps_json)?;
+
+    let completed_steps = package::find_setup_progress(&app_id, &steps_hash);
+    if completed_steps > 0 && completed_steps < steps.len() {
+        info!(
+            "run_setup. resuming at step {} of {}",
+            completed_steps + 1,
+            steps.len()
+        );
+    }
+
+    for (idx, step) in steps.iter().enumerate().skip(completed_steps) {
+        let step_message = std::format!("Setup step {} of {}", idx + 1, steps.len());
+        let description = step.description();
+        info!("{}: {}", step_message, description);
+
+        let status_obj = client::StatusObj {
+            label: Some(step_message.to_string()),
+            log_line: Some(std::format!("{}: {}", step_message, description)),
+            ..Default::default()
+        };
+        let status_str = serde_json::to_string(&status_obj).unwrap();
+        sender.send(status_str).unwrap();
+
+        if let Err(err) = run_setup_step(step, game_info, sender) {
+            error!("command::run_setup step {} err: {:?}", idx + 1, err);
+            if process::is_cancelled() {
+                return Err(Error::other(std::format!(
+                    "Setup was cancelled at {} ({}).\nThe next launch continues setup from this step.",
+                    step_message,
+                    description
+                )));
+            }
+            return Err(Error::other(std::format!(
+                "{} ({}) failed: {}\nThe next launch continues setup from this step.",
+                step_message,
+                description,
+                err
+            )));
+        }
 
-    if !setup_cmd.success() {
-        return Err(Error::other("setup failed"));
+        package::save_setup_progress(&app_id, &steps_hash, idx + 1)?;
     }
 
     File::create(setup_info.complete_path.clone())?;
+    package::save_setup_complete(&app_id, setup_info, game_info.choice_name())?;
+
+    if let Err(err) = package::clear_setup_progress(&app_id) {
+        error!("clear_setup_progress err: {:?}", err);
+    }
 
     Ok(())
 }
@@ -402,6 +750,7 @@ pub fn run(
     engine_choice: String,
     sender: &std::sync::mpsc::Sender<String>,
     after_setup_question_mode: bool,
+    skip_install: bool,
 ) -> io::Result<package_metadata::Game> {
     env::set_var(LUX_ERRORS_SUPPORTED, "1");
 
@@ -438,11 +787,21 @@ pub fn run(
         info!("tool dir: {:?}", user_env::tool_dir());
     }
 
-    if !after_setup_question_mode {
-        match package::install(&game_info, sender) {
-            Ok(()) => {}
-            Err(err) => {
-                return Err(err);
+    if skip_install {
+        info!("skipping install, keeping the installed engine version");
+    } else if !after_setup_question_mode {
+        if package::find_pending_rollback(&app_id, &game_info).is_some() {
+            package::install_engine_rollback(&app_id, &game_info, sender)?;
+        } else {
+            match package::install(&game_info, sender) {
+                Ok(()) => {}
+                Err(err) => {
+                    return Err(err);
+                }
+            }
+
+            if let Err(err) = package::record_installed_engine(&app_id, &game_info) {
+                error!("record_installed_engine err: {:?}", err);
             }
         }
     }
@@ -459,46 +818,112 @@ pub fn run(
     Ok(game_info)
 }
 
-fn get_proton_alias(game_info: &package_metadata::Game) -> io::Result<String> {
-    let app_id = user_env::steam_app_id();
-    let check_proton_choice_file_path = package::place_config_file(&app_id, "proton_choice.txt")?;
+// The Proton version picked by the user for this game, or for all games.
+fn find_user_proton_choice(app_id: &str) -> io::Result<Option<String>> {
+    let check_proton_choice_file_path = package::place_config_file(app_id, "proton_choice.txt")?;
     if check_proton_choice_file_path.exists() {
         let proton_choice_str = fs::read_to_string(check_proton_choice_file_path)?
             .trim()
             .to_string();
         info!("Using game specified Proton version {}", proton_choice_str);
+        return Ok(Some(proton_choice_str));
+    }
+
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("luxtorpeda");
+    let check_default_proton_file_path = xdg_dirs.place_config_file("default_proton_choice.txt")?;
+    if check_default_proton_file_path.exists() {
+        let proton_choice_str = fs::read_to_string(check_default_proton_file_path)?
+            .trim()
+            .to_string();
+        info!(
+            "Using default Proton version selected by user {}",
+            proton_choice_str
+        );
+        return Ok(Some(proton_choice_str));
+    }
+
+    Ok(None)
+}
+
+pub fn get_proton_alias(game_info: &package_metadata::Game) -> io::Result<String> {
+    let app_id = user_env::steam_app_id();
+    if let Some(proton_choice_str) = find_user_proton_choice(&app_id)? {
         return Ok(proton_choice_str);
-    } else {
-        let xdg_dirs = xdg::BaseDirectories::with_prefix("luxtorpeda");
-        let check_default_proton_file_path =
-            xdg_dirs.place_config_file("default_proton_choice.txt")?;
-        if check_default_proton_file_path.exists() {
-            let proton_choice_str = fs::read_to_string(check_default_proton_file_path)?
-                .trim()
-                .to_string();
-            info!(
-                "Using default Proton version selected by user {}",
-                proton_choice_str
-            );
-            return Ok(proton_choice_str);
-        } else if let Some(default_proton_choice) = &game_info.default_proton_choice {
+    }
+
+    let Some(steam_path) = user_env::steam_install_path() else {
+        if let Some(default_proton_choice) = &game_info.default_proton_choice {
             info!("Found default proton choice in game info: {default_proton_choice}");
             return Ok(default_proton_choice.to_string());
-        } else {
-            info!("Neither game or default specified Proton version has been found, selecting a Proton version that user has installed");
-            if let Some(steam_path) = user_env::steam_install_path() {
-                if let Ok(tools) = proton_handler::list_valve_proton_tools(&steam_path) {
-                    if let Some(first) = tools.first() {
-                        return Ok(first.alias.clone());
-                    }
-                }
-            }
         }
+        return Ok("proton_experimental".to_string());
+    };
+
+    let discovery = proton_handler::list_proton_tools(&steam_path);
+    let constraints = game_info.proton_constraints.clone().unwrap_or_default();
+
+    if let Some(default_proton_choice) = &game_info.default_proton_choice {
+        info!("Found default proton choice in game info: {default_proton_choice}");
+        match proton_handler::find_tool(&discovery.tools, default_proton_choice) {
+            Some(tool) => match proton_handler::check_tool_constraints(tool, &constraints) {
+                None => return Ok(default_proton_choice.to_string()),
+                Some(reason) => info!("Not using default proton choice: {}", reason),
+            },
+            None => info!(
+                "Default proton choice {} is not installed, looking for another version",
+                default_proton_choice
+            ),
+        }
+    }
+
+    if game_info.proton_constraints.is_some() {
+        if let Some(tool) = proton_handler::find_best_tool(&discovery.tools, &constraints) {
+            info!(
+                "Selected {} as the best installed match for the game",
+                tool.display_name
+            );
+            return Ok(tool.alias.clone());
+        }
+        info!("No installed Proton version matches the game constraints");
+    } else {
+        info!("Neither game or default specified Proton version has been found, selecting a Proton version that user has installed");
+        if let Some(tool) = proton_handler::find_newest_stable_valve_tool(&discovery.tools) {
+            info!("Selected newest stable Proton {}", tool.display_name);
+            return Ok(tool.alias.clone());
+        }
+        info!("No stable Valve Proton found, falling back to Proton Experimental");
     }
 
     Ok("proton_experimental".to_string())
 }
 
+/// Returns a warning when the Proton version picked by the user is outside the versions
+/// supported by the game.
+pub fn find_proton_constraint_warning(game_info: &package_metadata::Game) -> Option<String> {
+    let constraints = game_info.proton_constraints.as_ref()?;
+    let app_id = user_env::steam_app_id();
+    if runner::find_user_runner_choice(&app_id).is_some() {
+        return None;
+    }
+    let proton_choice = match find_user_proton_choice(&app_id) {
+        Ok(proton_choice) => proton_choice?,
+        Err(err) => {
+            error!("find_user_proton_choice err: {:?}", err);
+            return None;
+        }
+    };
+
+    let steam_path = user_env::steam_install_path()?;
+    let discovery = proton_handler::list_proton_tools(&steam_path);
+    match proton_handler::find_tool(&discovery.tools, &proton_choice) {
+        Some(tool) => proton_handler::check_tool_constraints(tool, constraints),
+        None => Some(std::format!(
+            "The selected Proton version {} is not installed.",
+            proton_choice
+        )),
+    }
+}
+
 pub fn run_wrapper(
     args: &[&str],
     game_info: &package_metadata::Game,
@@ -520,8 +945,9 @@ pub fn run_wrapper(
     }
 
     let mut ret: Result<(), Error> = Ok(());
+    let mut report_prompts: Vec<client::PromptRequestData> = Vec::new();
 
-    match find_game_command(game_info, args) {
+    match find_game_command(game_info, args)? {
         None => ret = Err(Error::other("No command line defined")),
         Some((cmd, cmd_args)) => {
             info!("run: \"{}\" with args: {:?} {:?}", cmd, cmd_args, exe_args);
@@ -536,101 +962,128 @@ pub fn run_wrapper(
             let status_str = serde_json::to_string(&status_obj).unwrap();
             sender.send(status_str).unwrap();
 
-            let mut proton_args: Vec<String> = Vec::new();
+            let mut runner_args: Vec<String> = Vec::new();
+            let mut runner_envs: Vec<(String, String)> = Vec::new();
             let mut commandline: String = cmd.clone();
 
+            let mut command_envs: Vec<(String, String)> = Vec::new();
             if let Some(vars) = &game_info.command_vars {
                 for (key, value) in vars {
                     info!("Set environmental variable {} to {}", key, value);
-                    user_env::set_env_var(key, value);
+                    command_envs.push((key.to_string(), value.to_string()));
                 }
             }
 
+            let launch_profile = LaunchProfile::from_file(&user_env::steam_app_id());
+            for (key, value) in &launch_profile.env {
+                info!(
+                    "Set environmental variable {} to {} from launch profile",
+                    key, value
+                );
+                command_envs.push((key.to_string(), value.to_string()));
+            }
+
             if cmd.ends_with(".exe") {
-                if let Some(steam_path) = user_env::steam_install_path() {
-                    if let Ok(tools) = proton_handler::list_proton_tools(&steam_path) {
-                        if let Ok(proton_version) = get_proton_alias(&game_info) {
-                            if let Some(tool) = proton_handler::find_tool(&tools, &proton_version) {
-                                commandline = tool.commandline.clone();
-                                proton_args.push("waitforexitandrun".to_string());
-
-                                let tmp_path = format!(
-                                    "{}/{}",
-                                    PathBuf::from(args[0])
-                                        .parent()
-                                        .unwrap()
-                                        .display()
-                                        .to_string(),
-                                    cmd
-                                );
-
-                                if game_info.command_relative_path {
-                                    proton_args.push(cmd);
-                                } else {
-                                    proton_args.push(tmp_path); // the original exe
-                                }
-                            } else {
-                                return Err(Error::other(std::format!(
-                                    "Error finding the requested proton version of {}. Check to see if it is installed and try again.", proton_version
-                                )));
-                            }
-                        }
-                    }
+                if let Some(runner) = runner::find_runner(game_info)? {
+                    info!("runner: {}", runner.name());
+
+                    let tmp_path = format!(
+                        "{}/{}",
+                        PathBuf::from(args[0]).parent().unwrap().display(),
+                        cmd
+                    );
+
+                    let exe = if game_info.command_relative_path {
+                        cmd
+                    } else {
+                        tmp_path // the original exe
+                    };
+
+                    (commandline, runner_args) = runner.command(exe);
+                    runner_envs = runner.envs();
                 }
             }
 
             let mut current_dir = env::current_dir().unwrap();
 
-            if let Some(command_current_dir) = &game_info.command_current_dir {
+            let mut resolver = TemplateResolver::new();
+            if let Some(command_current_dir) =
+                resolver.resolve_option(&game_info.command_current_dir)
+            {
                 current_dir = command_current_dir.into();
             }
+            resolver.finish("engine working directory")?;
+
+            if let Some(working_dir) = &launch_profile.working_dir {
+                current_dir = working_dir.into();
+            }
+
+            let mut command_args = runner_args;
+            command_args.extend(cmd_args);
+            command_args.extend(exe_args.iter().map(|arg| arg.to_string()));
+            let (commandline, command_args) =
+                launch_profile.wrap_command(commandline, command_args)?;
 
             info!(
-                "command - commandline: {:?} proton_args: {:?} current_dir: {:?}",
-                commandline, proton_args, current_dir
+                "command - commandline: {:?} args: {:?} current_dir: {:?}",
+                commandline, command_args, current_dir
             );
 
-            match Command::new(commandline)
-                .args(&proton_args)
-                .args(cmd_args)
-                .args(exe_args)
-                .current_dir(current_dir)
+            let app_id = user_env::steam_app_id();
+            let report_path = engine_report::report_path(&app_id)?;
+            engine_report::clear_report(&report_path);
+
+            let mut engine_cmd = Command::new(commandline);
+            engine_cmd
+                .args(&command_args)
+                .envs(runner_envs)
+                .envs(command_envs)
+                .current_dir(&current_dir)
                 .env(LUX_ORIGINAL_EXE, args[0])
                 .env(LUX_ORIGINAL_EXE_FILE, exe_file)
-                .spawn()
-            {
-                Ok(mut child) => {
-                    let config = config::Config::from_config_file();
+                .env(engine_report::LUX_REPORT_FILE, &report_path)
+                .env(
+                    engine_report::LUX_REPORT_VERSION,
+                    engine_report::REPORT_VERSION.to_string(),
+                );
+
+            let config = config::Config::from_config_file();
+            let child = if config.close_client_on_launch {
+                // Output is not captured, the pipes would close together with the client.
+                OutputProcess::spawn_without_output(&mut engine_cmd)
+            } else {
+                OutputProcess::spawn(&mut engine_cmd, "engine", sender)
+            };
+
+            match child {
+                Ok(child) => {
                     if config.close_client_on_launch {
                         info!("closing client without waiting on engine close");
                         let _ = sender.send("quit_client".to_string());
                     }
-                    match child.wait() {
-                        Ok(status) => {
-                            info!("run returned with {}", status);
-                            if let Some(exit_code) = status.code() {
-                                if exit_code == 10 {
-                                    info!("run returned with lux exit code");
-                                    match fs::read_to_string("last_error.txt") {
-                                        Ok(s) => {
-                                            ret = Err(Error::other(std::format!(
-                                                "Error on run: {}",
-                                                s
-                                            )));
-                                        }
-                                        Err(err) => {
-                                            error!("read err: {:?}", err);
-                                        }
-                                    };
-                                }
-                            } else {
-                                ret = Ok(());
+                    match child.wait(None) {
+                        Ok(result) if config.close_client_on_launch => {
+                            // The client is gone, the report is shown on the next launch.
+                            info!("run returned with {}", result.status);
+                        }
+                        Ok(result) => {
+                            info!("run returned with {}", result.status);
+                            match engine_report::process_engine_exit(
+                                &app_id,
+                                result.status,
+                                &report_path,
+                                &current_dir,
+                                &result.last_lines,
+                            ) {
+                                EngineOutcome::Completed(prompts) => report_prompts = prompts,
+                                EngineOutcome::Failed(message) => ret = Err(Error::other(message)),
                             }
+                            engine_report::clear_report(&report_path);
                         }
                         Err(err) => {
                             ret = Err(err);
                         }
-                    };
+                    }
                 }
                 Err(err) => {
                     ret = Err(err);
@@ -640,7 +1093,20 @@ pub fn run_wrapper(
     };
 
     if ret.is_ok() {
-        let _ = sender.send("quit_client".to_string());
+        if report_prompts.is_empty() {
+            let _ = sender.send("quit_client".to_string());
+        } else {
+            // The client stays open until the user has seen what the engine reported.
+            let status_obj = client::StatusObj {
+                prompt_items: Some(client::PromptItemsData {
+                    prompt_items: report_prompts,
+                    prompt_id: "allpromptsenginereport".to_string(),
+                }),
+                ..Default::default()
+            };
+            let status_str = serde_json::to_string(&status_obj).unwrap();
+            let _ = sender.send(status_str);
+        }
         Ok(())
     } else {
         ret
@@ -688,6 +1154,11 @@ pub fn main(running_in_editor: bool) -> io::Result<()> {
 
     user_env::assure_tool_dir(args[0])?;
 
+    // Command line commands run before logging is set up, so their output stays clean.
+    if let Some(cli_args) = cli::find_cli_args(&args) {
+        std::process::exit(cli::run(cli_args));
+    }
+
     match env::var(LUX_WRITE_LOGGING) {
         Ok(val) => {
             if val == "1" {
@@ -717,6 +1188,10 @@ pub fn main(running_in_editor: bool) -> io::Result<()> {
         }
     }
 
+    if let Err(err) = process::handle_termination_signals() {
+        error!("handle_termination_signals err: {:?}", err);
+    }
+
     let mut on_steam_deck = false;
 
     match env::var(STEAM_DECK_ENV) {
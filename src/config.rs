@@ -16,6 +16,10 @@ pub struct Config {
     pub close_client_on_launch: bool,
     pub steam_app_id_install_wait_in_seconds: u32,
     pub additional_remote_packages: Option<Vec<String>>,
+    pub metadata_refresh_interval_in_minutes: u32,
+    pub engine_versions_to_keep: u32,
+    pub wine_path: Option<String>,
+    pub manage_proton_prefix: bool,
 }
 
 impl Default for Config {
@@ -29,6 +33,10 @@ impl Default for Config {
             close_client_on_launch: false,
             steam_app_id_install_wait_in_seconds: 600,
             additional_remote_packages: None,
+            metadata_refresh_interval_in_minutes: 0,
+            engine_versions_to_keep: 3,
+            wine_path: None,
+            manage_proton_prefix: false,
         }
     }
 }
@@ -0,0 +1,639 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Error, Read};
+use std::path::Path;
+
+use crate::iso::SYNC_PATTERN;
+
+// Frames (sectors) per second in cue sheet INDEX timestamps.
+pub const FRAMES_PER_SECOND: u32 = 75;
+const MAX_TRACKS: u32 = 99;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CueFileType {
+    Binary,
+    Motorola,
+    Wave,
+    Other,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CueTrackType {
+    Audio,
+    Mode1_2048,
+    Mode1_2352,
+    Mode2_2336,
+    Mode2_2352,
+    Other,
+}
+
+impl CueTrackType {
+    fn parse(value: &str) -> CueTrackType {
+        match value.to_uppercase().as_str() {
+            "AUDIO" => CueTrackType::Audio,
+            "MODE1/2048" => CueTrackType::Mode1_2048,
+            "MODE1/2352" => CueTrackType::Mode1_2352,
+            "MODE2/2336" => CueTrackType::Mode2_2336,
+            "MODE2/2352" => CueTrackType::Mode2_2352,
+            _ => CueTrackType::Other,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            CueTrackType::Audio => "AUDIO",
+            CueTrackType::Mode1_2048 => "MODE1/2048",
+            CueTrackType::Mode1_2352 => "MODE1/2352",
+            CueTrackType::Mode2_2336 => "MODE2/2336",
+            CueTrackType::Mode2_2352 => "MODE2/2352",
+            CueTrackType::Other => "OTHER",
+        }
+    }
+
+    pub fn sector_size(&self) -> Option<u64> {
+        match self {
+            CueTrackType::Mode1_2048 => Some(2048),
+            CueTrackType::Mode2_2336 => Some(2336),
+            CueTrackType::Audio | CueTrackType::Mode1_2352 | CueTrackType::Mode2_2352 => Some(2352),
+            CueTrackType::Other => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CueIndex {
+    pub number: u32,
+    pub frames: u32, // position in the file, in sectors
+}
+
+#[derive(Clone, Debug)]
+pub struct CueTrack {
+    pub number: u32,
+    pub track_type: CueTrackType,
+    pub type_name: String, // as written in the cue sheet, for error messages
+    pub indexes: Vec<CueIndex>,
+}
+
+impl CueTrack {
+    // Where the track starts in its file, in sectors. INDEX 01 is the start, INDEX 00 the pregap.
+    pub fn start_frames(&self) -> Option<u32> {
+        self.indexes
+            .iter()
+            .find(|index| index.number == 1)
+            .map(|index| index.frames)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CueFile {
+    pub name: String,
+    pub file_type: CueFileType,
+    pub tracks: Vec<CueTrack>,
+}
+
+impl CueFile {
+    /// Byte offset of the INDEX 01 of each track in the file. Tracks of one file can have
+    /// different sector sizes (such as a MODE1/2048 track followed by AUDIO tracks), so every
+    /// track before it adds its own length in bytes.
+    pub fn track_offsets(&self) -> Vec<Option<u64>> {
+        let mut offsets = Vec::new();
+        // Frames and bytes to where the previous track starts, with the sector size of that
+        // track. Unknown once a track has an unsupported type or no index.
+        let mut position: Option<(u32, u64, Option<u64>)> = Some((0, 0, None));
+
+        for track in &self.tracks {
+            let sector_size = track.track_type.sector_size();
+            let first_frames = track.indexes.iter().map(|index| index.frames).min();
+
+            let offset = match (position, sector_size, first_frames) {
+                (Some((frames, bytes, previous_size)), Some(sector_size), Some(first_frames)) => {
+                    // Sectors before the first track are counted with its own sector size.
+                    let previous_size = previous_size.unwrap_or(sector_size);
+                    let track_bytes =
+                        bytes + u64::from(first_frames.saturating_sub(frames)) * previous_size;
+                    position = Some((first_frames, track_bytes, Some(sector_size)));
+                    track.start_frames().map(|start_frames| {
+                        track_bytes
+                            + u64::from(start_frames.saturating_sub(first_frames)) * sector_size
+                    })
+                }
+                _ => {
+                    position = None;
+                    None
+                }
+            };
+            offsets.push(offset);
+        }
+
+        offsets
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CueSheet {
+    pub files: Vec<CueFile>,
+}
+
+impl CueSheet {
+    pub fn from_file(path: &Path) -> io::Result<CueSheet> {
+        let contents = fs::read(path)?;
+        CueSheet::parse(&String::from_utf8_lossy(&contents))
+    }
+
+    pub fn parse(contents: &str) -> io::Result<CueSheet> {
+        let mut cue_sheet = CueSheet::default();
+
+        for (idx, line) in contents.lines().enumerate() {
+            let line_number = idx + 1;
+            let words = split_cue_line(line);
+            let Some(command) = words.first() else {
+                continue;
+            };
+
+            match command.to_uppercase().as_str() {
+                "FILE" => {
+                    let [_, name, file_type] = words.as_slice() else {
+                        return Err(cue_error(line_number, "FILE needs a name and a type"));
+                    };
+                    let file_type = match file_type.to_uppercase().as_str() {
+                        "BINARY" => CueFileType::Binary,
+                        "MOTOROLA" => CueFileType::Motorola,
+                        "WAVE" => CueFileType::Wave,
+                        _ => CueFileType::Other,
+                    };
+                    cue_sheet.files.push(CueFile {
+                        name: name.to_string(),
+                        file_type,
+                        tracks: Vec::new(),
+                    });
+                }
+                "TRACK" => {
+                    let [_, number, track_type] = words.as_slice() else {
+                        return Err(cue_error(line_number, "TRACK needs a number and a type"));
+                    };
+                    let number = number
+                        .parse::<u32>()
+                        .map_err(|_| cue_error(line_number, "invalid TRACK number"))?;
+                    let Some(file) = cue_sheet.files.last_mut() else {
+                        return Err(cue_error(line_number, "TRACK before FILE"));
+                    };
+                    file.tracks.push(CueTrack {
+                        number,
+                        track_type: CueTrackType::parse(track_type),
+                        type_name: track_type.to_string(),
+                        indexes: Vec::new(),
+                    });
+                }
+                "INDEX" => {
+                    let [_, number, timestamp] = words.as_slice() else {
+                        return Err(cue_error(line_number, "INDEX needs a number and a time"));
+                    };
+                    let number = number
+                        .parse::<u32>()
+                        .map_err(|_| cue_error(line_number, "invalid INDEX number"))?;
+                    let frames = parse_timestamp(timestamp).ok_or_else(|| {
+                        cue_error(
+                            line_number,
+                            &std::format!("invalid INDEX time {}", timestamp),
+                        )
+                    })?;
+                    let Some(track) = cue_sheet
+                        .files
+                        .last_mut()
+                        .and_then(|file| file.tracks.last_mut())
+                    else {
+                        return Err(cue_error(line_number, "INDEX before TRACK"));
+                    };
+                    track.indexes.push(CueIndex { number, frames });
+                }
+                _ => {}
+            }
+        }
+
+        Ok(cue_sheet)
+    }
+
+    /// Builds a cue sheet with a single data track for a bin file that came without one. The track
+    /// type is taken from the sector header when the sectors are raw, otherwise the file has to
+    /// be made of 2048 byte sectors.
+    pub fn generate_for_bin(bin_path: &Path) -> io::Result<CueSheet> {
+        let bin_size = fs::metadata(bin_path)?.len();
+        let mut header = [0u8; 16];
+        let header_read = File::open(bin_path)?.read_exact(&mut header).is_ok();
+
+        let track_type = if header_read && header[0..12] == SYNC_PATTERN {
+            if bin_size % 2352 != 0 {
+                return Err(Error::other(std::format!(
+                    "{} has raw sectors, but its size of {} bytes is not a multiple of 2352",
+                    bin_path.display(),
+                    bin_size
+                )));
+            }
+            match header[15] {
+                1 => CueTrackType::Mode1_2352,
+                2 => CueTrackType::Mode2_2352,
+                mode => {
+                    return Err(Error::other(std::format!(
+                        "{} starts with a sector of unknown mode {}",
+                        bin_path.display(),
+                        mode
+                    )))
+                }
+            }
+        } else if bin_size > 0 && bin_size % 2048 == 0 {
+            CueTrackType::Mode1_2048
+        } else {
+            return Err(Error::other(std::format!(
+                "Could not generate a cue sheet for {}, its size of {} bytes does not match any track type.",
+                bin_path.display(),
+                bin_size
+            )));
+        };
+
+        let name = bin_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        Ok(CueSheet {
+            files: vec![CueFile {
+                name,
+                file_type: CueFileType::Binary,
+                tracks: vec![CueTrack {
+                    number: 1,
+                    track_type,
+                    type_name: track_type.as_str().to_string(),
+                    indexes: vec![CueIndex {
+                        number: 1,
+                        frames: 0,
+                    }],
+                }],
+            }],
+        })
+    }
+
+    /// Checks that the cue sheet describes the given bin file: a single binary FILE, known track
+    /// types, track and INDEX numbers in order, and every track starting inside the bin.
+    pub fn validate_for_bin(&self, bin_path: &Path) -> io::Result<()> {
+        let [cue_file] = self.files.as_slice() else {
+            return Err(Error::other(std::format!(
+                "The cue sheet lists {} FILE entries, but exactly one is needed for {}.",
+                self.files.len(),
+                bin_path.display()
+            )));
+        };
+
+        if !matches!(
+            cue_file.file_type,
+            CueFileType::Binary | CueFileType::Motorola
+        ) {
+            return Err(Error::other(std::format!(
+                "The cue sheet FILE {} is not BINARY.",
+                cue_file.name
+            )));
+        }
+
+        if cue_file.tracks.is_empty() {
+            return Err(Error::other("The cue sheet has no TRACK entries."));
+        }
+
+        let bin_size = fs::metadata(bin_path).map_err(|err| {
+            Error::other(std::format!(
+                "Could not read {} - {}",
+                bin_path.display(),
+                err
+            ))
+        })?;
+        let bin_size = bin_size.len();
+
+        let mut previous_number = 0;
+        let mut previous_frames: Option<u32> = None;
+        let mut last_track_start: Option<(u64, u64)> = None;
+
+        for (track, start_offset) in cue_file.tracks.iter().zip(cue_file.track_offsets()) {
+            let Some(sector_size) = track.track_type.sector_size() else {
+                return Err(Error::other(std::format!(
+                    "Track {} has the unsupported type {}.",
+                    track.number,
+                    track.type_name
+                )));
+            };
+
+            if track.number == 0 || track.number > MAX_TRACKS || track.number <= previous_number {
+                return Err(Error::other(std::format!(
+                    "Track {} is out of order, track numbers have to go up from 1 to {}.",
+                    track.number,
+                    MAX_TRACKS
+                )));
+            }
+            previous_number = track.number;
+
+            let mut previous_index: Option<u32> = None;
+            for index in &track.indexes {
+                if previous_index.is_some_and(|previous| index.number <= previous) {
+                    return Err(Error::other(std::format!(
+                        "INDEX {:02} of track {} is out of order.",
+                        index.number,
+                        track.number
+                    )));
+                }
+                if previous_frames.is_some_and(|previous| index.frames < previous) {
+                    return Err(Error::other(std::format!(
+                        "INDEX {:02} of track {} starts before the previous index.",
+                        index.number,
+                        track.number
+                    )));
+                }
+                previous_index = Some(index.number);
+                previous_frames = Some(index.frames);
+            }
+
+            let (Some(start_frames), Some(start_offset)) = (track.start_frames(), start_offset)
+            else {
+                return Err(Error::other(std::format!(
+                    "Track {} has no INDEX 01.",
+                    track.number
+                )));
+            };
+
+            if start_offset >= bin_size {
+                return Err(Error::other(std::format!(
+                    "Track {} starts at {}, past the end of {} ({} bytes). The cue sheet does not belong to this bin file.",
+                    track.number,
+                    format_timestamp(start_frames),
+                    bin_path.display(),
+                    bin_size
+                )));
+            }
+            last_track_start = Some((start_offset, sector_size));
+        }
+
+        // The length of every other track is given by the cue sheet, the last one runs to the
+        // end of the bin and has to be made of complete sectors.
+        if let Some((start_offset, sector_size)) = last_track_start {
+            if (bin_size - start_offset) % sector_size != 0 {
+                return Err(Error::other(std::format!(
+                    "{} is {} bytes, which is not a multiple of the {} byte sectors of its cue sheet. The bin file may be incomplete.",
+                    bin_path.display(),
+                    bin_size,
+                    sector_size
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn to_cue_string(&self) -> String {
+        let mut contents = String::new();
+        for file in &self.files {
+            let file_type = match file.file_type {
+                CueFileType::Binary | CueFileType::Other => "BINARY",
+                CueFileType::Motorola => "MOTOROLA",
+                CueFileType::Wave => "WAVE",
+            };
+            contents.push_str(&std::format!("FILE \"{}\" {}\n", file.name, file_type));
+            for track in &file.tracks {
+                contents.push_str(&std::format!(
+                    "  TRACK {:02} {}\n",
+                    track.number,
+                    track.type_name
+                ));
+                for index in &track.indexes {
+                    contents.push_str(&std::format!(
+                        "    INDEX {:02} {}\n",
+                        index.number,
+                        format_timestamp(index.frames)
+                    ));
+                }
+            }
+        }
+        contents
+    }
+}
+
+// Splits a cue sheet line into words, a double quoted word can contain spaces. Backslashes are
+// kept, they are path separators in cue sheets made on Windows.
+fn split_cue_line(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut in_quotes = false;
+
+    for c in line.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if in_word {
+        words.push(current);
+    }
+    words
+}
+
+fn cue_error(line_number: usize, message: &str) -> Error {
+    Error::other(std::format!("cue sheet line {}: {}", line_number, message))
+}
+
+// "mm:ss:ff" -> frames
+pub fn parse_timestamp(timestamp: &str) -> Option<u32> {
+    let parts: Vec<u32> = timestamp
+        .split(':')
+        .map(|part| part.parse::<u32>().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    let [minutes, seconds, frames] = parts.as_slice() else {
+        return None;
+    };
+    if *seconds >= 60 || *frames >= FRAMES_PER_SECOND {
+        return None;
+    }
+    Some((minutes * 60 + seconds) * FRAMES_PER_SECOND + frames)
+}
+
+// frames -> "mm:ss:ff"
+pub fn format_timestamp(frames: u32) -> String {
+    let seconds = frames / FRAMES_PER_SECOND;
+    std::format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 60,
+        seconds % 60,
+        frames % FRAMES_PER_SECOND
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::FixtureDir;
+
+    fn raw_sectors(sectors: usize, mode: u8) -> Vec<u8> {
+        let mut data = Vec::new();
+        for _ in 0..sectors {
+            let mut sector = vec![0u8; 2352];
+            sector[0..12].copy_from_slice(&SYNC_PATTERN);
+            sector[15] = mode;
+            data.extend(sector);
+        }
+        data
+    }
+
+    fn validate(fixture: &FixtureDir, cue: &str, bin: &[u8]) -> io::Result<()> {
+        let bin_path = fixture.write("game.bin", bin);
+        CueSheet::parse(cue)?.validate_for_bin(&bin_path)
+    }
+
+    const MIXED_CUE: &str = "FILE \"game.bin\" BINARY\n  TRACK 01 MODE1/2048\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    INDEX 00 00:00:20\n    INDEX 01 00:00:22\n  TRACK 03 AUDIO\n    INDEX 01 00:00:25\n";
+
+    #[test]
+    fn parse_quoted_names() {
+        let cue_sheet = CueSheet::parse(
+            "REM made on windows\nFILE \"disc\\My Game (Disc 1).bin\" BINARY\n  TRACK 01 mode2/2352\n    INDEX 01 01:02:03\n",
+        )
+        .unwrap();
+        let file = &cue_sheet.files[0];
+        assert_eq!(file.name, "disc\\My Game (Disc 1).bin");
+        assert_eq!(file.file_type, CueFileType::Binary);
+        assert_eq!(file.tracks[0].track_type, CueTrackType::Mode2_2352);
+        assert_eq!(file.tracks[0].start_frames(), Some((62 * 75) + 3));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = CueSheet::parse("TRACK 01 AUDIO\n").unwrap_err();
+        assert_eq!(err.to_string(), "cue sheet line 1: TRACK before FILE");
+        let err = CueSheet::parse("FILE \"a.bin\" BINARY\nTRACK 01 AUDIO\nINDEX 01 00:60:00\n")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cue sheet line 3: invalid INDEX time 00:60:00"
+        );
+    }
+
+    #[test]
+    fn offsets_of_mixed_sector_sizes() {
+        let cue_sheet = CueSheet::parse(MIXED_CUE).unwrap();
+        assert_eq!(
+            cue_sheet.files[0].track_offsets(),
+            vec![
+                Some(0),
+                Some(20 * 2048 + 2 * 2352),
+                Some(20 * 2048 + 5 * 2352)
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_mixed_sector_sizes() {
+        let fixture = FixtureDir::new("cue", "mixed");
+        let mut bin = vec![0u8; 20 * 2048];
+        bin.extend(vec![0u8; 8 * 2352]);
+        assert!(validate(&fixture, MIXED_CUE, &bin).is_ok());
+
+        // The last audio track is missing half a sector.
+        bin.truncate(bin.len() - 1176);
+        let err = validate(&fixture, MIXED_CUE, &bin).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("is not a multiple of the 2352 byte sectors"));
+    }
+
+    #[test]
+    fn validate_out_of_order_tracks() {
+        let fixture = FixtureDir::new("cue", "order");
+        let cue = "FILE \"game.bin\" BINARY\n  TRACK 02 MODE1/2352\n    INDEX 01 00:00:00\n  TRACK 01 AUDIO\n    INDEX 01 00:00:10\n";
+        let err = validate(&fixture, cue, &raw_sectors(20, 1)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Track 1 is out of order, track numbers have to go up from 1 to 99."
+        );
+    }
+
+    #[test]
+    fn validate_missing_index_01() {
+        let fixture = FixtureDir::new("cue", "index");
+        let cue = "FILE \"game.bin\" BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    INDEX 00 00:00:10\n";
+        let err = validate(&fixture, cue, &raw_sectors(20, 1)).unwrap_err();
+        assert_eq!(err.to_string(), "Track 2 has no INDEX 01.");
+    }
+
+    #[test]
+    fn validate_bin_size() {
+        let fixture = FixtureDir::new("cue", "size");
+        let cue = "FILE \"game.bin\" BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n";
+
+        let mut bin = raw_sectors(20, 1);
+        bin.extend([0u8; 100]);
+        let err = validate(&fixture, cue, &bin).unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("is 47140 bytes, which is not a multiple of the 2352 byte sectors of its cue sheet. The bin file may be incomplete."));
+
+        let past_end = "FILE \"game.bin\" BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    INDEX 01 00:01:00\n";
+        let err = validate(&fixture, past_end, &raw_sectors(20, 1)).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Track 2 starts at 00:01:00, past the end of"));
+    }
+
+    #[test]
+    fn generate_for_raw_and_cooked_bins() {
+        let fixture = FixtureDir::new("cue", "generate");
+
+        let mode1 = fixture.write("mode1.bin", raw_sectors(4, 1));
+        let cue_sheet = CueSheet::generate_for_bin(&mode1).unwrap();
+        assert_eq!(
+            cue_sheet.to_cue_string(),
+            "FILE \"mode1.bin\" BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n"
+        );
+        assert!(cue_sheet.validate_for_bin(&mode1).is_ok());
+
+        let mode2 = fixture.write("mode2.bin", raw_sectors(4, 2));
+        let cue_sheet = CueSheet::generate_for_bin(&mode2).unwrap();
+        assert_eq!(
+            cue_sheet.files[0].tracks[0].track_type,
+            CueTrackType::Mode2_2352
+        );
+
+        let cooked = fixture.write("cooked.bin", [0u8; 4 * 2048]);
+        let cue_sheet = CueSheet::generate_for_bin(&cooked).unwrap();
+        assert_eq!(
+            cue_sheet.files[0].tracks[0].track_type,
+            CueTrackType::Mode1_2048
+        );
+        // The generated sheet reads back the same.
+        let parsed = CueSheet::parse(&cue_sheet.to_cue_string()).unwrap();
+        assert!(parsed.validate_for_bin(&cooked).is_ok());
+
+        let mut truncated_raw = raw_sectors(4, 1);
+        truncated_raw.pop();
+        let truncated_raw = fixture.write("truncated.bin", &truncated_raw);
+        assert!(CueSheet::generate_for_bin(&truncated_raw).is_err());
+
+        let odd = fixture.write("odd.bin", [0u8; 3000]);
+        assert!(CueSheet::generate_for_bin(&odd).is_err());
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("00:02:00"), Some(150));
+        assert_eq!(parse_timestamp("74:59:74"), Some(337499));
+        assert_eq!(parse_timestamp("00:00:75"), None);
+        assert_eq!(parse_timestamp("00:00"), None);
+        assert_eq!(format_timestamp(337499), "74:59:74");
+    }
+}
@@ -0,0 +1,1005 @@
+use log::{info, warn};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Error, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::cue_sheet::{CueFileType, CueSheet, CueTrackType};
+use crate::iso::{IsoImage, SectorLayout, SECTOR_SIZE, SYNC_PATTERN};
+
+pub const RAW_SECTOR_SIZE: u64 = 2352;
+const SUBCHANNEL_SECTOR_SIZE: u64 = 2448;
+const ISO_DESCRIPTOR_SECTOR: u64 = 16;
+
+/// Image types that can be extracted, in the order they are preferred when searching a
+/// directory. The descriptor files come before the raw data files they point to.
+pub const DISC_IMAGE_EXTENSIONS: [&str; 8] =
+    ["iso", "cue", "ccd", "mds", "nrg", "bin", "img", "mdf"];
+
+static MDS_SIGNATURE: &[u8] = b"MEDIA DESCRIPTOR";
+const MDS_SESSION_BLOCK_SIZE: usize = 24;
+const MDS_TRACK_BLOCK_SIZE: usize = 80;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrackKind {
+    Data,
+    Audio,
+}
+
+#[derive(Clone, Debug)]
+pub struct DiscTrack {
+    pub number: u32,
+    pub kind: TrackKind,
+    pub file: PathBuf,
+    pub start_offset: u64, // byte offset of the first sector in the file
+    pub sector_size: u64,
+    pub data_offset: u64, // where the user data starts in each sector
+    pub sectors: u64,
+    pub big_endian_audio: bool,
+}
+
+impl DiscTrack {
+    pub fn layout(&self) -> SectorLayout {
+        SectorLayout {
+            start_offset: self.start_offset,
+            sector_size: self.sector_size,
+            data_offset: self.data_offset,
+        }
+    }
+
+    /// Writes an audio track as a 44.1 kHz 16 bit stereo WAV file.
+    pub fn write_wav(&self, destination: &Path) -> io::Result<()> {
+        if self.kind != TrackKind::Audio {
+            return Err(Error::other(std::format!(
+                "track {} is not an audio track",
+                self.number
+            )));
+        }
+
+        let data_length = self.sectors * RAW_SECTOR_SIZE;
+        let riff_length = u32::try_from(data_length + 36)
+            .map_err(|_| Error::other("audio track too large for a WAV file"))?;
+
+        let mut reader = BufReader::new(File::open(&self.file)?);
+        let mut writer = BufWriter::new(File::create(destination)?);
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&riff_length.to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&2u16.to_le_bytes())?; // channels
+        writer.write_all(&44100u32.to_le_bytes())?;
+        writer.write_all(&(44100u32 * 4).to_le_bytes())?;
+        writer.write_all(&4u16.to_le_bytes())?; // block align
+        writer.write_all(&16u16.to_le_bytes())?; // bits per sample
+        writer.write_all(b"data")?;
+        writer.write_all(&(data_length as u32).to_le_bytes())?;
+
+        let mut sector = [0u8; RAW_SECTOR_SIZE as usize];
+        for idx in 0..self.sectors {
+            reader.seek(SeekFrom::Start(self.start_offset + idx * self.sector_size))?;
+            reader.read_exact(&mut sector)?;
+            if self.big_endian_audio {
+                for sample in sector.chunks_exact_mut(2) {
+                    sample.swap(0, 1);
+                }
+            }
+            writer.write_all(&sector)?;
+        }
+
+        writer.flush()
+    }
+
+    /// Writes an audio track as an OGG Vorbis file, using oggenc or ffmpeg for the encoding.
+    pub fn write_ogg(&self, destination: &Path) -> io::Result<()> {
+        let wav_path = destination.with_extension("wav");
+        self.write_wav(&wav_path)?;
+
+        let status = if let Ok(oggenc) = which::which("oggenc") {
+            Command::new(oggenc)
+                .args(["-Q", "-q", "6", "-o"])
+                .arg(destination)
+                .arg(&wav_path)
+                .status()
+        } else if let Ok(ffmpeg) = which::which("ffmpeg") {
+            Command::new(ffmpeg)
+                .args(["-y", "-loglevel", "error", "-i"])
+                .arg(&wav_path)
+                .args(["-c:a", "libvorbis", "-q:a", "6"])
+                .arg(destination)
+                .status()
+        } else {
+            let _ = fs::remove_file(&wav_path);
+            return Err(Error::other(
+                "Converting CD audio to OGG needs oggenc or ffmpeg, but neither could be found. Install one of them and try again.",
+            ));
+        };
+
+        let _ = fs::remove_file(&wav_path);
+        let status = status?;
+        if !status.success() {
+            return Err(Error::other(std::format!(
+                "OGG encoding of track {} failed with {}",
+                self.number,
+                status
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The tracks of a CD image, read from its descriptor file. Data tracks are read in place, so
+/// nothing has to be converted to an intermediate iso first.
+pub struct DiscImage {
+    pub tracks: Vec<DiscTrack>,
+}
+
+impl DiscImage {
+    pub fn open(path: &Path) -> io::Result<DiscImage> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+
+        let tracks = match extension.as_str() {
+            "iso" => vec![DiscTrack {
+                number: 1,
+                kind: TrackKind::Data,
+                file: path.to_path_buf(),
+                start_offset: 0,
+                sector_size: SECTOR_SIZE as u64,
+                data_offset: 0,
+                sectors: fs::metadata(path)?.len() / SECTOR_SIZE as u64,
+                big_endian_audio: false,
+            }],
+            "cue" => tracks_from_cue(path)?,
+            "ccd" => tracks_from_ccd(path)?,
+            "mds" => tracks_from_mds(path)?,
+            "nrg" => tracks_from_nrg(path)?,
+            "bin" => match find_sibling(path, "cue") {
+                Some(cue_path) => tracks_from_cue(&cue_path)?,
+                None => tracks_from_raw(path)?,
+            },
+            "img" => match find_sibling(path, "ccd") {
+                Some(ccd_path) => tracks_from_ccd(&ccd_path)?,
+                None => tracks_from_raw(path)?,
+            },
+            "mdf" => match find_sibling(path, "mds") {
+                Some(mds_path) => tracks_from_mds(&mds_path)?,
+                None => tracks_from_raw(path)?,
+            },
+            _ => {
+                return Err(Error::other(std::format!(
+                    "{} is not a supported disc image",
+                    path.display()
+                )))
+            }
+        };
+
+        if tracks.is_empty() {
+            return Err(Error::other(std::format!(
+                "no tracks found in {}",
+                path.display()
+            )));
+        }
+
+        for track in &tracks {
+            info!(
+                "disc_image. track {} {:?} in {:?} at {} ({} sectors of {} bytes)",
+                track.number,
+                track.kind,
+                track.file,
+                track.start_offset,
+                track.sectors,
+                track.sector_size
+            );
+        }
+
+        Ok(DiscImage { tracks })
+    }
+
+    pub fn data_track(&self) -> Option<&DiscTrack> {
+        self.tracks
+            .iter()
+            .find(|track| track.kind == TrackKind::Data)
+    }
+
+    pub fn audio_tracks(&self) -> Vec<&DiscTrack> {
+        self.tracks
+            .iter()
+            .filter(|track| track.kind == TrackKind::Audio)
+            .collect()
+    }
+
+    pub fn open_data_track(&self) -> io::Result<IsoImage<BufReader<File>>> {
+        let track = self
+            .data_track()
+            .ok_or_else(|| Error::other("the disc image has no data track"))?;
+        let file = File::open(&track.file)?;
+        IsoImage::with_layout(BufReader::new(file), track.layout())
+    }
+}
+
+// Finds "name.<extension>" next to "name.<other>", ignoring the case of the extension.
+fn find_sibling(path: &Path, extension: &str) -> Option<PathBuf> {
+    let stem = path.file_stem()?;
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty());
+    let parent = parent.unwrap_or(Path::new("."));
+
+    fs::read_dir(parent)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|candidate| {
+            candidate.file_stem() == Some(stem)
+                && candidate
+                    .extension()
+                    .and_then(|candidate_extension| candidate_extension.to_str())
+                    .is_some_and(|candidate_extension| {
+                        candidate_extension.eq_ignore_ascii_case(extension)
+                    })
+        })
+}
+
+fn resolve_track_file(descriptor: &Path, name: &str) -> PathBuf {
+    let parent = descriptor.parent().unwrap_or(Path::new(""));
+    let path = parent.join(name);
+    if path.exists() {
+        return path;
+    }
+
+    // Cue sheets made on Windows often differ in case from the actual file name.
+    fs::read_dir(if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    })
+    .ok()
+    .and_then(|entries| {
+        entries
+            .filter_map(|entry| entry.ok())
+            .find(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .eq_ignore_ascii_case(name)
+            })
+            .map(|entry| entry.path())
+    })
+    .unwrap_or(path)
+}
+
+// Finds where the user data of a data track is, by looking for the ISO9660 volume descriptor at
+// sector 16 and falling back to the mode byte of the sector header.
+fn detect_data_offset(file: &Path, start_offset: u64, sector_size: u64) -> io::Result<u64> {
+    let candidates: &[u64] = match sector_size {
+        2048 => &[0],
+        2336 => &[8, 0],
+        _ => &[16, 24, 0],
+    };
+
+    let mut reader = File::open(file)?;
+    let mut buf = [0u8; 6];
+    for &data_offset in candidates {
+        let offset = start_offset + ISO_DESCRIPTOR_SECTOR * sector_size + data_offset;
+        if reader.seek(SeekFrom::Start(offset)).is_ok()
+            && reader.read_exact(&mut buf).is_ok()
+            && &buf[1..6] == b"CD001"
+        {
+            return Ok(data_offset);
+        }
+    }
+
+    if sector_size >= RAW_SECTOR_SIZE {
+        let mut header = [0u8; 16];
+        reader.seek(SeekFrom::Start(start_offset))?;
+        if reader.read_exact(&mut header).is_ok() && header[0..12] == SYNC_PATTERN {
+            return Ok(if header[15] == 2 { 24 } else { 16 });
+        }
+    }
+
+    Ok(candidates[0])
+}
+
+// Fills in the number of sectors of each track from where the next track in the same file
+// starts, or from the size of the file.
+fn finish_tracks(mut tracks: Vec<DiscTrack>) -> io::Result<Vec<DiscTrack>> {
+    for idx in 0..tracks.len() {
+        let end_offset = match tracks[idx + 1..].iter().find(|next| {
+            next.file == tracks[idx].file && next.start_offset > tracks[idx].start_offset
+        }) {
+            Some(next) => next.start_offset,
+            None => fs::metadata(&tracks[idx].file)?.len(),
+        };
+        let track = &mut tracks[idx];
+        if track.sectors == 0 {
+            track.sectors = end_offset.saturating_sub(track.start_offset) / track.sector_size;
+        }
+        if track.kind == TrackKind::Data {
+            track.data_offset =
+                detect_data_offset(&track.file, track.start_offset, track.sector_size)?;
+        }
+    }
+    Ok(tracks)
+}
+
+fn tracks_from_cue(cue_path: &Path) -> io::Result<Vec<DiscTrack>> {
+    let cue_sheet = CueSheet::from_file(cue_path)?;
+    let mut tracks = Vec::new();
+
+    for cue_file in &cue_sheet.files {
+        if cue_file.file_type == CueFileType::Wave {
+            warn!(
+                "disc_image. skipping {}, WAVE files in cue sheets are not supported",
+                cue_file.name
+            );
+            continue;
+        }
+
+        let file = resolve_track_file(cue_path, &cue_file.name);
+        for (cue_track, start_offset) in cue_file.tracks.iter().zip(cue_file.track_offsets()) {
+            let Some(sector_size) = cue_track.track_type.sector_size() else {
+                warn!(
+                    "disc_image. skipping track {} with an unsupported type",
+                    cue_track.number
+                );
+                continue;
+            };
+            let Some(start_frames) = cue_track.start_frames() else {
+                return Err(Error::other(std::format!(
+                    "track {} has no INDEX 01",
+                    cue_track.number
+                )));
+            };
+            let start_offset = start_offset.ok_or_else(|| {
+                Error::other(std::format!(
+                    "could not find where track {} starts, a track before it has an unsupported type",
+                    cue_track.number
+                ))
+            })?;
+
+            // The previous track ends where the pregap (INDEX 00) of this one starts.
+            let first_frames = cue_track.indexes.iter().map(|index| index.frames).min();
+            let pregap_bytes =
+                u64::from(start_frames - first_frames.unwrap_or(start_frames)) * sector_size;
+            if let Some(previous) = tracks
+                .last_mut()
+                .filter(|previous: &&mut DiscTrack| previous.file == file)
+            {
+                previous.sectors = (start_offset - pregap_bytes)
+                    .saturating_sub(previous.start_offset)
+                    / previous.sector_size;
+            }
+
+            tracks.push(DiscTrack {
+                number: cue_track.number,
+                kind: if cue_track.track_type == CueTrackType::Audio {
+                    TrackKind::Audio
+                } else {
+                    TrackKind::Data
+                },
+                file: file.clone(),
+                start_offset,
+                sector_size,
+                data_offset: 0,
+                sectors: 0,
+                big_endian_audio: cue_file.file_type == CueFileType::Motorola,
+            });
+        }
+    }
+
+    finish_tracks(tracks)
+}
+
+// CloneCD images keep raw 2352 byte sectors in the .img file, the .ccd file lists the tracks.
+fn tracks_from_ccd(ccd_path: &Path) -> io::Result<Vec<DiscTrack>> {
+    let ccd = fs::read(ccd_path)?;
+    let ccd = String::from_utf8_lossy(&ccd);
+    let img_path = find_sibling(ccd_path, "img").ok_or_else(|| {
+        Error::other(std::format!(
+            "no .img file found for {}",
+            ccd_path.display()
+        ))
+    })?;
+
+    let mut tracks = Vec::new();
+    let mut current: Option<(u32, Option<u32>, Option<u64>)> = None;
+
+    let mut push_track = |current: Option<(u32, Option<u32>, Option<u64>)>| {
+        if let Some((number, Some(mode), Some(lba))) = current {
+            tracks.push(DiscTrack {
+                number,
+                kind: if mode == 0 {
+                    TrackKind::Audio
+                } else {
+                    TrackKind::Data
+                },
+                file: img_path.clone(),
+                start_offset: lba * RAW_SECTOR_SIZE,
+                sector_size: RAW_SECTOR_SIZE,
+                data_offset: 0,
+                sectors: 0,
+                big_endian_audio: false,
+            });
+        }
+    };
+
+    for line in ccd.lines().map(|line| line.trim()) {
+        if let Some(section) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            push_track(current.take());
+            current = section
+                .strip_prefix("TRACK ")
+                .and_then(|number| number.trim().parse::<u32>().ok())
+                .map(|number| (number, None, None));
+            continue;
+        }
+
+        let Some((_, mode, lba)) = current.as_mut() else {
+            continue;
+        };
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        match key.trim() {
+            "MODE" => *mode = value.trim().parse::<u32>().ok(),
+            "INDEX 1" => *lba = value.trim().parse::<u64>().ok(),
+            _ => {}
+        }
+    }
+    push_track(current.take());
+
+    finish_tracks(tracks)
+}
+
+fn read_u16_le(data: &[u8], offset: usize) -> io::Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| Error::other("descriptor file is truncated"))
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> io::Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| Error::other("descriptor file is truncated"))
+}
+
+fn read_u64_le(data: &[u8], offset: usize) -> io::Result<u64> {
+    data.get(offset..offset + 8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| Error::other("descriptor file is truncated"))
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> io::Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| Error::other("image footer is truncated"))
+}
+
+fn read_u64_be(data: &[u8], offset: usize) -> io::Result<u64> {
+    data.get(offset..offset + 8)
+        .map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| Error::other("image footer is truncated"))
+}
+
+// Alcohol 120% images: the .mds file describes sessions and tracks of the .mdf file.
+fn tracks_from_mds(mds_path: &Path) -> io::Result<Vec<DiscTrack>> {
+    let mds = fs::read(mds_path)?;
+    if !mds.starts_with(MDS_SIGNATURE) {
+        return Err(Error::other(std::format!(
+            "{} is not a media descriptor file",
+            mds_path.display()
+        )));
+    }
+
+    let session_count = read_u16_le(&mds, 0x14)? as usize;
+    let sessions_offset = read_u32_le(&mds, 0x50)? as usize;
+    let mut tracks = Vec::new();
+
+    for session in 0..session_count {
+        let session_offset = sessions_offset + session * MDS_SESSION_BLOCK_SIZE;
+        let block_count =
+            *mds.get(session_offset + 10)
+                .ok_or_else(|| Error::other("descriptor file is truncated"))? as usize;
+        let blocks_offset = read_u32_le(&mds, session_offset + 20)? as usize;
+
+        for block in 0..block_count {
+            let block_offset = blocks_offset + block * MDS_TRACK_BLOCK_SIZE;
+            let block_data = mds
+                .get(block_offset..block_offset + MDS_TRACK_BLOCK_SIZE)
+                .ok_or_else(|| Error::other("descriptor file is truncated"))?;
+
+            // Points from 0xA0 up describe the disc, not a track.
+            let point = block_data[4];
+            if point == 0 || point >= 0xA0 {
+                continue;
+            }
+
+            let mode = block_data[0] & 0x0F;
+            let sector_size = u64::from(read_u16_le(block_data, 0x10)?);
+            let start_offset = read_u64_le(block_data, 0x28)?;
+            let footer_offset = read_u32_le(block_data, 0x34)? as usize;
+
+            let file_name = read_mds_file_name(&mds, footer_offset)?;
+            let file_name = match file_name.strip_prefix('*') {
+                Some(extension) => std::format!(
+                    "{}{}",
+                    mds_path.file_stem().unwrap_or_default().to_string_lossy(),
+                    extension
+                ),
+                None => file_name,
+            };
+
+            tracks.push(DiscTrack {
+                number: u32::from(point),
+                kind: if mode == 0x09 {
+                    TrackKind::Audio
+                } else {
+                    TrackKind::Data
+                },
+                file: resolve_track_file(mds_path, &file_name),
+                start_offset,
+                sector_size,
+                data_offset: 0,
+                sectors: 0,
+                big_endian_audio: false,
+            });
+        }
+    }
+
+    tracks.sort_by_key(|track| track.number);
+    finish_tracks(tracks)
+}
+
+fn read_mds_file_name(mds: &[u8], footer_offset: usize) -> io::Result<String> {
+    let name_offset = read_u32_le(mds, footer_offset)? as usize;
+    let wide = read_u32_le(mds, footer_offset + 4)? != 0;
+    let name_data = mds
+        .get(name_offset..)
+        .ok_or_else(|| Error::other("descriptor file is truncated"))?;
+
+    if wide {
+        let utf16: Vec<u16> = name_data
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .take_while(|c| *c != 0)
+            .collect();
+        Ok(String::from_utf16_lossy(&utf16))
+    } else {
+        let end = name_data
+            .iter()
+            .position(|c| *c == 0)
+            .unwrap_or(name_data.len());
+        Ok(String::from_utf8_lossy(&name_data[..end]).to_string())
+    }
+}
+
+// Nero images keep the track list in chunks at the end of the file, found through the footer.
+fn tracks_from_nrg(nrg_path: &Path) -> io::Result<Vec<DiscTrack>> {
+    let mut file = File::open(nrg_path)?;
+    let file_length = file.metadata()?.len();
+
+    let mut footer = [0u8; 12];
+    file.seek(SeekFrom::Start(file_length.saturating_sub(12)))?;
+    file.read_exact(&mut footer)?;
+
+    let chunks_offset = if &footer[0..4] == b"NER5" {
+        read_u64_be(&footer, 4)?
+    } else if &footer[4..8] == b"NERO" {
+        u64::from(read_u32_be(&footer, 8)?)
+    } else {
+        return Err(Error::other(std::format!(
+            "{} is not a Nero image",
+            nrg_path.display()
+        )));
+    };
+
+    let mut chunks = Vec::new();
+    file.seek(SeekFrom::Start(chunks_offset))?;
+    file.take(file_length.saturating_sub(chunks_offset))
+        .read_to_end(&mut chunks)?;
+
+    let mut tracks = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= chunks.len() {
+        let chunk_id = &chunks[pos..pos + 4];
+        let chunk_length = read_u32_be(&chunks, pos + 4)? as usize;
+        let chunk_data = chunks
+            .get(pos + 8..pos + 8 + chunk_length)
+            .ok_or_else(|| Error::other("Nero chunk is truncated"))?;
+
+        match chunk_id {
+            b"DAOX" | b"DAOI" => {
+                let wide = chunk_id == b"DAOX";
+                let entry_size = if wide { 42 } else { 30 };
+                let first_track = u32::from(*chunk_data.get(20).unwrap_or(&1));
+                for (idx, entry) in chunk_data
+                    .get(22..)
+                    .unwrap_or_default()
+                    .chunks_exact(entry_size)
+                    .enumerate()
+                {
+                    let sector_size = u64::from(u16::from_be_bytes([entry[12], entry[13]]));
+                    let mode = entry[14];
+                    let (start_offset, end_offset) = if wide {
+                        (read_u64_be(entry, 26)?, read_u64_be(entry, 34)?)
+                    } else {
+                        (
+                            u64::from(read_u32_be(entry, 22)?),
+                            u64::from(read_u32_be(entry, 26)?),
+                        )
+                    };
+                    tracks.push(nrg_track(
+                        nrg_path,
+                        first_track + idx as u32,
+                        mode,
+                        sector_size,
+                        start_offset,
+                        end_offset,
+                    ));
+                }
+            }
+            b"ETN2" | b"ETNF" => {
+                let wide = chunk_id == b"ETN2";
+                let entry_size = if wide { 32 } else { 20 };
+                for entry in chunk_data.chunks_exact(entry_size) {
+                    let (start_offset, length, mode) = if wide {
+                        (read_u64_be(entry, 0)?, read_u64_be(entry, 8)?, entry[19])
+                    } else {
+                        (
+                            u64::from(read_u32_be(entry, 0)?),
+                            u64::from(read_u32_be(entry, 4)?),
+                            entry[11],
+                        )
+                    };
+                    let sector_size = match mode {
+                        0x00 | 0x02 => 2048,
+                        0x03 => 2336,
+                        _ => RAW_SECTOR_SIZE,
+                    };
+                    let number = tracks.len() as u32 + 1;
+                    tracks.push(nrg_track(
+                        nrg_path,
+                        number,
+                        mode,
+                        sector_size,
+                        start_offset,
+                        start_offset + length,
+                    ));
+                }
+            }
+            b"END!" => break,
+            _ => {}
+        }
+
+        pos += 8 + chunk_length;
+    }
+
+    finish_tracks(tracks)
+}
+
+fn nrg_track(
+    nrg_path: &Path,
+    number: u32,
+    mode: u8,
+    sector_size: u64,
+    start_offset: u64,
+    end_offset: u64,
+) -> DiscTrack {
+    DiscTrack {
+        number,
+        // 0x07 is audio, 0x10 audio with subchannel data.
+        kind: if mode == 0x07 || mode == 0x10 {
+            TrackKind::Audio
+        } else {
+            TrackKind::Data
+        },
+        file: nrg_path.to_path_buf(),
+        start_offset,
+        sector_size,
+        data_offset: 0,
+        sectors: end_offset.saturating_sub(start_offset) / sector_size.max(1),
+        big_endian_audio: false,
+    }
+}
+
+// A .bin, .img or .mdf without its descriptor file, assumed to hold a single data track.
+fn tracks_from_raw(path: &Path) -> io::Result<Vec<DiscTrack>> {
+    let file_length = fs::metadata(path)?.len();
+    let mut header = [0u8; 12];
+    File::open(path)?.read_exact(&mut header)?;
+
+    let sector_size = if header == SYNC_PATTERN {
+        if file_length % SUBCHANNEL_SECTOR_SIZE == 0 && file_length % RAW_SECTOR_SIZE != 0 {
+            SUBCHANNEL_SECTOR_SIZE
+        } else {
+            RAW_SECTOR_SIZE
+        }
+    } else {
+        SECTOR_SIZE as u64
+    };
+
+    finish_tracks(vec![DiscTrack {
+        number: 1,
+        kind: TrackKind::Data,
+        file: path.to_path_buf(),
+        start_offset: 0,
+        sector_size,
+        data_offset: 0,
+        sectors: 0,
+        big_endian_audio: false,
+    }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::FixtureDir;
+
+    // Cooked 2048 byte sectors with an ISO9660 volume descriptor at sector 16.
+    fn cooked_data_track(sectors: usize) -> Vec<u8> {
+        let mut data = vec![0u8; sectors * SECTOR_SIZE];
+        let descriptor = ISO_DESCRIPTOR_SECTOR as usize * SECTOR_SIZE;
+        data[descriptor..descriptor + 6].copy_from_slice(b"\x01CD001");
+        data
+    }
+
+    // Raw 2352 byte sectors with sync pattern and mode byte, ISO9660 descriptor at sector 16.
+    fn raw_data_track(sectors: usize, mode: u8) -> Vec<u8> {
+        let data_offset = if mode == 2 { 24 } else { 16 };
+        let mut data = Vec::new();
+        for lba in 0..sectors {
+            let mut sector = vec![0u8; RAW_SECTOR_SIZE as usize];
+            sector[0..12].copy_from_slice(&SYNC_PATTERN);
+            sector[15] = mode;
+            if lba == ISO_DESCRIPTOR_SECTOR as usize {
+                sector[data_offset..data_offset + 6].copy_from_slice(b"\x01CD001");
+            }
+            data.extend(sector);
+        }
+        data
+    }
+
+    fn audio_track(sectors: usize, sample: u8) -> Vec<u8> {
+        vec![sample; sectors * RAW_SECTOR_SIZE as usize]
+    }
+
+    fn track_summary(image: &DiscImage) -> Vec<(u32, TrackKind, u64, u64, u64, u64)> {
+        image
+            .tracks
+            .iter()
+            .map(|track| {
+                (
+                    track.number,
+                    track.kind,
+                    track.start_offset,
+                    track.sector_size,
+                    track.data_offset,
+                    track.sectors,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cue_with_mixed_sector_sizes() {
+        let fixture = FixtureDir::new("disc", "cue");
+        let mut bin = cooked_data_track(20);
+        bin.extend(audio_track(2, 0)); // pregap of track 2
+        bin.extend(audio_track(3, 1));
+        fixture.write("Game.bin", &bin);
+        fixture.write(
+            "Game.cue",
+            b"FILE \"GAME.BIN\" BINARY\n  TRACK 01 MODE1/2048\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    INDEX 00 00:00:20\n    INDEX 01 00:00:22\n",
+        );
+
+        // Opening the bin finds the cue next to it, the FILE name differs in case.
+        let image = DiscImage::open(&fixture.root.join("Game.bin")).unwrap();
+        assert_eq!(
+            track_summary(&image),
+            vec![
+                (1, TrackKind::Data, 0, 2048, 0, 20),
+                (2, TrackKind::Audio, 20 * 2048 + 2 * 2352, 2352, 0, 3),
+            ]
+        );
+        assert_eq!(image.audio_tracks().len(), 1);
+        assert_eq!(image.data_track().map(|track| track.number), Some(1));
+    }
+
+    #[test]
+    fn ccd_tracks() {
+        let fixture = FixtureDir::new("disc", "ccd");
+        let mut img = raw_data_track(18, 1);
+        img.extend(audio_track(2, 1));
+        fixture.write("game.img", &img);
+        let ccd_path = fixture.write(
+            "game.ccd",
+            b"[CloneCD]\nVersion=3\n[TRACK 1]\nMODE=1\nINDEX 1=0\n[TRACK 2]\nMODE=0\nINDEX 0=16\nINDEX 1=18\n",
+        );
+
+        let image = DiscImage::open(&ccd_path).unwrap();
+        assert_eq!(
+            track_summary(&image),
+            vec![
+                (1, TrackKind::Data, 0, 2352, 16, 18),
+                (2, TrackKind::Audio, 18 * 2352, 2352, 0, 2),
+            ]
+        );
+        assert_eq!(image.data_track().map(|track| track.number), Some(1));
+    }
+
+    fn mds_track_block(mode: u8, point: u8, sector_size: u16, start: u64, footer: u32) -> Vec<u8> {
+        let mut block = vec![0u8; MDS_TRACK_BLOCK_SIZE];
+        block[0] = mode;
+        block[4] = point;
+        block[0x10..0x12].copy_from_slice(&sector_size.to_le_bytes());
+        block[0x28..0x30].copy_from_slice(&start.to_le_bytes());
+        block[0x34..0x38].copy_from_slice(&footer.to_le_bytes());
+        block
+    }
+
+    #[test]
+    fn mds_tracks() {
+        let fixture = FixtureDir::new("disc", "mds");
+        let mut mdf = raw_data_track(17, 2);
+        mdf.extend(audio_track(4, 1));
+        fixture.write("game.mdf", &mdf);
+
+        let sessions_offset = 0x58;
+        let blocks_offset = sessions_offset + MDS_SESSION_BLOCK_SIZE;
+        let footer_offset = blocks_offset + 3 * MDS_TRACK_BLOCK_SIZE;
+        let name_offset = footer_offset + 16;
+
+        let mut mds = vec![0u8; sessions_offset];
+        mds[0..16].copy_from_slice(MDS_SIGNATURE);
+        mds[0x14..0x16].copy_from_slice(&1u16.to_le_bytes());
+        mds[0x50..0x54].copy_from_slice(&(sessions_offset as u32).to_le_bytes());
+
+        let mut session = vec![0u8; MDS_SESSION_BLOCK_SIZE];
+        session[10] = 3;
+        session[20..24].copy_from_slice(&(blocks_offset as u32).to_le_bytes());
+        mds.extend(session);
+
+        let footer = footer_offset as u32;
+        mds.extend(mds_track_block(0x0A, 0xA0, 0, 0, footer)); // lead-in, not a track
+        mds.extend(mds_track_block(0x0A, 1, 2352, 0, footer));
+        mds.extend(mds_track_block(0x09, 2, 2352, 17 * 2352, footer));
+
+        let mut footer = vec![0u8; 16];
+        footer[0..4].copy_from_slice(&(name_offset as u32).to_le_bytes());
+        mds.extend(footer);
+        mds.extend(b"*.mdf\0");
+        let mds_path = fixture.write("game.mds", &mds);
+
+        let image = DiscImage::open(&mds_path).unwrap();
+        assert_eq!(
+            track_summary(&image),
+            vec![
+                (1, TrackKind::Data, 0, 2352, 24, 17),
+                (2, TrackKind::Audio, 17 * 2352, 2352, 0, 4),
+            ]
+        );
+        assert_eq!(image.tracks[0].file, fixture.root.join("game.mdf"));
+    }
+
+    #[test]
+    fn nrg_tracks() {
+        let fixture = FixtureDir::new("disc", "nrg");
+        let mut nrg = cooked_data_track(17);
+        let audio_start = nrg.len() as u64;
+        nrg.extend(audio_track(2, 1));
+        let audio_end = nrg.len() as u64;
+        let chunks_offset = nrg.len() as u64;
+
+        let mut daox = vec![0u8; 22];
+        daox[20] = 1; // first track
+        for (mode, sector_size, start, end) in [
+            (0x00u8, 2048u16, 0, audio_start),
+            (0x07, 2352, audio_start, audio_end),
+        ] {
+            let mut entry = vec![0u8; 42];
+            entry[12..14].copy_from_slice(&sector_size.to_be_bytes());
+            entry[14] = mode;
+            entry[26..34].copy_from_slice(&start.to_be_bytes());
+            entry[34..42].copy_from_slice(&end.to_be_bytes());
+            daox.extend(entry);
+        }
+        nrg.extend(b"DAOX");
+        nrg.extend((daox.len() as u32).to_be_bytes());
+        nrg.extend(daox);
+        nrg.extend(b"END!\0\0\0\0");
+        nrg.extend(b"NER5");
+        nrg.extend(chunks_offset.to_be_bytes());
+        let nrg_path = fixture.write("game.nrg", &nrg);
+
+        let image = DiscImage::open(&nrg_path).unwrap();
+        assert_eq!(
+            track_summary(&image),
+            vec![
+                (1, TrackKind::Data, 0, 2048, 0, 17),
+                (2, TrackKind::Audio, audio_start, 2352, 0, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn raw_sector_detection() {
+        let fixture = FixtureDir::new("disc", "raw");
+
+        let raw_path = fixture.write("raw.bin", raw_data_track(17, 1));
+        let cooked_path = fixture.write("cooked.img", cooked_data_track(17));
+        // 2448 byte sectors carry 96 bytes of subchannel data after each raw sector.
+        let mut subchannel = Vec::new();
+        for sector in raw_data_track(17, 1).chunks(RAW_SECTOR_SIZE as usize) {
+            subchannel.extend(sector);
+            subchannel.extend([0u8; 96]);
+        }
+        let subchannel_path = fixture.write("subchannel.mdf", &subchannel);
+
+        let raw = DiscImage::open(&raw_path).unwrap();
+        assert_eq!(
+            track_summary(&raw),
+            vec![(1, TrackKind::Data, 0, 2352, 16, 17)]
+        );
+        let cooked = DiscImage::open(&cooked_path).unwrap();
+        assert_eq!(
+            track_summary(&cooked),
+            vec![(1, TrackKind::Data, 0, 2048, 0, 17)]
+        );
+        let subchannel = DiscImage::open(&subchannel_path).unwrap();
+        assert_eq!(
+            track_summary(&subchannel),
+            vec![(1, TrackKind::Data, 0, 2448, 16, 17)]
+        );
+    }
+
+    #[test]
+    fn wav_header_and_byte_order() {
+        let fixture = FixtureDir::new("disc", "wav");
+        let mut audio = Vec::new();
+        for _ in 0..2 * RAW_SECTOR_SIZE / 2 {
+            audio.extend([0x12u8, 0x34]);
+        }
+        let file = fixture.write("audio.bin", &audio);
+        let track = DiscTrack {
+            number: 2,
+            kind: TrackKind::Audio,
+            file,
+            start_offset: 0,
+            sector_size: RAW_SECTOR_SIZE,
+            data_offset: 0,
+            sectors: 2,
+            big_endian_audio: true,
+        };
+
+        let wav_path = fixture.root.join("track02.wav");
+        track.write_wav(&wav_path).unwrap();
+        let wav = fs::read(&wav_path).unwrap();
+
+        let data_length = 2 * RAW_SECTOR_SIZE as u32;
+        assert_eq!(wav.len(), 44 + data_length as usize);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(wav[4..8], (data_length + 36).to_le_bytes());
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(wav[22..24], 2u16.to_le_bytes());
+        assert_eq!(wav[24..28], 44100u32.to_le_bytes());
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav[40..44], data_length.to_le_bytes());
+        // Motorola byte order is swapped to the little endian samples of WAV.
+        assert_eq!(wav[44..46], [0x34, 0x12]);
+
+        let data_track = DiscTrack {
+            kind: TrackKind::Data,
+            ..track
+        };
+        assert!(data_track.write_wav(&wav_path).is_err());
+    }
+}
@@ -0,0 +1,466 @@
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+use crate::client;
+use crate::package;
+
+pub static LUX_REPORT_FILE: &str = "LUX_REPORT_FILE";
+pub static LUX_REPORT_VERSION: &str = "LUX_REPORT_VERSION";
+pub const REPORT_VERSION: u32 = 1;
+
+static REPORT_FILE: &str = "engine_report.json";
+static LEGACY_ERROR_FILE: &str = "last_error.txt";
+
+/// The engine failed. The message comes from the report file, or from last_error.txt in the
+/// directory the engine ran in for engines written before the report file existed.
+pub const EXIT_CODE_ERROR: i32 = 10;
+/// The engine exited normally and left a report with warnings, notices or requests.
+pub const EXIT_CODE_REPORT: i32 = 11;
+/// The setup of the game has to run again, for example because files it created are missing.
+pub const EXIT_CODE_RERUN_SETUP: i32 = 12;
+
+// What an engine wrapper wrote to the file named by LUX_REPORT_FILE before exiting.
+#[derive(Default, Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct EngineReport {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+    pub requests: Vec<EngineRequest>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EngineRequest {
+    RerunSetup,
+    ShowNotice {
+        #[serde(default)]
+        title: Option<String>,
+        message: String,
+    },
+}
+
+/// The outcome of an engine run: an error to show, or prompts to show before the client closes.
+pub enum EngineOutcome {
+    Completed(Vec<client::PromptRequestData>),
+    Failed(String),
+}
+
+/// Location of the report file for a game, passed to the engine in LUX_REPORT_FILE.
+pub fn report_path(app_id: &str) -> io::Result<PathBuf> {
+    package::place_state_file(&std::format!("{}/{}", app_id, REPORT_FILE))
+}
+
+/// Removes the report of an earlier run, so an old report is never mistaken for a new one.
+pub fn clear_report(report_path: &Path) {
+    if report_path.exists() {
+        if let Err(err) = fs::remove_file(report_path) {
+            error!(
+                "engine_report. could not remove {:?}: {:?}",
+                report_path, err
+            );
+        }
+    }
+}
+
+fn read_report(report_path: &Path) -> io::Result<Option<EngineReport>> {
+    if !report_path.exists() {
+        return Ok(None);
+    }
+
+    let report_str = fs::read_to_string(report_path)?;
+    let report = serde_json::from_str::<EngineReport>(&report_str).map_err(|err| {
+        Error::other(std::format!(
+            "The engine wrote a report that could not be read: {}",
+            err
+        ))
+    })?;
+    Ok(Some(report))
+}
+
+fn question_prompt(title: &str, label: &str, prompt_id: String) -> client::PromptRequestData {
+    client::PromptRequestData {
+        label: Some(label.to_string()),
+        prompt_type: "question".to_string(),
+        title: title.to_string(),
+        prompt_id,
+        rich_text: None,
+        default_value: None,
+        dialog: None,
+    }
+}
+
+/// Reads the exit status and report of an engine run and decides what the user is shown.
+pub fn process_engine_exit(
+    app_id: &str,
+    status: ExitStatus,
+    report_path: &Path,
+    working_dir: &Path,
+    last_lines: &[String],
+) -> EngineOutcome {
+    let exit_code = status.code();
+    let report = match read_report(report_path) {
+        Ok(report) => report,
+        Err(err) => {
+            error!("engine_report. read err: {:?}", err);
+            return EngineOutcome::Failed(err.to_string());
+        }
+    };
+    match &report {
+        Some(report) => info!("engine_report. exit code {:?} with {:?}", exit_code, report),
+        None if exit_code == Some(EXIT_CODE_REPORT) => {
+            error!(
+                "engine_report. exit code {} without a report file",
+                EXIT_CODE_REPORT
+            )
+        }
+        None => {}
+    }
+    let mut report = report.unwrap_or_default();
+
+    if exit_code == Some(EXIT_CODE_ERROR) && report.errors.is_empty() {
+        match fs::read_to_string(working_dir.join(LEGACY_ERROR_FILE)) {
+            Ok(s) => report.errors.push(s),
+            Err(err) => error!("engine_report. legacy error read err: {:?}", err),
+        }
+    }
+
+    let rerun_setup =
+        reset_setup_if_requested(app_id, &report, exit_code == Some(EXIT_CODE_RERUN_SETUP));
+
+    if !report.errors.is_empty() {
+        let mut message = std::format!("Error on run: {}", report.errors.join("\n"));
+        if rerun_setup {
+            message.push_str("\nSetup runs again on the next launch.");
+        }
+        if !last_lines.is_empty() {
+            message.push_str("\nLast output:\n");
+            message.push_str(&last_lines.join("\n"));
+        }
+        return EngineOutcome::Failed(message);
+    }
+
+    EngineOutcome::Completed(report_prompts(&report, rerun_setup))
+}
+
+/// Reads a report the client did not see because it closed when the engine launched, and
+/// returns the prompts to show on this launch. The report is removed once read.
+pub fn take_pending_report(app_id: &str, report_path: &Path) -> Vec<client::PromptRequestData> {
+    let report = read_report(report_path);
+    clear_report(report_path);
+
+    let report = match report {
+        Ok(Some(report)) => report,
+        Ok(None) => return Vec::new(),
+        Err(err) => {
+            error!("engine_report. pending read err: {:?}", err);
+            return vec![question_prompt(
+                "Engine Error",
+                &err.to_string(),
+                "enginepending".to_string(),
+            )];
+        }
+    };
+    info!("engine_report. pending report {:?}", report);
+
+    // The exit code of that run is not known, only what the report asks for.
+    let rerun_setup = reset_setup_if_requested(app_id, &report, false);
+
+    let mut prompts = Vec::new();
+    if !report.errors.is_empty() {
+        prompts.push(question_prompt(
+            "Engine Error",
+            &std::format!("Error on the last run: {}", report.errors.join("\n")),
+            "engineerror".to_string(),
+        ));
+    }
+    prompts.extend(report_prompts(&report, rerun_setup));
+    prompts
+}
+
+fn reset_setup_if_requested(
+    app_id: &str,
+    report: &EngineReport,
+    exit_code_requested: bool,
+) -> bool {
+    let rerun_setup = exit_code_requested
+        || report
+            .requests
+            .iter()
+            .any(|request| matches!(request, EngineRequest::RerunSetup));
+    if rerun_setup {
+        info!("engine_report. engine requested setup to run again");
+        if let Err(err) = package::reset_setup(app_id) {
+            error!("engine_report. reset_setup err: {:?}", err);
+        }
+    }
+    rerun_setup
+}
+
+fn report_prompts(report: &EngineReport, rerun_setup: bool) -> Vec<client::PromptRequestData> {
+    let mut prompts = Vec::new();
+    for (idx, warning) in report.warnings.iter().enumerate() {
+        prompts.push(question_prompt(
+            "Engine Warning",
+            warning,
+            std::format!("enginewarning{}", idx),
+        ));
+    }
+    for (idx, request) in report.requests.iter().enumerate() {
+        if let EngineRequest::ShowNotice { title, message } = request {
+            prompts.push(question_prompt(
+                title.as_deref().unwrap_or("Engine Notice"),
+                message,
+                std::format!("enginenotice{}", idx),
+            ));
+        }
+    }
+    if rerun_setup {
+        prompts.push(question_prompt(
+            "Setup",
+            "The engine asked for the setup of this game to run again. It runs on the next launch.",
+            "enginererunsetup".to_string(),
+        ));
+    }
+    prompts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::FixtureDir;
+    use std::env;
+    use std::os::unix::process::ExitStatusExt;
+
+    // A fixture directory used as the working directory of the engine and for its report.
+    struct FixtureEngine {
+        dir: FixtureDir,
+    }
+
+    impl FixtureEngine {
+        fn new(name: &str) -> FixtureEngine {
+            FixtureEngine {
+                dir: FixtureDir::new("engine-report", name),
+            }
+        }
+
+        fn report_path(&self) -> PathBuf {
+            self.dir.root.join(REPORT_FILE)
+        }
+
+        fn write_report(&self, report: &str) {
+            self.dir.write(REPORT_FILE, report);
+        }
+
+        fn run(&self, exit_code: i32) -> EngineOutcome {
+            process_engine_exit(
+                "0",
+                ExitStatus::from_raw(exit_code << 8),
+                &self.report_path(),
+                &self.dir.root,
+                &["engine: last line".to_string()],
+            )
+        }
+    }
+
+    fn prompts(outcome: EngineOutcome) -> Vec<(String, String, String)> {
+        match outcome {
+            EngineOutcome::Completed(prompts) => prompts
+                .into_iter()
+                .map(|prompt| (prompt.title, prompt.label.unwrap(), prompt.prompt_id))
+                .collect(),
+            EngineOutcome::Failed(message) => panic!("unexpected failure: {}", message),
+        }
+    }
+
+    fn failure(outcome: EngineOutcome) -> String {
+        match outcome {
+            EngineOutcome::Failed(message) => message,
+            EngineOutcome::Completed(_) => panic!("expected a failure"),
+        }
+    }
+
+    #[test]
+    fn normal_exit_without_report() {
+        let fixture = FixtureEngine::new("normal");
+        assert!(prompts(fixture.run(0)).is_empty());
+        // An error exit code without any message keeps the old behaviour of closing normally.
+        assert!(prompts(fixture.run(EXIT_CODE_ERROR)).is_empty());
+    }
+
+    #[test]
+    fn errors_from_report() {
+        let fixture = FixtureEngine::new("errors");
+        fixture.write_report(r#"{"errors": ["Game data is missing.", "Reinstall the game."]}"#);
+        // Errors in the report are shown whatever the exit code is.
+        assert_eq!(
+            failure(fixture.run(1)),
+            "Error on run: Game data is missing.\nReinstall the game.\nLast output:\nengine: last line"
+        );
+    }
+
+    #[test]
+    fn legacy_error_file_in_working_dir() {
+        let fixture = FixtureEngine::new("legacy");
+        fixture.dir.write(LEGACY_ERROR_FILE, "No game data found");
+        assert_eq!(
+            failure(fixture.run(EXIT_CODE_ERROR)),
+            "Error on run: No game data found\nLast output:\nengine: last line"
+        );
+        // Only read for the error exit code.
+        assert!(prompts(fixture.run(0)).is_empty());
+    }
+
+    #[test]
+    fn warnings_and_notices() {
+        let fixture = FixtureEngine::new("warnings");
+        fixture.write_report(
+            r#"{
+                "warnings": ["No MIDI device was found."],
+                "requests": [
+                    {"type": "show_notice", "title": "Saves", "message": "Saves were moved."},
+                    {"type": "show_notice", "message": "Thanks for playing."}
+                ]
+            }"#,
+        );
+        assert_eq!(
+            prompts(fixture.run(EXIT_CODE_REPORT)),
+            vec![
+                (
+                    "Engine Warning".to_string(),
+                    "No MIDI device was found.".to_string(),
+                    "enginewarning0".to_string()
+                ),
+                (
+                    "Saves".to_string(),
+                    "Saves were moved.".to_string(),
+                    "enginenotice0".to_string()
+                ),
+                (
+                    "Engine Notice".to_string(),
+                    "Thanks for playing.".to_string(),
+                    "enginenotice1".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn report_exit_code_without_report() {
+        let fixture = FixtureEngine::new("missing");
+        assert!(prompts(fixture.run(EXIT_CODE_REPORT)).is_empty());
+    }
+
+    #[test]
+    fn malformed_report() {
+        let fixture = FixtureEngine::new("malformed");
+        fixture.write_report(r#"{"errors": "not a list"}"#);
+        assert!(failure(fixture.run(EXIT_CODE_REPORT))
+            .starts_with("The engine wrote a report that could not be read:"));
+
+        fixture.write_report(r#"{"requests": [{"type": "format_disk"}]}"#);
+        assert!(failure(fixture.run(0))
+            .starts_with("The engine wrote a report that could not be read:"));
+    }
+
+    #[test]
+    fn rerun_setup() {
+        let fixture = FixtureEngine::new("rerun");
+        env::set_var("XDG_CONFIG_HOME", fixture.dir.root.join("config"));
+        let app_id = "engine-report-test";
+
+        let rerun_setup = |exit_code: i32| {
+            package::SetupState {
+                setup_hash: "hash".to_string(),
+                completed_at: Some(1),
+                ..Default::default()
+            }
+            .save(app_id)
+            .unwrap();
+
+            let outcome = process_engine_exit(
+                app_id,
+                ExitStatus::from_raw(exit_code << 8),
+                &fixture.report_path(),
+                &fixture.dir.root,
+                &[],
+            );
+            let setup_state = package::SetupState::from_file(app_id).unwrap();
+            assert_eq!(setup_state.completed_at, None);
+            assert!(setup_state.setup_hash.is_empty());
+            prompts(outcome)
+        };
+
+        let expected = vec![(
+            "Setup".to_string(),
+            "The engine asked for the setup of this game to run again. It runs on the next launch."
+                .to_string(),
+            "enginererunsetup".to_string(),
+        )];
+        assert_eq!(rerun_setup(EXIT_CODE_RERUN_SETUP), expected);
+
+        fixture.write_report(r#"{"requests": [{"type": "rerun_setup"}]}"#);
+        assert_eq!(rerun_setup(0), expected);
+
+        // A report left after the client closed on launch resets the setup on the next launch.
+        fixture.write_report(r#"{"requests": [{"type": "rerun_setup"}]}"#);
+        let pending = take_pending_report(app_id, &fixture.report_path());
+        let setup_state = package::SetupState::from_file(app_id).unwrap();
+        assert_eq!(setup_state.completed_at, None);
+        assert_eq!(prompts(EngineOutcome::Completed(pending)), expected);
+    }
+
+    #[test]
+    fn pending_report() {
+        let fixture = FixtureEngine::new("pending");
+        assert!(take_pending_report("0", &fixture.report_path()).is_empty());
+
+        fixture.write_report(
+            r#"{
+                "errors": ["Game data is missing."],
+                "warnings": ["No MIDI device was found."],
+                "requests": [{"type": "show_notice", "message": "Saves were moved."}]
+            }"#,
+        );
+        assert_eq!(
+            prompts(EngineOutcome::Completed(take_pending_report(
+                "0",
+                &fixture.report_path()
+            ))),
+            vec![
+                (
+                    "Engine Error".to_string(),
+                    "Error on the last run: Game data is missing.".to_string(),
+                    "engineerror".to_string()
+                ),
+                (
+                    "Engine Warning".to_string(),
+                    "No MIDI device was found.".to_string(),
+                    "enginewarning0".to_string()
+                ),
+                (
+                    "Engine Notice".to_string(),
+                    "Saves were moved.".to_string(),
+                    "enginenotice0".to_string()
+                ),
+            ]
+        );
+        // The report is only shown once.
+        assert!(!fixture.report_path().exists());
+        assert!(take_pending_report("0", &fixture.report_path()).is_empty());
+
+        fixture.write_report("not json");
+        let pending = take_pending_report("0", &fixture.report_path());
+        assert_eq!(pending.len(), 1);
+        assert!(pending[0]
+            .label
+            .as_deref()
+            .unwrap()
+            .starts_with("The engine wrote a report that could not be read:"));
+        assert!(!fixture.report_path().exists());
+    }
+}
@@ -0,0 +1,541 @@
+use log::{info, warn};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Error, Read, Seek};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use walkdir::WalkDir;
+
+static MS_CAB_SIGNATURE: &[u8] = b"MSCF";
+static INSTALLSHIELD_CAB_SIGNATURE: &[u8] = b"ISc(";
+static OLE_SIGNATURE: &[u8] = &[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+static INNO_SETUP_MARKER: &[u8] = b"Inno Setup Setup Data";
+static TEMP_DIR_NAME: &str = ".luxtorpeda_installer_extract";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InstallerType {
+    InnoSetup,
+    InstallShield,
+    MsCab,
+    Msi,
+}
+
+impl InstallerType {
+    pub fn from_name(name: &str) -> io::Result<InstallerType> {
+        match name {
+            "inno" | "innosetup" => Ok(InstallerType::InnoSetup),
+            "installshield" => Ok(InstallerType::InstallShield),
+            "cab" => Ok(InstallerType::MsCab),
+            "msi" => Ok(InstallerType::Msi),
+            _ => Err(Error::other(std::format!(
+                "unknown installer type {}, expected inno, installshield, cab or msi",
+                name
+            ))),
+        }
+    }
+
+    // Detects the type from the signature at the start of the file. Inno Setup installers are
+    // plain Windows executables, so those are recognised by the marker of their setup data.
+    pub fn detect(path: &Path) -> io::Result<InstallerType> {
+        let mut header = [0u8; 8];
+        let mut file = File::open(path)?;
+        let read = file.read(&mut header)?;
+        let header = &header[..read];
+
+        if header.starts_with(MS_CAB_SIGNATURE) {
+            return Ok(InstallerType::MsCab);
+        }
+        if header.starts_with(INSTALLSHIELD_CAB_SIGNATURE) {
+            return Ok(InstallerType::InstallShield);
+        }
+        if header.starts_with(OLE_SIGNATURE) {
+            return Ok(InstallerType::Msi);
+        }
+        if header.starts_with(b"MZ") && contains_marker(path, INNO_SETUP_MARKER)? {
+            return Ok(InstallerType::InnoSetup);
+        }
+
+        Err(Error::other(std::format!(
+            "{} is not a known installer, set installer_type to inno, installshield, cab or msi",
+            path.display()
+        )))
+    }
+}
+
+fn contains_marker(path: &Path, marker: &[u8]) -> io::Result<bool> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buf = vec![0u8; 1024 * 1024];
+    let mut carry: Vec<u8> = Vec::new();
+
+    loop {
+        let read = reader.read(&mut buf)?;
+        if read == 0 {
+            return Ok(false);
+        }
+        carry.extend_from_slice(&buf[..read]);
+        if carry.windows(marker.len()).any(|window| window == marker) {
+            return Ok(true);
+        }
+        let keep = carry.len().saturating_sub(marker.len());
+        carry.drain(..keep);
+    }
+}
+
+/// Decides which files of an installer are extracted and where they go. Paths use "/" and are
+/// relative to the installer root; patterns support `*` and `?` and ignore case.
+pub struct FileFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    strip_prefix: Option<String>,
+}
+
+impl FileFilter {
+    pub fn new(
+        include: &[String],
+        exclude: &[String],
+        strip_prefix: Option<&str>,
+    ) -> io::Result<FileFilter> {
+        Ok(FileFilter {
+            include: include
+                .iter()
+                .map(|pattern| glob_to_regex(pattern))
+                .collect::<io::Result<Vec<Regex>>>()?,
+            exclude: exclude
+                .iter()
+                .map(|pattern| glob_to_regex(pattern))
+                .collect::<io::Result<Vec<Regex>>>()?,
+            strip_prefix: strip_prefix.map(|prefix| prefix.trim_matches('/').to_lowercase()),
+        })
+    }
+
+    // Returns where the file goes below the destination, or None when it is filtered out.
+    pub fn target_path(&self, path: &str) -> Option<PathBuf> {
+        let path = path.replace('\\', "/");
+        let path = path.trim_start_matches('/');
+
+        if !self.include.is_empty() && !self.include.iter().any(|re| re.is_match(path)) {
+            return None;
+        }
+        if self.exclude.iter().any(|re| re.is_match(path)) {
+            return None;
+        }
+
+        let path = match &self.strip_prefix {
+            Some(prefix) if !prefix.is_empty() => {
+                let head = path.get(..prefix.len())?;
+                if !head.eq_ignore_ascii_case(prefix) {
+                    return None;
+                }
+                path[prefix.len()..].strip_prefix('/')?
+            }
+            _ => path,
+        };
+
+        let target = PathBuf::from(path);
+        let is_safe = target.components().count() > 0
+            && target
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+        if !is_safe {
+            warn!("installer. skipping unsafe path {}", path);
+            return None;
+        }
+        Some(target)
+    }
+}
+
+fn glob_to_regex(pattern: &str) -> io::Result<Regex> {
+    let mut regex = String::from("(?i)^");
+    for c in pattern.replace('\\', "/").trim_start_matches('/').chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).map_err(Error::other)
+}
+
+fn write_file<R: Read>(reader: &mut R, destination: &Path, target: &Path) -> io::Result<u64> {
+    let new_path = destination.join(target);
+    if let Some(parent) = new_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let _ = fs::remove_file(&new_path);
+    let mut outfile = io::BufWriter::new(File::create(&new_path)?);
+    io::copy(reader, &mut outfile)
+}
+
+/// Extracts the files of an installer into a directory. Cabinet files and MSI packages are read
+/// directly, Inno Setup and InstallShield need the innoextract and unshield tools.
+pub fn extract_installer(
+    installer_path: &Path,
+    installer_type: Option<InstallerType>,
+    destination: &Path,
+    filter: &FileFilter,
+) -> io::Result<usize> {
+    let installer_type = match installer_type {
+        Some(installer_type) => installer_type,
+        None => InstallerType::detect(installer_path)?,
+    };
+    info!(
+        "installer. extracting {:?} ({:?}) to {:?}",
+        installer_path, installer_type, destination
+    );
+    fs::create_dir_all(destination)?;
+
+    let count = match installer_type {
+        InstallerType::MsCab => {
+            let cabinet = cab::Cabinet::new(BufReader::new(File::open(installer_path)?))?;
+            extract_cabinet(cabinet, &HashMap::new(), destination, filter)?
+        }
+        InstallerType::Msi => extract_msi(installer_path, destination, filter)?,
+        InstallerType::InnoSetup => extract_with_tool(
+            installer_path,
+            destination,
+            filter,
+            "innoextract",
+            &["--silent", "--extract", "--output-dir"],
+        )?,
+        InstallerType::InstallShield => {
+            extract_with_tool(installer_path, destination, filter, "unshield", &["-d"])?
+        }
+    };
+
+    info!("installer. extracted {} files", count);
+    Ok(count)
+}
+
+// Extracts a Microsoft cabinet. Names in the cabinet are renamed through `names` when given,
+// which MSI packages use to map file keys to install paths.
+fn extract_cabinet<R: Read + Seek>(
+    mut cabinet: cab::Cabinet<R>,
+    names: &HashMap<String, String>,
+    destination: &Path,
+    filter: &FileFilter,
+) -> io::Result<usize> {
+    let file_names: Vec<String> = cabinet
+        .folder_entries()
+        .flat_map(|folder| folder.file_entries())
+        .map(|file| file.name().to_string())
+        .collect();
+
+    let mut count = 0;
+    for file_name in file_names {
+        let path = match names.get(&file_name) {
+            Some(path) => path.clone(),
+            None if names.is_empty() => file_name.clone(),
+            None => {
+                warn!("installer. {} is not listed in the File table", file_name);
+                continue;
+            }
+        };
+        let Some(target) = filter.target_path(&path) else {
+            continue;
+        };
+
+        info!("installer. {} -> {:?}", path, target);
+        let mut reader = cabinet.read_file(&file_name)?;
+        write_file(&mut reader, destination, &target)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn long_name(name: &str) -> &str {
+    // "SHORTN~1|Long Name" in the Directory and File tables
+    name.rsplit('|').next().unwrap_or(name)
+}
+
+fn msi_string(row: &msi::Row, column: &str) -> Option<String> {
+    row[column].as_str().map(|value| value.to_string())
+}
+
+// Reads the File, Component, Directory and Media tables of an MSI package to find the install
+// path of every file, then extracts the cabinets that hold them.
+fn extract_msi(msi_path: &Path, destination: &Path, filter: &FileFilter) -> io::Result<usize> {
+    let mut package = msi::open(msi_path)?;
+
+    let mut directories: HashMap<String, (Option<String>, String)> = HashMap::new();
+    for row in package.select_rows(msi::Select::table("Directory"))? {
+        let (Some(directory), Some(default_dir)) = (
+            msi_string(&row, "Directory"),
+            msi_string(&row, "DefaultDir"),
+        ) else {
+            continue;
+        };
+        // "target:source", only the target name matters here.
+        let target_dir = default_dir.split(':').next().unwrap_or(&default_dir);
+        let parent = msi_string(&row, "Directory_Parent").filter(|parent| *parent != directory);
+        directories.insert(directory, (parent, long_name(target_dir).to_string()));
+    }
+
+    let mut component_dirs: HashMap<String, String> = HashMap::new();
+    for row in package.select_rows(msi::Select::table("Component"))? {
+        if let (Some(component), Some(directory)) = (
+            msi_string(&row, "Component"),
+            msi_string(&row, "Directory_"),
+        ) {
+            component_dirs.insert(component, directory);
+        }
+    }
+
+    let mut names: HashMap<String, String> = HashMap::new();
+    for row in package.select_rows(msi::Select::table("File"))? {
+        let (Some(file), Some(component), Some(file_name)) = (
+            msi_string(&row, "File"),
+            msi_string(&row, "Component_"),
+            msi_string(&row, "FileName"),
+        ) else {
+            continue;
+        };
+        let mut path = vec![long_name(&file_name).to_string()];
+        let mut directory = component_dirs.get(&component).cloned();
+        let mut depth = 0;
+        while let Some(current) = directory {
+            let Some((parent, name)) = directories.get(&current) else {
+                break;
+            };
+            // The root is TARGETDIR with the name SourceDir, "." keeps the parent directory.
+            if parent.is_some() && name != "." {
+                path.push(name.clone());
+            }
+            directory = parent.clone();
+            depth += 1;
+            if depth > 64 {
+                return Err(Error::other("msi directory table has a loop"));
+            }
+        }
+        path.reverse();
+        names.insert(file, path.join("/"));
+    }
+
+    let mut cabinets: Vec<String> = Vec::new();
+    for row in package.select_rows(msi::Select::table("Media"))? {
+        if let Some(cabinet) = msi_string(&row, "Cabinet").filter(|cabinet| !cabinet.is_empty()) {
+            cabinets.push(cabinet);
+        }
+    }
+    if cabinets.is_empty() {
+        return Err(Error::other(
+            "the msi package has no cabinets, uncompressed packages are not supported",
+        ));
+    }
+
+    let mut count = 0;
+    for cabinet_name in cabinets {
+        count += match cabinet_name.strip_prefix('#') {
+            Some(stream_name) => {
+                let stream = package.read_stream(stream_name)?;
+                extract_cabinet(cab::Cabinet::new(stream)?, &names, destination, filter)?
+            }
+            None => {
+                let cabinet_path = msi_path
+                    .parent()
+                    .unwrap_or(Path::new(""))
+                    .join(&cabinet_name);
+                let file = File::open(&cabinet_path).map_err(|err| {
+                    Error::other(std::format!(
+                        "cabinet {} of the msi package could not be opened: {}",
+                        cabinet_path.display(),
+                        err
+                    ))
+                })?;
+                extract_cabinet(
+                    cab::Cabinet::new(BufReader::new(file))?,
+                    &names,
+                    destination,
+                    filter,
+                )?
+            }
+        };
+    }
+    Ok(count)
+}
+
+// Runs an external extractor into a temporary directory, then moves the files that pass the
+// filter into the destination.
+fn extract_with_tool(
+    installer_path: &Path,
+    destination: &Path,
+    filter: &FileFilter,
+    tool: &str,
+    args: &[&str],
+) -> io::Result<usize> {
+    let Ok(tool_binary) = which::which(tool) else {
+        return Err(Error::other(std::format!(
+            "Extracting {} needs {}, but it could not be found. Install {} and try again.",
+            installer_path.display(),
+            tool,
+            tool
+        )));
+    };
+
+    let temp_dir = destination.join(TEMP_DIR_NAME);
+    let _ = fs::remove_dir_all(&temp_dir);
+    fs::create_dir_all(&temp_dir)?;
+
+    let mut command = Command::new(tool_binary);
+    command.args(args).arg(&temp_dir);
+    if tool == "unshield" {
+        command.arg("x");
+    }
+    let status = command.arg(installer_path).status()?;
+    if !status.success() {
+        let _ = fs::remove_dir_all(&temp_dir);
+        return Err(Error::other(std::format!(
+            "{} failed with {}",
+            tool,
+            status
+        )));
+    }
+
+    let mut count = 0;
+    for entry in WalkDir::new(&temp_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+    {
+        let Ok(relative_path) = entry.path().strip_prefix(&temp_dir) else {
+            continue;
+        };
+        let relative_path = relative_path.to_string_lossy().replace('\\', "/");
+        let Some(target) = filter.target_path(&relative_path) else {
+            continue;
+        };
+
+        let new_path = destination.join(&target);
+        if let Some(parent) = new_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let _ = fs::remove_file(&new_path);
+        if fs::rename(entry.path(), &new_path).is_err() {
+            fs::copy(entry.path(), &new_path)?;
+        }
+        count += 1;
+    }
+
+    fs::remove_dir_all(&temp_dir)?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::FixtureDir;
+
+    fn filter(include: &[&str], exclude: &[&str], strip_prefix: Option<&str>) -> FileFilter {
+        let include: Vec<String> = include.iter().map(|s| s.to_string()).collect();
+        let exclude: Vec<String> = exclude.iter().map(|s| s.to_string()).collect();
+        FileFilter::new(&include, &exclude, strip_prefix).unwrap()
+    }
+
+    #[test]
+    fn installer_type_from_name() {
+        assert_eq!(
+            InstallerType::from_name("inno").unwrap(),
+            InstallerType::InnoSetup
+        );
+        assert_eq!(
+            InstallerType::from_name("installshield").unwrap(),
+            InstallerType::InstallShield
+        );
+        assert_eq!(
+            InstallerType::from_name("cab").unwrap(),
+            InstallerType::MsCab
+        );
+        assert_eq!(InstallerType::from_name("msi").unwrap(), InstallerType::Msi);
+        assert!(InstallerType::from_name("nsis").is_err());
+    }
+
+    #[test]
+    fn detect_by_signature() {
+        let fixture = FixtureDir::new("installer", "detect");
+        let detect =
+            |name: &str, contents: &[u8]| InstallerType::detect(&fixture.write(name, contents));
+
+        assert_eq!(
+            detect("data1.cab", b"MSCF\0\0\0\0").unwrap(),
+            InstallerType::MsCab
+        );
+        assert_eq!(
+            detect("data1.hdr", b"ISc(\x00\x01\x00\x00").unwrap(),
+            InstallerType::InstallShield
+        );
+        let mut msi = OLE_SIGNATURE.to_vec();
+        msi.extend([0u8; 16]);
+        assert_eq!(detect("game.msi", &msi).unwrap(), InstallerType::Msi);
+
+        let mut inno = b"MZ".to_vec();
+        inno.extend([0u8; 4096]);
+        inno.extend(INNO_SETUP_MARKER);
+        inno.extend(b" (5.5.7)");
+        assert_eq!(
+            detect("setup.exe", &inno).unwrap(),
+            InstallerType::InnoSetup
+        );
+
+        // Other executables and files too short for a signature are not guessed.
+        assert!(detect("other.exe", b"MZ\x90\0\x03\0\0\0\x04\0").is_err());
+        assert!(detect("short.bin", b"MS").is_err());
+    }
+
+    #[test]
+    fn marker_across_reads() {
+        let fixture = FixtureDir::new("installer", "marker");
+        let chunk = 1024 * 1024;
+        let mut contents = vec![0u8; chunk - 10];
+        contents.extend(INNO_SETUP_MARKER);
+        contents.extend(vec![0u8; chunk]);
+        let path = fixture.write("setup.exe", &contents);
+        assert!(contains_marker(&path, INNO_SETUP_MARKER).unwrap());
+
+        let path = fixture.write("other.exe", vec![0u8; 2 * chunk + 7]);
+        assert!(!contains_marker(&path, INNO_SETUP_MARKER).unwrap());
+    }
+
+    #[test]
+    fn include_and_exclude_ignore_case() {
+        let filter = filter(&["data/*.pak", "GAME.EXE"], &["data/demo*"], None);
+        assert_eq!(
+            filter.target_path("Data\\Maps.PAK"),
+            Some(PathBuf::from("Data/Maps.PAK"))
+        );
+        assert_eq!(
+            filter.target_path("/game.exe"),
+            Some(PathBuf::from("game.exe"))
+        );
+        assert_eq!(filter.target_path("data/DEMO1.pak"), None);
+        assert_eq!(filter.target_path("readme.txt"), None);
+        assert_eq!(filter.target_path("data/maps.pa"), None);
+    }
+
+    #[test]
+    fn strip_prefix() {
+        let filter = filter(&[], &[], Some("/App/"));
+        assert_eq!(
+            filter.target_path("app\\bin\\game.exe"),
+            Some(PathBuf::from("bin/game.exe"))
+        );
+        assert_eq!(filter.target_path("tmp/setup.dll"), None);
+        // Only whole path components are stripped.
+        assert_eq!(filter.target_path("application/game.exe"), None);
+        assert_eq!(filter.target_path("app"), None);
+    }
+
+    #[test]
+    fn unsafe_paths_are_skipped() {
+        let filter = filter(&[], &[], None);
+        assert_eq!(filter.target_path("../outside.txt"), None);
+        assert_eq!(filter.target_path("data\\..\\..\\outside.txt"), None);
+        assert_eq!(filter.target_path(""), None);
+        assert_eq!(
+            filter.target_path("data/game.dat"),
+            Some(PathBuf::from("data/game.dat"))
+        );
+    }
+}
@@ -0,0 +1,757 @@
+use log::{info, warn};
+use std::collections::HashSet;
+use std::io;
+use std::io::{Error, Read, Seek, SeekFrom, Write};
+
+pub const SECTOR_SIZE: usize = 2048;
+/// The first 12 bytes of every raw data sector.
+pub const SYNC_PATTERN: [u8; 12] = [
+    0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00,
+];
+
+static STANDARD_IDENTIFIER: &[u8] = b"CD001";
+const VOLUME_DESCRIPTOR_START: u32 = 16;
+const VOLUME_DESCRIPTOR_LIMIT: u32 = 64;
+const VOLUME_DESCRIPTOR_PRIMARY: u8 = 1;
+const VOLUME_DESCRIPTOR_SUPPLEMENTARY: u8 = 2;
+const VOLUME_DESCRIPTOR_TERMINATOR: u8 = 255;
+const ROOT_RECORD_OFFSET: usize = 156;
+
+const FLAG_DIRECTORY: u8 = 0x02;
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+// Escape sequences of a supplementary volume descriptor that mark it as Joliet (UCS-2 levels 1-3).
+const JOLIET_ESCAPES: [&[u8]; 3] = [b"%/@", b"%/C", b"%/E"];
+
+const MAX_DIRECTORY_DEPTH: usize = 64;
+const MAX_CONTINUATION_AREAS: usize = 16;
+
+/// Where the 2048 bytes of user data of each sector are found in an image file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SectorLayout {
+    pub start_offset: u64, // byte offset of the first sector of the data track
+    pub sector_size: u64,  // 2048 for .iso files, 2352 for raw images
+    pub data_offset: u64,  // 0 for .iso files, 16 for raw Mode 1, 24 for raw Mode 2 Form 1
+}
+
+impl SectorLayout {
+    pub const ISO: SectorLayout = SectorLayout {
+        start_offset: 0,
+        sector_size: SECTOR_SIZE as u64,
+        data_offset: 0,
+    };
+}
+
+#[derive(Clone, Debug)]
+pub struct IsoEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    extents: Vec<(u32, u64)>, // (first sector, length in bytes)
+}
+
+// Which names are used for the entries of the image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum NameSource {
+    Primary,
+    RockRidge { susp_skip: usize },
+    Joliet,
+}
+
+/// Reads the file tree of an ISO9660 image, using Rock Ridge or Joliet names when the image has
+/// them. Files are copied out a sector at a time, so large files are never held in memory.
+///
+/// Rock Ridge relocated directories (deeper than 8 levels) are listed at their relocated place.
+pub struct IsoImage<R: Read + Seek> {
+    reader: R,
+    layout: SectorLayout,
+    root: IsoEntry,
+    name_source: NameSource,
+}
+
+impl<R: Read + Seek> IsoImage<R> {
+    pub fn with_layout(reader: R, layout: SectorLayout) -> io::Result<IsoImage<R>> {
+        let mut image = IsoImage {
+            reader,
+            layout,
+            root: IsoEntry {
+                name: String::new(),
+                is_dir: true,
+                size: 0,
+                extents: Vec::new(),
+            },
+            name_source: NameSource::Primary,
+        };
+
+        let mut primary_root = None;
+        let mut joliet_root = None;
+        let mut sector = [0u8; SECTOR_SIZE];
+
+        for lba in VOLUME_DESCRIPTOR_START..VOLUME_DESCRIPTOR_START + VOLUME_DESCRIPTOR_LIMIT {
+            image.read_sector(lba, &mut sector)?;
+            if sector[1..6] != *STANDARD_IDENTIFIER {
+                return Err(Error::other("not an ISO9660 image"));
+            }
+
+            match sector[0] {
+                VOLUME_DESCRIPTOR_PRIMARY if primary_root.is_none() => {
+                    primary_root = parse_record_extent(&sector[ROOT_RECORD_OFFSET..]);
+                }
+                VOLUME_DESCRIPTOR_SUPPLEMENTARY if joliet_root.is_none() => {
+                    let escapes = &sector[88..91];
+                    if JOLIET_ESCAPES.contains(&escapes) {
+                        joliet_root = parse_record_extent(&sector[ROOT_RECORD_OFFSET..]);
+                    }
+                }
+                VOLUME_DESCRIPTOR_TERMINATOR => break,
+                _ => {}
+            }
+        }
+
+        let Some(primary_root) = primary_root else {
+            return Err(Error::other("ISO9660 primary volume descriptor not found"));
+        };
+
+        image.root.extents = vec![primary_root];
+        if let Some(susp_skip) = image.find_rock_ridge()? {
+            info!("iso. using rock ridge names");
+            image.name_source = NameSource::RockRidge { susp_skip };
+        } else if let Some(joliet_root) = joliet_root {
+            info!("iso. using joliet names");
+            image.root.extents = vec![joliet_root];
+            image.name_source = NameSource::Joliet;
+        } else {
+            info!("iso. using iso9660 names");
+        }
+        image.root.size = image.root.extents[0].1;
+
+        Ok(image)
+    }
+
+    pub fn root(&self) -> IsoEntry {
+        self.root.clone()
+    }
+
+    fn read_sector(&mut self, lba: u32, sector: &mut [u8; SECTOR_SIZE]) -> io::Result<()> {
+        let offset = self.layout.start_offset
+            + u64::from(lba) * self.layout.sector_size
+            + self.layout.data_offset;
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.reader.read_exact(sector)
+    }
+
+    fn read_extent(&mut self, lba: u32, length: u64) -> io::Result<Vec<u8>> {
+        let sectors = length.div_ceil(SECTOR_SIZE as u64) as u32;
+        let mut data = Vec::with_capacity(sectors as usize * SECTOR_SIZE);
+        let mut sector = [0u8; SECTOR_SIZE];
+        for idx in 0..sectors {
+            self.read_sector(lba + idx, &mut sector)?;
+            data.extend_from_slice(&sector);
+        }
+        Ok(data)
+    }
+
+    // The SUSP "SP" entry in the first record of the root directory marks Rock Ridge images. It
+    // also gives the number of bytes to skip in the system use area of every record.
+    fn find_rock_ridge(&mut self) -> io::Result<Option<usize>> {
+        let (lba, _) = self.root.extents[0];
+        let mut sector = [0u8; SECTOR_SIZE];
+        self.read_sector(lba, &mut sector)?;
+
+        let Some(record) = DirectoryRecord::parse(&sector) else {
+            return Ok(None);
+        };
+        let system_use = record.system_use(&sector);
+        if system_use.len() >= 7
+            && &system_use[0..2] == b"SP"
+            && system_use[4] == 0xBE
+            && system_use[5] == 0xEF
+        {
+            return Ok(Some(system_use[6] as usize));
+        }
+        Ok(None)
+    }
+
+    pub fn read_dir(&mut self, dir: &IsoEntry) -> io::Result<Vec<IsoEntry>> {
+        let mut entries: Vec<IsoEntry> = Vec::new();
+        let mut continues_previous = false;
+
+        for &(lba, length) in &dir.extents {
+            let data = self.read_extent(lba, length)?;
+            let data = &data[..(length as usize).min(data.len())];
+
+            let mut pos = 0;
+            while pos < data.len() {
+                let record_length = data[pos] as usize;
+                if record_length == 0 {
+                    // Records never cross sectors, the rest of this one is padding.
+                    pos = (pos / SECTOR_SIZE + 1) * SECTOR_SIZE;
+                    continue;
+                }
+
+                let record_data = &data[pos..(pos + record_length).min(data.len())];
+                pos += record_length;
+
+                let Some(record) = DirectoryRecord::parse(record_data) else {
+                    warn!("iso. skipping invalid directory record at sector {}", lba);
+                    continue;
+                };
+                if record.is_self_or_parent() {
+                    continue;
+                }
+
+                if continues_previous {
+                    if let Some(previous) = entries.last_mut() {
+                        previous.extents.push((record.lba, record.length));
+                        previous.size += record.length;
+                    }
+                } else {
+                    let name = self.entry_name(&record, record_data)?;
+                    let Some(name) = name.filter(|name| is_safe_name(name)) else {
+                        warn!("iso. skipping entry with an unusable name");
+                        continue;
+                    };
+                    entries.push(IsoEntry {
+                        name,
+                        is_dir: record.flags & FLAG_DIRECTORY != 0,
+                        size: record.length,
+                        extents: vec![(record.lba, record.length)],
+                    });
+                }
+                continues_previous = record.flags & FLAG_MULTI_EXTENT != 0;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn entry_name(
+        &mut self,
+        record: &DirectoryRecord,
+        record_data: &[u8],
+    ) -> io::Result<Option<String>> {
+        let identifier = &record_data[33..33 + record.name_length];
+        match self.name_source {
+            NameSource::Joliet => {
+                let utf16: Vec<u16> = identifier
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect();
+                Ok(Some(strip_version(&String::from_utf16_lossy(&utf16))))
+            }
+            NameSource::RockRidge { susp_skip } => {
+                let system_use = record.system_use(record_data);
+                let system_use = system_use.get(susp_skip..).unwrap_or_default();
+                match self.rock_ridge_name(system_use)? {
+                    Some(name) => Ok(Some(name)),
+                    None => Ok(Some(primary_name(identifier))),
+                }
+            }
+            NameSource::Primary => Ok(Some(primary_name(identifier))),
+        }
+    }
+
+    // Collects the NM entries of a system use area, following CE continuation areas.
+    fn rock_ridge_name(&mut self, system_use: &[u8]) -> io::Result<Option<String>> {
+        let mut name: Option<Vec<u8>> = None;
+        let mut area = system_use.to_vec();
+
+        for _ in 0..MAX_CONTINUATION_AREAS {
+            let mut continuation = None;
+            let mut pos = 0;
+
+            while pos + 4 <= area.len() {
+                let signature = &area[pos..pos + 2];
+                let entry_length = area[pos + 2] as usize;
+                if entry_length < 4 || pos + entry_length > area.len() {
+                    break;
+                }
+                let entry_data = &area[pos + 4..pos + entry_length];
+
+                match signature {
+                    b"NM" if !entry_data.is_empty() => {
+                        let flags = entry_data[0];
+                        // Names for "." and ".." are not needed, those entries are skipped.
+                        if flags & 0x06 == 0 {
+                            name.get_or_insert_with(Vec::new)
+                                .extend_from_slice(&entry_data[1..]);
+                        }
+                    }
+                    b"CE" if entry_data.len() >= 24 => {
+                        let lba = u32::from_le_bytes(entry_data[0..4].try_into().unwrap());
+                        let offset = u32::from_le_bytes(entry_data[8..12].try_into().unwrap());
+                        let length = u32::from_le_bytes(entry_data[16..20].try_into().unwrap());
+                        continuation = Some((lba, offset as usize, length as usize));
+                    }
+                    b"ST" => break,
+                    _ => {}
+                }
+                pos += entry_length;
+            }
+
+            let Some((lba, offset, length)) = continuation else {
+                break;
+            };
+            let data = self.read_extent(lba, (offset + length) as u64)?;
+            area = data
+                .get(offset..offset + length)
+                .map(|area| area.to_vec())
+                .unwrap_or_default();
+        }
+
+        Ok(name.map(|name| String::from_utf8_lossy(&name).to_string()))
+    }
+
+    /// Lists every file of the image with its path, for example "/DATA/intro.mov".
+    pub fn walk_files(&mut self) -> io::Result<Vec<(String, IsoEntry)>> {
+        let mut files = Vec::new();
+        let mut visited = HashSet::new();
+        let root = self.root();
+        self.walk_dir(&root, "", 0, &mut visited, &mut files)?;
+        Ok(files)
+    }
+
+    fn walk_dir(
+        &mut self,
+        dir: &IsoEntry,
+        path: &str,
+        depth: usize,
+        visited: &mut HashSet<u32>,
+        files: &mut Vec<(String, IsoEntry)>,
+    ) -> io::Result<()> {
+        if depth > MAX_DIRECTORY_DEPTH || !visited.insert(dir.extents[0].0) {
+            warn!("iso. not following directory loop at {}", path);
+            return Ok(());
+        }
+
+        for entry in self.read_dir(dir)? {
+            let entry_path = format!("{}/{}", path, entry.name);
+            if entry.is_dir {
+                self.walk_dir(&entry, &entry_path, depth + 1, visited, files)?;
+            } else {
+                files.push((entry_path, entry));
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies the contents of a file to the writer.
+    pub fn copy_file<W: Write>(&mut self, file: &IsoEntry, writer: &mut W) -> io::Result<u64> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let mut written = 0;
+
+        for &(lba, length) in &file.extents {
+            if self.layout == SectorLayout::ISO {
+                self.reader
+                    .seek(SeekFrom::Start(u64::from(lba) * SECTOR_SIZE as u64))?;
+                let copied = io::copy(&mut (&mut self.reader).take(length), writer)?;
+                if copied != length {
+                    return Err(Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("{} is truncated in the image", file.name),
+                    ));
+                }
+                written += copied;
+                continue;
+            }
+
+            let mut remaining = length;
+            let mut current = lba;
+            while remaining > 0 {
+                self.read_sector(current, &mut sector)?;
+                let chunk = remaining.min(SECTOR_SIZE as u64) as usize;
+                writer.write_all(&sector[..chunk])?;
+                remaining -= chunk as u64;
+                written += chunk as u64;
+                current += 1;
+            }
+        }
+
+        Ok(written)
+    }
+}
+
+struct DirectoryRecord {
+    length: u64,
+    lba: u32,
+    flags: u8,
+    name_length: usize,
+    first_identifier_byte: u8,
+    record_length: usize,
+}
+
+impl DirectoryRecord {
+    fn parse(data: &[u8]) -> Option<DirectoryRecord> {
+        let record_length = *data.first()? as usize;
+        if record_length < 34 || data.len() < record_length {
+            return None;
+        }
+        let name_length = data[32] as usize;
+        if 33 + name_length > record_length {
+            return None;
+        }
+
+        Some(DirectoryRecord {
+            lba: u32::from_le_bytes(data[2..6].try_into().ok()?),
+            length: u64::from(u32::from_le_bytes(data[10..14].try_into().ok()?)),
+            flags: data[25],
+            name_length,
+            first_identifier_byte: if name_length > 0 { data[33] } else { 0 },
+            record_length,
+        })
+    }
+
+    // The "." and ".." records use the single byte identifiers 0 and 1.
+    fn is_self_or_parent(&self) -> bool {
+        self.name_length == 1 && self.first_identifier_byte <= 1
+    }
+
+    fn system_use<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        // The identifier is padded to an even length.
+        let start = 33 + self.name_length + (1 - self.name_length % 2);
+        data.get(start..self.record_length).unwrap_or_default()
+    }
+}
+
+fn parse_record_extent(data: &[u8]) -> Option<(u32, u64)> {
+    let record = DirectoryRecord::parse(data)?;
+    Some((record.lba, record.length))
+}
+
+// "README.TXT;1" -> "README.TXT", "DATA." -> "DATA"
+fn strip_version(name: &str) -> String {
+    let name = match name.rfind(';') {
+        Some(idx) => &name[..idx],
+        None => name,
+    };
+    name.strip_suffix('.').unwrap_or(name).to_string()
+}
+
+fn primary_name(identifier: &[u8]) -> String {
+    strip_version(&String::from_utf8_lossy(identifier))
+}
+
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/') && !name.contains('\0')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const PRIMARY_ROOT: u32 = 20;
+    const JOLIET_ROOT: u32 = 21;
+    const README: u32 = 22;
+    const PRIMARY_DATA: u32 = 23;
+    const JOLIET_DATA: u32 = 24;
+    const INTRO: u32 = 25;
+    const IMAGE_SECTORS: usize = 27;
+
+    const README_CONTENTS: &[u8] = b"Read me first.";
+    const INTRO_LENGTH: usize = 3000; // spans two sectors
+
+    fn record(lba: u32, length: u32, flags: u8, identifier: &[u8], system_use: &[u8]) -> Vec<u8> {
+        let padding = 1 - identifier.len() % 2;
+        let record_length = 33 + identifier.len() + padding + system_use.len();
+        let mut record = vec![0u8; record_length];
+        record[0] = record_length as u8;
+        record[2..6].copy_from_slice(&lba.to_le_bytes());
+        record[6..10].copy_from_slice(&lba.to_be_bytes());
+        record[10..14].copy_from_slice(&length.to_le_bytes());
+        record[14..18].copy_from_slice(&length.to_be_bytes());
+        record[25] = flags;
+        record[32] = identifier.len() as u8;
+        record[33..33 + identifier.len()].copy_from_slice(identifier);
+        record[33 + identifier.len() + padding..].copy_from_slice(system_use);
+        record
+    }
+
+    fn joliet(name: &str) -> Vec<u8> {
+        name.encode_utf16().flat_map(|c| c.to_be_bytes()).collect()
+    }
+
+    fn intro_contents() -> Vec<u8> {
+        (0..INTRO_LENGTH).map(|idx| (idx % 251) as u8).collect()
+    }
+
+    struct ImageBuilder {
+        data: Vec<u8>,
+    }
+
+    impl ImageBuilder {
+        fn new() -> ImageBuilder {
+            ImageBuilder {
+                data: vec![0u8; IMAGE_SECTORS * SECTOR_SIZE],
+            }
+        }
+
+        fn put(&mut self, lba: u32, offset: usize, bytes: &[u8]) {
+            let start = lba as usize * SECTOR_SIZE + offset;
+            self.data[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+
+        fn volume_descriptor(&mut self, lba: u32, kind: u8, escapes: &[u8], root: Option<u32>) {
+            self.put(lba, 0, &[kind]);
+            self.put(lba, 1, STANDARD_IDENTIFIER);
+            self.put(lba, 6, &[1]);
+            self.put(lba, 88, escapes);
+            if let Some(root) = root {
+                let root_record = record(root, SECTOR_SIZE as u32, FLAG_DIRECTORY, &[0], &[]);
+                self.put(lba, ROOT_RECORD_OFFSET, &root_record);
+            }
+        }
+
+        fn directory(&mut self, lba: u32, parent: u32, records: &[Vec<u8>]) {
+            let mut data = record(lba, SECTOR_SIZE as u32, FLAG_DIRECTORY, &[0], &[]);
+            data.extend(record(
+                parent,
+                SECTOR_SIZE as u32,
+                FLAG_DIRECTORY,
+                &[1],
+                &[],
+            ));
+            for record in records {
+                data.extend(record);
+            }
+            self.put(lba, 0, &data);
+        }
+
+        fn directory_with_system_use(
+            &mut self,
+            lba: u32,
+            self_system_use: &[u8],
+            records: &[Vec<u8>],
+        ) {
+            let mut data = record(
+                lba,
+                SECTOR_SIZE as u32,
+                FLAG_DIRECTORY,
+                &[0],
+                self_system_use,
+            );
+            data.extend(record(lba, SECTOR_SIZE as u32, FLAG_DIRECTORY, &[1], &[]));
+            for record in records {
+                data.extend(record);
+            }
+            self.put(lba, 0, &data);
+        }
+
+        fn files(&mut self) {
+            self.put(README, 0, README_CONTENTS);
+            self.put(INTRO, 0, &intro_contents());
+        }
+
+        fn primary_tree(&mut self) {
+            self.directory(
+                PRIMARY_ROOT,
+                PRIMARY_ROOT,
+                &[
+                    record(
+                        README,
+                        README_CONTENTS.len() as u32,
+                        0,
+                        b"README.TXT;1",
+                        &[],
+                    ),
+                    record(
+                        PRIMARY_DATA,
+                        SECTOR_SIZE as u32,
+                        FLAG_DIRECTORY,
+                        b"DATA",
+                        &[],
+                    ),
+                ],
+            );
+            self.directory(
+                PRIMARY_DATA,
+                PRIMARY_ROOT,
+                &[record(INTRO, INTRO_LENGTH as u32, 0, b"INTRO.MOV;1", &[])],
+            );
+        }
+
+        fn joliet_tree(&mut self) {
+            self.directory(
+                JOLIET_ROOT,
+                JOLIET_ROOT,
+                &[
+                    record(
+                        README,
+                        README_CONTENTS.len() as u32,
+                        0,
+                        &joliet("ReadMe.txt;1"),
+                        &[],
+                    ),
+                    record(
+                        JOLIET_DATA,
+                        SECTOR_SIZE as u32,
+                        FLAG_DIRECTORY,
+                        &joliet("Data"),
+                        &[],
+                    ),
+                ],
+            );
+            self.directory(
+                JOLIET_DATA,
+                JOLIET_ROOT,
+                &[record(
+                    INTRO,
+                    INTRO_LENGTH as u32,
+                    0,
+                    &joliet("Intro.mov;1"),
+                    &[],
+                )],
+            );
+        }
+
+        fn finish(self) -> Cursor<Vec<u8>> {
+            Cursor::new(self.data)
+        }
+    }
+
+    fn primary_image() -> ImageBuilder {
+        let mut builder = ImageBuilder::new();
+        builder.volume_descriptor(16, VOLUME_DESCRIPTOR_PRIMARY, &[], Some(PRIMARY_ROOT));
+        builder.volume_descriptor(17, VOLUME_DESCRIPTOR_TERMINATOR, &[], None);
+        builder.primary_tree();
+        builder.files();
+        builder
+    }
+
+    fn joliet_image() -> ImageBuilder {
+        let mut builder = ImageBuilder::new();
+        builder.volume_descriptor(16, VOLUME_DESCRIPTOR_PRIMARY, &[], Some(PRIMARY_ROOT));
+        builder.volume_descriptor(
+            17,
+            VOLUME_DESCRIPTOR_SUPPLEMENTARY,
+            b"%/E",
+            Some(JOLIET_ROOT),
+        );
+        builder.volume_descriptor(18, VOLUME_DESCRIPTOR_TERMINATOR, &[], None);
+        builder.primary_tree();
+        builder.joliet_tree();
+        builder.files();
+        builder
+    }
+
+    fn file_names<R: Read + Seek>(image: &mut IsoImage<R>) -> Vec<String> {
+        image
+            .walk_files()
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    #[test]
+    fn primary_names_without_version() {
+        let mut image = IsoImage::with_layout(primary_image().finish(), SectorLayout::ISO).unwrap();
+        assert_eq!(
+            file_names(&mut image),
+            vec!["/README.TXT", "/DATA/INTRO.MOV"]
+        );
+    }
+
+    #[test]
+    fn joliet_names_from_supplementary_descriptor() {
+        let mut image = IsoImage::with_layout(joliet_image().finish(), SectorLayout::ISO).unwrap();
+        assert_eq!(
+            file_names(&mut image),
+            vec!["/ReadMe.txt", "/Data/Intro.mov"]
+        );
+    }
+
+    #[test]
+    fn rock_ridge_names_from_nm_entries() {
+        let mut builder = ImageBuilder::new();
+        builder.volume_descriptor(16, VOLUME_DESCRIPTOR_PRIMARY, &[], Some(PRIMARY_ROOT));
+        builder.volume_descriptor(
+            17,
+            VOLUME_DESCRIPTOR_SUPPLEMENTARY,
+            b"%/E",
+            Some(JOLIET_ROOT),
+        );
+        builder.volume_descriptor(18, VOLUME_DESCRIPTOR_TERMINATOR, &[], None);
+        builder.joliet_tree();
+        builder.files();
+
+        let sp_entry = [b'S', b'P', 7, 1, 0xBE, 0xEF, 0];
+        let mut nm_entry = vec![b'N', b'M', 0, 1, 0];
+        nm_entry.extend_from_slice(b"read_me_first.txt");
+        nm_entry[2] = nm_entry.len() as u8;
+        builder.directory_with_system_use(
+            PRIMARY_ROOT,
+            &sp_entry,
+            &[
+                record(
+                    README,
+                    README_CONTENTS.len() as u32,
+                    0,
+                    b"README.TXT;1",
+                    &nm_entry,
+                ),
+                record(INTRO, INTRO_LENGTH as u32, 0, b"INTRO.MOV;1", &[]),
+            ],
+        );
+
+        // Rock Ridge is preferred over Joliet, records without NM keep their primary name.
+        let mut image = IsoImage::with_layout(builder.finish(), SectorLayout::ISO).unwrap();
+        assert_eq!(
+            file_names(&mut image),
+            vec!["/read_me_first.txt", "/INTRO.MOV"]
+        );
+    }
+
+    #[test]
+    fn copy_file_across_sectors() {
+        let mut image = IsoImage::with_layout(joliet_image().finish(), SectorLayout::ISO).unwrap();
+        let files = image.walk_files().unwrap();
+
+        let mut readme = Vec::new();
+        image.copy_file(&files[0].1, &mut readme).unwrap();
+        assert_eq!(readme, README_CONTENTS);
+
+        let mut intro = Vec::new();
+        assert_eq!(
+            image.copy_file(&files[1].1, &mut intro).unwrap(),
+            INTRO_LENGTH as u64
+        );
+        assert_eq!(intro, intro_contents());
+    }
+
+    #[test]
+    fn copy_file_from_raw_sectors() {
+        // Raw Mode 1 sectors: 16 bytes of sync and header, 2048 bytes of data, 288 bytes of EDC/ECC.
+        let layout = SectorLayout {
+            start_offset: 0,
+            sector_size: 2352,
+            data_offset: 16,
+        };
+        let iso = primary_image().data;
+        let mut raw = Vec::new();
+        for sector in iso.chunks(SECTOR_SIZE) {
+            raw.extend_from_slice(&[0xAA; 16]);
+            raw.extend_from_slice(sector);
+            raw.extend_from_slice(&[0x55; 288]);
+        }
+
+        let mut image = IsoImage::with_layout(Cursor::new(raw), layout).unwrap();
+        let files = image.walk_files().unwrap();
+        let mut intro = Vec::new();
+        image.copy_file(&files[1].1, &mut intro).unwrap();
+        assert_eq!(intro, intro_contents());
+    }
+
+    #[test]
+    fn not_an_iso_image() {
+        let data = Cursor::new(vec![0u8; IMAGE_SECTORS * SECTOR_SIZE]);
+        assert!(IsoImage::with_layout(data, SectorLayout::ISO).is_err());
+    }
+
+    #[test]
+    fn strip_version_suffix() {
+        assert_eq!(strip_version("README.TXT;1"), "README.TXT");
+        assert_eq!(strip_version("DATA."), "DATA");
+        assert_eq!(strip_version("SETUP.EXE"), "SETUP.EXE");
+    }
+}
@@ -0,0 +1,389 @@
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::Error;
+
+use crate::package;
+
+static LAUNCH_PROFILE_FILE: &str = "launch_profile.json";
+static COMMAND_PLACEHOLDER: &str = "%command%";
+
+// User settings for how a game is launched, on top of what the package metadata defines.
+#[derive(Default, Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct LaunchProfile {
+    pub env: BTreeMap<String, String>, // example: {"DXVK_HUD": "fps"}
+    pub wrappers: Vec<String>,         // example: ["gamemoderun", "gamescope -f --"]
+    pub args: Vec<String>,             // example: ["-windowed"]
+    pub working_dir: Option<String>,
+}
+
+impl LaunchProfile {
+    pub fn from_file(app_id: &str) -> LaunchProfile {
+        let profile_path = match package::place_config_file(app_id, LAUNCH_PROFILE_FILE) {
+            Ok(profile_path) => profile_path,
+            Err(err) => {
+                error!("launch_profile. could not place config file: {:?}", err);
+                return LaunchProfile::default();
+            }
+        };
+        if !profile_path.exists() {
+            return LaunchProfile::default();
+        }
+
+        match fs::read_to_string(&profile_path) {
+            Ok(s) => match serde_json::from_str::<LaunchProfile>(&s) {
+                Ok(profile) => profile,
+                Err(err) => {
+                    error!(
+                        "launch_profile. error parsing {:?}: {:?}",
+                        profile_path, err
+                    );
+                    LaunchProfile::default()
+                }
+            },
+            Err(err) => {
+                error!(
+                    "launch_profile. error reading {:?}: {:?}",
+                    profile_path, err
+                );
+                LaunchProfile::default()
+            }
+        }
+    }
+
+    pub fn save(&self, app_id: &str) -> io::Result<()> {
+        let profile_path = package::place_config_file(app_id, LAUNCH_PROFILE_FILE)?;
+        if self.is_empty() {
+            if profile_path.exists() {
+                fs::remove_file(profile_path)?;
+            }
+            return Ok(());
+        }
+
+        info!("launch_profile. saving {:?}", profile_path);
+        let profile_json = serde_json::to_string_pretty(self).map_err(Error::other)?;
+        fs::write(profile_path, profile_json)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.env.is_empty()
+            && self.wrappers.is_empty()
+            && self.args.is_empty()
+            && self.working_dir.is_none()
+    }
+
+    // Reads launch options in the format Steam uses, for example
+    // "DXVK_HUD=fps gamemoderun %command% -windowed". Without %command%, everything after the
+    // variables is passed to the game. The working directory is kept from the current profile.
+    pub fn update_from_launch_options(&mut self, launch_options: &str) {
+        let mut tokens = split_args(launch_options).into_iter().peekable();
+
+        self.env.clear();
+        while let Some(token) = tokens.peek() {
+            match token.split_once('=') {
+                Some((key, value)) if is_env_key(key) => {
+                    self.env.insert(key.to_string(), value.to_string());
+                    tokens.next();
+                }
+                _ => break,
+            }
+        }
+
+        let rest: Vec<String> = tokens.collect();
+        match rest.iter().position(|token| token == COMMAND_PLACEHOLDER) {
+            Some(idx) => {
+                self.wrappers = if idx > 0 {
+                    vec![join_args(&rest[..idx])]
+                } else {
+                    Vec::new()
+                };
+                self.args = rest[idx + 1..].to_vec();
+            }
+            None => {
+                self.wrappers = Vec::new();
+                self.args = rest;
+            }
+        }
+    }
+
+    // Sets the directory the engine is started in. An empty path clears it.
+    pub fn set_working_dir(&mut self, working_dir: &str) {
+        let working_dir = working_dir.trim();
+        self.working_dir = if working_dir.is_empty() {
+            None
+        } else {
+            Some(working_dir.to_string())
+        };
+    }
+
+    pub fn to_launch_options(&self) -> String {
+        if self.is_empty() {
+            return String::new();
+        }
+
+        let mut parts: Vec<String> = self
+            .env
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, quote_arg(value)))
+            .collect();
+        parts.extend(self.wrappers.iter().cloned());
+        parts.push(COMMAND_PLACEHOLDER.to_string());
+        if !self.args.is_empty() {
+            parts.push(join_args(&self.args));
+        }
+        parts.join(" ")
+    }
+
+    // Puts the wrapper chain in front of the command and the extra arguments after it.
+    pub fn wrap_command(
+        &self,
+        commandline: String,
+        args: Vec<String>,
+    ) -> io::Result<(String, Vec<String>)> {
+        let mut wrapper_args: Vec<String> = self
+            .wrappers
+            .iter()
+            .flat_map(|wrapper| split_args(wrapper))
+            .collect();
+
+        let mut command_args = args;
+        command_args.extend(self.args.iter().cloned());
+
+        if wrapper_args.is_empty() {
+            return Ok((commandline, command_args));
+        }
+
+        let wrapper = wrapper_args.remove(0);
+        if which::which(&wrapper).is_err() {
+            return Err(Error::other(std::format!(
+                "The launch options use {}, but it could not be found. Install it or change the launch options and try again.",
+                wrapper
+            )));
+        }
+
+        wrapper_args.push(commandline);
+        wrapper_args.extend(command_args);
+        Ok((wrapper, wrapper_args))
+    }
+}
+
+fn is_env_key(key: &str) -> bool {
+    !key.is_empty()
+        && !key.starts_with(|c: char| c.is_ascii_digit())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Splits a command line into arguments, honoring single quotes, double quotes and backslashes.
+fn split_args(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                }
+                in_arg = true;
+            }
+            (Some(_), c) => current.push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+
+    if in_arg {
+        args.push(current);
+    }
+    args
+}
+
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty()
+        && !arg
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '\'' || c == '\\')
+    {
+        return arg.to_string();
+    }
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn join_args(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| quote_arg(arg))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn split_args_quotes_and_backslashes() {
+        assert_eq!(
+            split_args(r#"-windowed  "My Game" 'it''s' a\ b "say \"hi\"" 'c:\dir'"#),
+            strings(&[
+                "-windowed",
+                "My Game",
+                "its",
+                "a b",
+                "say \"hi\"",
+                r"c:\dir"
+            ])
+        );
+        assert_eq!(
+            split_args(r#"-name "" '' end"#),
+            strings(&["-name", "", "", "end"])
+        );
+        assert!(split_args("   ").is_empty());
+    }
+
+    #[test]
+    fn launch_options_round_trip() {
+        let mut profile = LaunchProfile::default();
+        profile
+            .env
+            .insert("WINEDLLOVERRIDES".to_string(), "d3d9=n,b".to_string());
+        profile
+            .env
+            .insert("GAME_TITLE".to_string(), "My \"Game\"".to_string());
+        profile.wrappers = vec!["gamescope -f --".to_string()];
+        profile.args = strings(&["-windowed", "C:\\Games\\My Game", ""]);
+
+        let launch_options = profile.to_launch_options();
+        assert_eq!(
+            launch_options,
+            r#"GAME_TITLE="My \"Game\"" WINEDLLOVERRIDES=d3d9=n,b gamescope -f -- %command% -windowed "C:\\Games\\My Game" """#
+        );
+
+        let mut parsed = LaunchProfile::default();
+        parsed.update_from_launch_options(&launch_options);
+        assert_eq!(parsed.env, profile.env);
+        assert_eq!(parsed.wrappers, profile.wrappers);
+        assert_eq!(parsed.args, profile.args);
+    }
+
+    #[test]
+    fn env_prefix_with_command() {
+        let mut profile = LaunchProfile {
+            working_dir: Some("/games/quake".to_string()),
+            ..Default::default()
+        };
+        profile.update_from_launch_options("DXVK_HUD=fps _X1=\"a b\" gamemoderun %command% -fast");
+        assert_eq!(
+            profile.env,
+            BTreeMap::from([
+                ("DXVK_HUD".to_string(), "fps".to_string()),
+                ("_X1".to_string(), "a b".to_string()),
+            ])
+        );
+        assert_eq!(profile.wrappers, strings(&["gamemoderun"]));
+        assert_eq!(profile.args, strings(&["-fast"]));
+        // The working directory is not part of the launch options.
+        assert_eq!(profile.working_dir.as_deref(), Some("/games/quake"));
+
+        profile.update_from_launch_options("%command%");
+        assert!(profile.env.is_empty());
+        assert!(profile.wrappers.is_empty());
+        assert!(profile.args.is_empty());
+    }
+
+    #[test]
+    fn env_prefix_without_command() {
+        let mut profile = LaunchProfile::default();
+        profile.update_from_launch_options("DXVK_HUD=fps 1ST=no -windowed +set=1");
+        assert_eq!(
+            profile.env,
+            BTreeMap::from([("DXVK_HUD".to_string(), "fps".to_string())])
+        );
+        assert!(profile.wrappers.is_empty());
+        // A variable name cannot start with a digit, so the rest goes to the game.
+        assert_eq!(profile.args, strings(&["1ST=no", "-windowed", "+set=1"]));
+
+        profile.update_from_launch_options("");
+        assert!(profile.is_empty());
+    }
+
+    #[test]
+    fn wrap_command_order() {
+        let profile = LaunchProfile {
+            wrappers: vec!["env A=1".to_string(), "nice -n 5".to_string()],
+            args: strings(&["-windowed"]),
+            ..Default::default()
+        };
+        let (commandline, args) = profile
+            .wrap_command("./engine".to_string(), strings(&["-game", "base"]))
+            .unwrap();
+        assert_eq!(commandline, "env");
+        assert_eq!(
+            args,
+            strings(&[
+                "A=1",
+                "nice",
+                "-n",
+                "5",
+                "./engine",
+                "-game",
+                "base",
+                "-windowed"
+            ])
+        );
+
+        let profile = LaunchProfile {
+            args: strings(&["-windowed"]),
+            ..Default::default()
+        };
+        let (commandline, args) = profile
+            .wrap_command("./engine".to_string(), strings(&["-game"]))
+            .unwrap();
+        assert_eq!(commandline, "./engine");
+        assert_eq!(args, strings(&["-game", "-windowed"]));
+    }
+
+    #[test]
+    fn wrap_command_missing_wrapper() {
+        let profile = LaunchProfile {
+            wrappers: vec!["luxtorpeda-missing-wrapper --flag".to_string()],
+            ..Default::default()
+        };
+        let err = profile
+            .wrap_command("./engine".to_string(), Vec::new())
+            .unwrap_err();
+        assert!(err.to_string().contains("luxtorpeda-missing-wrapper"));
+    }
+
+    #[test]
+    fn set_working_dir() {
+        let mut profile = LaunchProfile::default();
+        profile.set_working_dir(" /games/quake ");
+        assert_eq!(profile.working_dir.as_deref(), Some("/games/quake"));
+        assert!(!profile.is_empty());
+        profile.set_working_dir("");
+        assert_eq!(profile.working_dir, None);
+    }
+}
@@ -1,12 +1,25 @@
 use godot::prelude::*;
 
+mod cli;
 mod client;
 mod command;
 mod config;
+mod cue_sheet;
+mod disc_image;
+mod engine_report;
 mod godot_logger;
+mod installer;
+mod iso;
+mod launch_profile;
 mod package;
 mod package_metadata;
+mod prefix;
+mod process;
 mod proton_handler;
+mod runner;
+mod template;
+#[cfg(test)]
+mod test_util;
 mod user_env;
 struct Luxtorpeda;
 
@@ -4,10 +4,12 @@ extern crate xz2;
 
 use ar::Archive as ArArchive;
 use bzip2::read::BzDecoder;
+use chrono::Local;
 use flate2::read::GzDecoder;
 use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsStr;
 use std::fs;
 use std::io;
@@ -31,6 +33,84 @@ use steamlocate::SteamDir;
 
 pub static LUX_DISABLE_DEFAULT_CONFIRM: &str = "LUX_DISABLE_DEFAULT_CONFIRM";
 
+pub static LUX_CHOOSE_ENGINE_VERSION: &str = "LUX_CHOOSE_ENGINE_VERSION";
+
+static INSTALLED_ENGINE_FILE: &str = "installed_engine.json";
+static ENGINE_VERSIONS_DIR: &str = "versions";
+static SETUP_PROGRESS_FILE: &str = "setup_progress.json";
+static SETUP_STATE_FILE: &str = "setup_state.json";
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct InstalledEngine {
+    pub engine_name: String,
+    pub version: String,
+    pub pinned_version: Option<String>,
+    pub archived_versions: Vec<String>,
+    pub rollback_version: Option<String>,
+}
+
+// How far a setup pipeline got, so a failed setup can continue from the step that failed.
+#[derive(Default, Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct SetupProgress {
+    pub steps_hash: String,
+    pub completed_steps: usize,
+}
+
+// Which setup created the install, so setup runs again when the package changes it.
+#[derive(Default, Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct SetupState {
+    pub engine_choice: Option<String>,
+    pub setup_hash: String,
+    pub completed_at: Option<i64>, // unix timestamp, none until setup completes
+    pub dialog_answers: BTreeMap<String, String>,
+}
+
+impl SetupState {
+    pub fn from_file(app_id: &str) -> Option<SetupState> {
+        let setup_state_path = place_config_file(app_id, SETUP_STATE_FILE).ok()?;
+        if !setup_state_path.exists() {
+            return None;
+        }
+
+        match fs::read_to_string(setup_state_path) {
+            Ok(s) => match serde_json::from_str::<SetupState>(&s) {
+                Ok(setup_state) => Some(setup_state),
+                Err(err) => {
+                    error!("setup_state. parse err: {:?}", err);
+                    None
+                }
+            },
+            Err(err) => {
+                error!("setup_state. read err: {:?}", err);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, app_id: &str) -> io::Result<()> {
+        let setup_state_path = place_config_file(app_id, SETUP_STATE_FILE)?;
+        let setup_state_json = serde_json::to_string_pretty(self).map_err(Error::other)?;
+        fs::write(setup_state_path, setup_state_json)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EngineUpdate {
+    pub engine_name: String,
+    pub installed_version: String,
+    pub available_version: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum EngineUpdateCheck {
+    UpToDate,
+    UpdateAvailable(EngineUpdate),
+    Pinned(EngineUpdate),
+}
+
 pub fn place_cached_file(app_id: &str, file: &str) -> io::Result<PathBuf> {
     let xdg_dirs = xdg::BaseDirectories::with_prefix("luxtorpeda");
     let path_str = format!("{}/{}", app_id, file);
@@ -425,9 +505,277 @@ fn copy_only(path: &Path, sender: &std::sync::mpsc::Sender<String>) -> io::Resul
     Ok(())
 }
 
-pub fn is_setup_complete(setup_info: &package_metadata::Setup) -> bool {
-    let setup_complete = Path::new(&setup_info.complete_path).exists();
-    setup_complete
+fn install_hash_file_name(game_info: &package_metadata::Game, name: &str) -> io::Result<String> {
+    let mut hash_file_path = std::format!("{}.hash", name);
+
+    if let Some(file_download_config) = game_info.find_download_config_by_name(name) {
+        if let Some(tmp_extract_location) = file_download_config.extract_location {
+            let hashed_extract_location = generate_hash_from_string(&tmp_extract_location)?;
+            hash_file_path = std::format!("{}-{}.hash", name, hashed_extract_location);
+            info!(
+                "hash_check_install extract location with config {}",
+                tmp_extract_location
+            );
+        }
+    }
+
+    Ok(hash_file_path)
+}
+
+// Returns how many steps of the setup completed on an earlier run, as long as the steps have not
+// changed since.
+pub fn find_setup_progress(app_id: &str, steps_hash: &str) -> usize {
+    let Ok(setup_progress_path) = place_config_file(app_id, SETUP_PROGRESS_FILE) else {
+        return 0;
+    };
+    if !setup_progress_path.exists() {
+        return 0;
+    }
+
+    match fs::read_to_string(setup_progress_path) {
+        Ok(s) => match serde_json::from_str::<SetupProgress>(&s) {
+            Ok(setup_progress) if setup_progress.steps_hash == steps_hash => {
+                setup_progress.completed_steps
+            }
+            Ok(_) => {
+                info!("find_setup_progress. setup steps changed, starting over");
+                0
+            }
+            Err(err) => {
+                error!("find_setup_progress parse err: {:?}", err);
+                0
+            }
+        },
+        Err(err) => {
+            error!("find_setup_progress read err: {:?}", err);
+            0
+        }
+    }
+}
+
+pub fn save_setup_progress(
+    app_id: &str,
+    steps_hash: &str,
+    completed_steps: usize,
+) -> io::Result<()> {
+    let setup_progress_path = place_config_file(app_id, SETUP_PROGRESS_FILE)?;
+    let setup_progress = SetupProgress {
+        steps_hash: steps_hash.to_string(),
+        completed_steps,
+    };
+    let setup_progress_json =
+        serde_json::to_string_pretty(&setup_progress).map_err(Error::other)?;
+    fs::write(setup_progress_path, setup_progress_json)
+}
+
+// Answers given to setup dialogs, offered as defaults when setup runs again.
+pub fn find_dialog_answers(app_id: &str) -> BTreeMap<String, String> {
+    SetupState::from_file(app_id)
+        .map(|setup_state| setup_state.dialog_answers)
+        .unwrap_or_default()
+}
+
+pub fn save_dialog_answer(app_id: &str, key: &str, value: &str) -> io::Result<()> {
+    let mut setup_state = SetupState::from_file(app_id).unwrap_or_default();
+    setup_state
+        .dialog_answers
+        .insert(key.to_string(), value.to_string());
+    setup_state.save(app_id)
+}
+
+pub fn generate_setup_hash(setup_info: &package_metadata::Setup) -> io::Result<String> {
+    let setup_json = serde_json::to_string(setup_info).map_err(Error::other)?;
+    generate_hash_from_string(&setup_json)
+}
+
+pub fn save_setup_complete(
+    app_id: &str,
+    setup_info: &package_metadata::Setup,
+    engine_choice: &str,
+) -> io::Result<()> {
+    let mut setup_state = SetupState::from_file(app_id).unwrap_or_default();
+    setup_state.engine_choice = Some(engine_choice.to_string());
+    setup_state.setup_hash = generate_setup_hash(setup_info)?;
+    setup_state.completed_at = Some(Local::now().timestamp());
+    setup_state.save(app_id)
+}
+
+/// Marks the setup of a game as not done, so it runs again from the first step on the next
+/// launch. Dialog answers are kept and offered as defaults.
+pub fn reset_setup(app_id: &str) -> io::Result<()> {
+    let mut setup_state = SetupState::from_file(app_id).unwrap_or_default();
+    setup_state.setup_hash = String::new();
+    setup_state.completed_at = None;
+    setup_state.save(app_id)?;
+    clear_setup_progress(app_id)
+}
+
+pub fn clear_setup_progress(app_id: &str) -> io::Result<()> {
+    let setup_progress_path = place_config_file(app_id, SETUP_PROGRESS_FILE)?;
+    if setup_progress_path.exists() {
+        fs::remove_file(setup_progress_path)?;
+    }
+    Ok(())
+}
+
+fn strip_archive_path(path: &Path, strip_prefix: Option<&str>) -> Option<PathBuf> {
+    let path = match strip_prefix {
+        Some(strip_prefix) => path.strip_prefix(strip_prefix).ok()?,
+        None => path,
+    };
+    if path.as_os_str().is_empty() {
+        None
+    } else {
+        Some(path.to_path_buf())
+    }
+}
+
+/// Extracts a zip, 7z or tar archive (optionally compressed with gz, bz2 or xz) into a directory.
+/// Entries outside of `strip_prefix` are skipped when it is given.
+pub fn extract_archive(
+    archive_path: &Path,
+    destination: &Path,
+    strip_prefix: Option<&str>,
+) -> io::Result<()> {
+    let file_name = archive_path
+        .file_name()
+        .and_then(OsStr::to_str)
+        .unwrap_or("")
+        .to_lowercase();
+    info!(
+        "extract_archive. extracting {:?} to {:?}",
+        archive_path, destination
+    );
+
+    let file = fs::File::open(archive_path)?;
+    fs::create_dir_all(destination)?;
+
+    if file_name.ends_with(".zip") {
+        let mut archive = zip::ZipArchive::new(file).map_err(Error::other)?;
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i).map_err(Error::other)?;
+            if entry.is_dir() {
+                continue;
+            }
+            let Some(entry_path) = entry.enclosed_name().map(|path| path.to_path_buf()) else {
+                warn!("extract_archive. skipping unsafe path {}", entry.name());
+                continue;
+            };
+            let Some(new_path) = strip_archive_path(&entry_path, strip_prefix) else {
+                continue;
+            };
+            let new_path = destination.join(new_path);
+            if let Some(parent) = new_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let _ = fs::remove_file(&new_path);
+            let mut outfile = fs::File::create(&new_path)?;
+            io::copy(&mut entry, &mut outfile)?;
+        }
+    } else if file_name.ends_with(".7z") {
+        sevenz_rust::decompress_with_extract_fn(file, destination, |entry, reader, _dest| {
+            if entry.is_directory() {
+                return Ok(true);
+            }
+            let Some(new_path) = strip_archive_path(Path::new(entry.name()), strip_prefix) else {
+                return Ok(true);
+            };
+            let new_path = destination.join(new_path);
+            if let Some(parent) = new_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let _ = fs::remove_file(&new_path);
+            let mut outfile = fs::File::create(&new_path)?;
+            io::copy(reader, &mut outfile)?;
+            Ok(true)
+        })
+        .map_err(|err| Error::other(err.to_string()))?;
+    } else {
+        let decoder: Box<dyn std::io::Read> =
+            if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+                Box::new(GzDecoder::new(file))
+            } else if file_name.ends_with(".tar.bz2") {
+                Box::new(BzDecoder::new(file))
+            } else if file_name.ends_with(".tar.xz") {
+                Box::new(XzDecoder::new(file))
+            } else if file_name.ends_with(".tar") {
+                Box::new(file)
+            } else {
+                return Err(Error::other(std::format!(
+                    "{} is not a supported archive",
+                    archive_path.display()
+                )));
+            };
+
+        let mut archive = Archive::new(decoder);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let entry_path = entry.path()?.to_path_buf();
+            let Some(new_path) = strip_archive_path(&entry_path, strip_prefix) else {
+                continue;
+            };
+            if entry.header().entry_type().is_dir() {
+                continue;
+            }
+            let new_path = destination.join(new_path);
+            if let Some(parent) = new_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let _ = fs::remove_file(&new_path);
+            entry.unpack(&new_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn is_setup_complete(setup_info: &package_metadata::Setup, engine_choice: &str) -> bool {
+    if !Path::new(&setup_info.complete_path).exists() {
+        return false;
+    }
+
+    let app_id = user_env::steam_app_id();
+    let setup_hash = match generate_setup_hash(setup_info) {
+        Ok(setup_hash) => setup_hash,
+        Err(err) => {
+            error!("is_setup_complete. hash err: {:?}", err);
+            return true;
+        }
+    };
+
+    let Some(setup_state) = SetupState::from_file(&app_id) else {
+        // Installs from before setup state was recorded only have the marker file.
+        info!("is_setup_complete. no setup state, recording the existing setup");
+        if let Err(err) = save_setup_complete(&app_id, setup_info, engine_choice) {
+            error!("is_setup_complete. save err: {:?}", err);
+        }
+        return true;
+    };
+
+    setup_state_matches(&setup_state, &setup_hash, engine_choice)
+}
+
+// Whether a recorded setup is still valid for the setup and engine choice of this launch.
+fn setup_state_matches(setup_state: &SetupState, setup_hash: &str, engine_choice: &str) -> bool {
+    if setup_state.completed_at.is_none() {
+        info!("is_setup_complete. setup was reset or did not complete");
+        return false;
+    }
+    if setup_state.setup_hash != setup_hash {
+        info!("is_setup_complete. setup changed since it ran, running it again");
+        return false;
+    }
+    if let Some(state_engine_choice) = &setup_state.engine_choice {
+        if state_engine_choice != engine_choice {
+            info!(
+                "is_setup_complete. setup ran for {}, running it again for {}",
+                state_engine_choice, engine_choice
+            );
+            return false;
+        }
+    }
+
+    true
 }
 
 pub fn install(
@@ -446,14 +794,15 @@ pub fn install(
 
     let mut setup_complete = false;
     if let Some(setup) = &game_info.setup {
-        setup_complete = is_setup_complete(setup);
+        setup_complete = is_setup_complete(setup, game_info.choice_name());
     }
 
     let config = config::Config::from_config_file();
     let hash_check_install = config.hash_check_install;
 
     let mut game_command_file_found = false;
-    if let Some((cmd, _)) = find_game_command(game_info, &[]) {
+    // Variables such as dialog answers may not be known yet, the command is then treated as missing.
+    if let Ok(Some((cmd, _))) = find_game_command(game_info, &[]) {
         let cmd_path = Path::new(&cmd);
         if cmd_path.exists() {
             game_command_file_found = true;
@@ -485,19 +834,7 @@ pub fn install(
                 let status_str = serde_json::to_string(&status_obj).unwrap();
                 sender.send(status_str).unwrap();
 
-                let mut hash_file_path = std::format!("{}.hash", name);
-
-                if let Some(file_download_config) = game_info.find_download_config_by_name(name) {
-                    if let Some(tmp_extract_location) = file_download_config.extract_location {
-                        let hashed_extract_location =
-                            generate_hash_from_string(&tmp_extract_location)?;
-                        hash_file_path = std::format!("{}-{}.hash", name, hashed_extract_location);
-                        info!(
-                            "hash_check_install extract location with config {}",
-                            tmp_extract_location
-                        );
-                    }
-                }
+                let hash_file_path = install_hash_file_name(game_info, name)?;
 
                 info!(
                     "hash_check_install is enabled, checking for {}, game_command_file_found: {}",
@@ -640,6 +977,356 @@ pub fn get_game_info(app_id: &str) -> io::Result<package_metadata::Game> {
     }
 }
 
+pub fn find_installed_engine(app_id: &str) -> Option<InstalledEngine> {
+    let installed_engine_path = place_config_file(app_id, INSTALLED_ENGINE_FILE).ok()?;
+    if !installed_engine_path.exists() {
+        return None;
+    }
+
+    match fs::read_to_string(installed_engine_path) {
+        Ok(s) => match serde_json::from_str::<InstalledEngine>(&s) {
+            Ok(installed_engine) => Some(installed_engine),
+            Err(err) => {
+                error!("find_installed_engine parse err: {:?}", err);
+                None
+            }
+        },
+        Err(err) => {
+            error!("find_installed_engine read err: {:?}", err);
+            None
+        }
+    }
+}
+
+pub fn save_installed_engine(app_id: &str, installed_engine: &InstalledEngine) -> io::Result<()> {
+    let installed_engine_path = place_config_file(app_id, INSTALLED_ENGINE_FILE)?;
+    let installed_engine_json =
+        serde_json::to_string_pretty(installed_engine).map_err(Error::other)?;
+    fs::write(installed_engine_path, installed_engine_json)
+}
+
+fn find_engine_version(game_info: &package_metadata::Game) -> Option<String> {
+    let package_metadata = package_metadata::PackageMetadata::from_packages_file();
+    package_metadata
+        .find_engine_by_name(&game_info.engine_name)
+        .map(|engine| engine.version)
+        .filter(|version| !version.is_empty())
+}
+
+fn engine_version_dir(version: &str) -> String {
+    let version_dir: String = version
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '.' || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    std::format!("{}/{}", ENGINE_VERSIONS_DIR, version_dir)
+}
+
+fn find_archived_download(app_id: &str, version: &str, name: &str) -> Option<PathBuf> {
+    let archive_dir_str = std::format!("{}/{}", engine_version_dir(version), name);
+    let archive_dir = find_cached_file(app_id, &archive_dir_str)?;
+    fs::read_dir(archive_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| path.is_file())
+}
+
+fn archive_engine_downloads(
+    app_id: &str,
+    game_info: &package_metadata::Game,
+    version: &str,
+) -> io::Result<()> {
+    for file_info in &game_info.download {
+        let mut cache_dir = app_id;
+        if file_info.cache_by_name {
+            cache_dir = &file_info.name;
+        }
+
+        let Some(cached_file) = find_cached_file(cache_dir, &file_info.file) else {
+            continue;
+        };
+
+        if let Some(archived_file) = find_archived_download(app_id, version, &file_info.name) {
+            if archived_file.file_name() == cached_file.file_name() {
+                continue;
+            }
+            fs::remove_file(archived_file)?;
+        }
+
+        let archive_path_str = std::format!(
+            "{}/{}/{}",
+            engine_version_dir(version),
+            file_info.name,
+            file_info.file
+        );
+        let archive_path = place_cached_file(app_id, &archive_path_str)?;
+
+        info!(
+            "archive_engine_downloads. archiving {} for version {}",
+            file_info.name, version
+        );
+
+        // Hard links keep the archive free on disk, as downloads replace the cached file instead
+        // of writing into it. Copy when the cache is spread across filesystems.
+        if fs::hard_link(&cached_file, &archive_path).is_err() {
+            fs::copy(&cached_file, &archive_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn remove_archived_version(app_id: &str, version: &str) {
+    if let Some(version_dir) = find_cached_file(app_id, &engine_version_dir(version)) {
+        info!(
+            "remove_archived_version. removing version {} for {}",
+            version, app_id
+        );
+        if let Err(err) = fs::remove_dir_all(version_dir) {
+            error!("remove_archived_version err: {:?}", err);
+        }
+    }
+}
+
+fn prune_archived_versions(app_id: &str, installed_engine: &mut InstalledEngine) {
+    let config = config::Config::from_config_file();
+    let versions_to_keep = config.engine_versions_to_keep as usize;
+
+    while installed_engine.archived_versions.len() > versions_to_keep {
+        let Some(idx) = installed_engine
+            .archived_versions
+            .iter()
+            .position(|version| {
+                Some(version) != installed_engine.pinned_version.as_ref()
+                    && *version != installed_engine.version
+            })
+        else {
+            break;
+        };
+
+        let version = installed_engine.archived_versions.remove(idx);
+        remove_archived_version(app_id, &version);
+    }
+}
+
+pub fn record_installed_engine(app_id: &str, game_info: &package_metadata::Game) -> io::Result<()> {
+    let Some(version) = find_engine_version(game_info) else {
+        info!(
+            "record_installed_engine. no version found for {}",
+            game_info.engine_name
+        );
+        return Ok(());
+    };
+
+    let mut installed_engine = find_installed_engine(app_id).unwrap_or_default();
+    if installed_engine.engine_name != game_info.engine_name {
+        installed_engine.pinned_version = None;
+        installed_engine.rollback_version = None;
+        for archived_version in installed_engine.archived_versions.drain(..) {
+            remove_archived_version(app_id, &archived_version);
+        }
+    }
+    installed_engine.engine_name = game_info.engine_name.to_string();
+    installed_engine.version = version;
+
+    info!(
+        "record_installed_engine. {} version {} installed for {}",
+        installed_engine.engine_name, installed_engine.version, app_id
+    );
+
+    let config = config::Config::from_config_file();
+    if config.engine_versions_to_keep > 0 {
+        match archive_engine_downloads(app_id, game_info, &installed_engine.version) {
+            Ok(()) => {
+                let version = installed_engine.version.to_string();
+                installed_engine.archived_versions.retain(|v| *v != version);
+                installed_engine.archived_versions.push(version);
+            }
+            Err(err) => {
+                error!("archive_engine_downloads err: {:?}", err);
+            }
+        }
+    }
+    prune_archived_versions(app_id, &mut installed_engine);
+
+    save_installed_engine(app_id, &installed_engine)
+}
+
+pub fn check_engine_update(app_id: &str, game_info: &package_metadata::Game) -> EngineUpdateCheck {
+    let Some(installed_engine) = find_installed_engine(app_id) else {
+        return EngineUpdateCheck::UpToDate;
+    };
+
+    if installed_engine.engine_name != game_info.engine_name || installed_engine.version.is_empty()
+    {
+        return EngineUpdateCheck::UpToDate;
+    }
+
+    if let Some(rollback_version) = &installed_engine.rollback_version {
+        info!(
+            "check_engine_update. rollback to {} is pending",
+            rollback_version
+        );
+        return EngineUpdateCheck::UpToDate;
+    }
+
+    let Some(available_version) = find_engine_version(game_info) else {
+        return EngineUpdateCheck::UpToDate;
+    };
+
+    if available_version == installed_engine.version {
+        return EngineUpdateCheck::UpToDate;
+    }
+
+    let engine_update = EngineUpdate {
+        engine_name: installed_engine.engine_name,
+        installed_version: installed_engine.version,
+        available_version,
+    };
+
+    if installed_engine.pinned_version.is_some() {
+        EngineUpdateCheck::Pinned(engine_update)
+    } else {
+        EngineUpdateCheck::UpdateAvailable(engine_update)
+    }
+}
+
+/// Versions of the installed engine that can be rolled back to, newest first.
+pub fn find_rollback_versions(app_id: &str) -> Vec<String> {
+    let Some(installed_engine) = find_installed_engine(app_id) else {
+        return Vec::new();
+    };
+
+    installed_engine
+        .archived_versions
+        .iter()
+        .rev()
+        .filter(|version| **version != installed_engine.version)
+        .cloned()
+        .collect()
+}
+
+pub fn find_pending_rollback(app_id: &str, game_info: &package_metadata::Game) -> Option<String> {
+    let installed_engine = find_installed_engine(app_id)?;
+    if installed_engine.engine_name != game_info.engine_name {
+        return None;
+    }
+    installed_engine.rollback_version
+}
+
+pub fn pin_engine_version(app_id: &str, version: Option<&str>) -> io::Result<()> {
+    let Some(mut installed_engine) = find_installed_engine(app_id) else {
+        return Err(Error::other("no installed engine found to pin"));
+    };
+
+    let version = version.unwrap_or(&installed_engine.version).to_string();
+    if version != installed_engine.version {
+        if !installed_engine.archived_versions.contains(&version) {
+            return Err(Error::other(format!(
+                "version {} of {} is not available, the kept versions are: {}",
+                version,
+                installed_engine.engine_name,
+                installed_engine.archived_versions.join(", ")
+            )));
+        }
+
+        info!(
+            "pin_engine_version. rollback to version {} requested",
+            version
+        );
+        installed_engine.rollback_version = Some(version.to_string());
+    } else {
+        installed_engine.rollback_version = None;
+    }
+
+    info!(
+        "pin_engine_version. pinning {} to version {}",
+        installed_engine.engine_name, version
+    );
+    installed_engine.pinned_version = Some(version);
+    save_installed_engine(app_id, &installed_engine)
+}
+
+pub fn unpin_engine_version(app_id: &str) -> io::Result<()> {
+    let Some(mut installed_engine) = find_installed_engine(app_id) else {
+        return Err(Error::other("no installed engine found to unpin"));
+    };
+
+    info!(
+        "unpin_engine_version. unpinning {}",
+        installed_engine.engine_name
+    );
+    installed_engine.pinned_version = None;
+    save_installed_engine(app_id, &installed_engine)
+}
+
+pub fn install_engine_rollback(
+    app_id: &str,
+    game_info: &package_metadata::Game,
+    sender: &std::sync::mpsc::Sender<String>,
+) -> io::Result<()> {
+    let Some(mut installed_engine) = find_installed_engine(app_id) else {
+        return Err(Error::other("no installed engine found to roll back"));
+    };
+    let Some(version) = installed_engine.rollback_version.clone() else {
+        return Err(Error::other("no engine rollback requested"));
+    };
+
+    let status_obj = client::StatusObj {
+        label: Some(format!("Rolling back to {}", version)),
+        progress: Some(0),
+        ..Default::default()
+    };
+    let status_str = serde_json::to_string(&status_obj).unwrap();
+    sender.send(status_str).unwrap();
+
+    let mut setup_complete = false;
+    if let Some(setup) = &game_info.setup {
+        setup_complete = is_setup_complete(setup, game_info.choice_name());
+    }
+
+    for file_info in &game_info.download {
+        let name = &file_info.name;
+
+        if setup_complete {
+            if let Some(download_config) = game_info.find_download_config_by_name(name) {
+                if download_config.setup {
+                    continue;
+                }
+            }
+        }
+
+        let Some(archived_file) = find_archived_download(app_id, &version, name) else {
+            return Err(Error::other(format!(
+                "{} for version {} is no longer kept, cannot roll back",
+                name, version
+            )));
+        };
+
+        // The install hash belongs to the newer download, so the next update has to reinstall.
+        let hash_file_path = install_hash_file_name(game_info, name)?;
+        if let Some(cached_hash_path) = find_cached_file(app_id, &hash_file_path) {
+            fs::remove_file(cached_hash_path)?;
+        }
+
+        unpack_tarball(&archived_file, game_info, name, sender)?;
+    }
+
+    info!(
+        "install_engine_rollback. {} rolled back from {} to {}",
+        installed_engine.engine_name, installed_engine.version, version
+    );
+    installed_engine.version = version;
+    installed_engine.rollback_version = None;
+    save_installed_engine(app_id, &installed_engine)
+}
+
 pub fn get_app_id_deps_paths(
     deps: &Vec<u32>,
     retry: bool,
@@ -908,3 +1595,67 @@ pub fn install_steam_input_template(app_id: &u32, steam_input_template_path: &Pa
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game_with_choices() -> package_metadata::Game {
+        let choice = |name: &str| {
+            let mut choice = package_metadata::EngineChoice::default();
+            choice.name = name.to_string();
+            choice.engine_name = Some("ioquake3".to_string());
+            choice
+        };
+        let mut game_info = package_metadata::Game::default();
+        game_info.engine_name = "ioquake3".to_string();
+        game_info.choices = Some(vec![choice("ioquake3"), choice("ioquake3 (Team Arena)")]);
+        game_info
+    }
+
+    #[test]
+    fn choice_name_of_game() {
+        let mut game_info = package_metadata::Game::default();
+        game_info.engine_name = "openmw".to_string();
+        assert_eq!(game_info.choice_name(), "openmw");
+
+        let mut game_info = game_with_choices();
+        convert_game_info_with_choice("ioquake3 (Team Arena)".to_string(), &mut game_info).unwrap();
+        assert_eq!(game_info.engine_name, "ioquake3");
+        assert_eq!(game_info.choice_name(), "ioquake3 (Team Arena)");
+    }
+
+    #[test]
+    fn setup_runs_again_for_another_choice_of_the_same_engine() {
+        let setup_state = SetupState {
+            engine_choice: Some("ioquake3".to_string()),
+            setup_hash: "hash".to_string(),
+            completed_at: Some(1),
+            ..Default::default()
+        };
+        assert!(setup_state_matches(&setup_state, "hash", "ioquake3"));
+        assert!(!setup_state_matches(
+            &setup_state,
+            "hash",
+            "ioquake3 (Team Arena)"
+        ));
+        assert!(!setup_state_matches(&setup_state, "changed", "ioquake3"));
+
+        let reset = SetupState {
+            completed_at: None,
+            ..setup_state.clone()
+        };
+        assert!(!setup_state_matches(&reset, "hash", "ioquake3"));
+
+        // States recorded without a choice are valid for any choice.
+        let without_choice = SetupState {
+            engine_choice: None,
+            ..setup_state
+        };
+        assert!(setup_state_matches(
+            &without_choice,
+            "hash",
+            "ioquake3 (Team Arena)"
+        ));
+    }
+}
@@ -1,4 +1,8 @@
-use log::{error, info};
+use chrono::Local;
+use flate2::read::GzDecoder;
+use log::{error, info, warn};
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
@@ -11,6 +15,13 @@ use crate::config;
 use crate::package;
 
 const PACKAGE_METADATA_FILENAME: &str = "packagessniper_v2";
+const REMOTE_SOURCES_STATE_FILENAME: &str = "remote_sources_state.json";
+
+// Compressed variants are tried first, the hash is always checked against the decompressed json.
+const METADATA_TRANSPORT_EXTENSIONS: [&str; 3] = ["json.zst", "json.gz", "json"];
+
+pub const LAUNCH_OPTIONS_CHOICE: &str = "Launch Options";
+pub const RESET_SETUP_CHOICE: &str = "Reset Setup";
 
 #[derive(Default, Deserialize, Serialize, Debug, Clone)]
 #[serde(default)]
@@ -51,6 +62,10 @@ pub struct Game {
     pub setup: Option<Setup>,
     pub commands: Option<Vec<GameCommand>>,
     pub default_proton_choice: Option<String>,
+    pub proton_constraints: Option<ProtonConstraints>,
+    pub runner: Option<String>,
+    #[serde(skip)]
+    pub engine_choice: Option<String>, // name of the engine choice applied to the game
 }
 
 #[derive(Default, Deserialize, Serialize, Debug, Clone)]
@@ -88,6 +103,16 @@ pub struct EngineChoice {
     pub commands: Option<Vec<GameCommand>>,
     pub setup: Option<Setup>,
     pub default_proton_choice: Option<String>,
+    pub proton_constraints: Option<ProtonConstraints>,
+    pub runner: Option<String>,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ProtonConstraints {
+    pub allowed: Option<Vec<String>>,
+    pub minimum_version: Option<String>,
+    pub blocked: Vec<String>,
 }
 
 #[derive(Default, Deserialize, Serialize, Debug, Clone)]
@@ -117,16 +142,217 @@ pub struct Setup {
     pub iso_extract: Option<SetupIsoExtract>,
     pub command_current_dir: Option<String>,
     pub command_args: Vec<String>,
+    pub steps: Option<Vec<SetupStep>>,
+    // Default for every command of the setup. Not serialized when unset, so the setup hash
+    // of existing installs stays the same.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_in_seconds: Option<u64>,
+}
+
+// One step of a setup pipeline, with an optional label shown while it runs.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SetupStep {
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(flatten)]
+    pub action: SetupAction,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SetupAction {
+    // Runs a native command as is.
+    Command(SetupCommand),
+    // Runs a Windows executable through Proton or Wine.
+    RunExe(SetupCommand),
+    Bchunk(SetupBChunk),
+    // Extracts the data track of an iso, bin/cue, mdf/mds, nrg or ccd/img image.
+    #[serde(alias = "disc_extract")]
+    IsoExtract(SetupIsoExtract),
+    ArchiveExtract(SetupArchiveExtract),
+    // Extracts files from an Inno Setup, InstallShield, cabinet or MSI installer without running it.
+    InstallerExtract(SetupInstallerExtract),
+    Copy(SetupCopy),
+    Delete(SetupDelete),
+    Patch(SetupPatch),
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct SetupCommand {
+    pub command: String,
+    pub args: Vec<String>,
+    pub current_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_in_seconds: Option<u64>,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct SetupArchiveExtract {
+    pub file: String,
+    pub destination: Option<String>,
+    pub strip_prefix: Option<String>,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct SetupInstallerExtract {
+    pub file: String,
+    pub installer_type: Option<String>, // inno, installshield, cab or msi, detected when not set
+    pub destination: Option<String>,
+    pub include: Vec<String>, // example: ["app/data/*", "*.pak"]
+    pub exclude: Vec<String>,
+    pub strip_prefix: Option<String>,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct SetupCopy {
+    pub source: String,
+    pub destination: String,
+}
+
+#[derive(Default, Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct SetupDelete {
+    pub path: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct SetupPatch {
+    pub patch_file: String,
+    pub directory: Option<String>,
+    pub strip: u32,
+}
+
+impl Default for SetupPatch {
+    fn default() -> SetupPatch {
+        SetupPatch {
+            patch_file: String::new(),
+            directory: None,
+            strip: 1,
+        }
+    }
+}
+
+impl Setup {
+    // The steps to run, with the single command, bchunk and iso_extract of older packages
+    // turned into steps in the order they have always run in.
+    pub fn find_steps(&self) -> Vec<SetupStep> {
+        if let Some(steps) = &self.steps {
+            return steps.clone();
+        }
+
+        let mut steps = Vec::new();
+        if let Some(bchunk) = &self.bchunk {
+            steps.push(SetupStep {
+                label: None,
+                action: SetupAction::Bchunk(bchunk.clone()),
+            });
+        }
+        if let Some(iso_extract) = &self.iso_extract {
+            steps.push(SetupStep {
+                label: None,
+                action: SetupAction::IsoExtract(iso_extract.clone()),
+            });
+        }
+        if !self.command.is_empty() {
+            let command = SetupCommand {
+                command: self.command.to_string(),
+                args: self.command_args.clone(),
+                current_dir: self.command_current_dir.clone(),
+                timeout_in_seconds: None,
+            };
+            let action = if self.command.ends_with(".exe") {
+                SetupAction::RunExe(command)
+            } else {
+                SetupAction::Command(command)
+            };
+            steps.push(SetupStep {
+                label: None,
+                action,
+            });
+        }
+        steps
+    }
+}
+
+impl SetupStep {
+    pub fn description(&self) -> String {
+        if let Some(label) = &self.label {
+            return label.to_string();
+        }
+
+        match &self.action {
+            SetupAction::Command(command) => std::format!("Running {}", command.command),
+            SetupAction::RunExe(command) => std::format!("Running {}", command.command),
+            SetupAction::Bchunk(bchunk) => std::format!("Converting {}", bchunk.bin_file),
+            SetupAction::IsoExtract(_) => "Extracting iso".to_string(),
+            SetupAction::ArchiveExtract(archive) => std::format!("Extracting {}", archive.file),
+            SetupAction::InstallerExtract(installer) => {
+                std::format!("Extracting {}", installer.file)
+            }
+            SetupAction::Copy(copy) => std::format!("Copying {}", copy.source),
+            SetupAction::Delete(delete) => std::format!("Deleting {}", delete.path),
+            SetupAction::Patch(patch) => std::format!("Applying {}", patch.patch_file),
+        }
+    }
 }
 
+pub static SETUP_DIALOG_TYPES: [&str; 5] = ["input", "file", "directory", "choice", "yes_no"];
+
 #[derive(Default, Deserialize, Serialize, Debug, Clone)]
 #[serde(default)]
 pub struct SetupDialog {
     #[serde(alias = "type")]
-    pub dialog_type: String,
+    pub dialog_type: String, // input, file, directory, choice or yes_no
     pub title: String,
     pub label: String,
     pub key: String,
+    pub choices: Vec<String>,
+    pub default: Option<String>,
+    pub validation: Option<String>, // regex the answer has to match
+    pub validation_message: Option<String>,
+}
+
+impl SetupDialog {
+    /// Checks an answer, returning the message to show when it is not accepted.
+    pub fn validate(&self, value: &str) -> Result<(), String> {
+        match self.dialog_type.as_str() {
+            "file" if !Path::new(value).is_file() => {
+                return Err(std::format!("{} is not a file.", value));
+            }
+            "directory" if !Path::new(value).is_dir() => {
+                return Err(std::format!("{} is not a directory.", value));
+            }
+            "choice" if !self.choices.iter().any(|choice| choice == value) => {
+                return Err(std::format!("{} is not one of the choices.", value));
+            }
+            "yes_no" if value != "true" && value != "false" => {
+                return Err(std::format!("{} is not true or false.", value));
+            }
+            _ => {}
+        }
+
+        if let Some(validation) = &self.validation {
+            let re = regex::Regex::new(validation).map_err(|err| {
+                error!(
+                    "setup dialog {} has an invalid validation: {:?}",
+                    self.key, err
+                );
+                std::format!("The package has an invalid validation for {}.", self.key)
+            })?;
+            if !re.is_match(value) {
+                return Err(self.validation_message.clone().unwrap_or_else(|| {
+                    "The value does not have the expected format.".to_string()
+                }));
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Default, Deserialize, Serialize, Debug, Clone)]
@@ -152,6 +378,26 @@ pub struct SetupIsoExtract {
     pub recursive_start_path: Option<String>,
     pub extract_prefix: Option<String>,
     pub extract_to_prefix: Option<String>,
+    pub audio_tracks: Option<SetupAudioTracks>,
+}
+
+// Audio tracks of a disc image, written as "wav" or "ogg" files.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct SetupAudioTracks {
+    pub format: String,
+    pub destination: String,
+    pub file_name: String, // {track} is replaced by the two digit track number
+}
+
+impl Default for SetupAudioTracks {
+    fn default() -> Self {
+        SetupAudioTracks {
+            format: "wav".to_string(),
+            destination: ".".to_string(),
+            file_name: "Track{track}".to_string(),
+        }
+    }
 }
 
 #[derive(Default, Deserialize, Serialize, Debug, Clone)]
@@ -188,6 +434,46 @@ pub struct NoticeTranslation {
     pub value: String,
 }
 
+/// Validators remembered for a remote metadata source, used to make conditional requests.
+#[derive(Default, Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct RemoteSourceState {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_hash: Option<String>,
+    pub last_checked: i64,
+    pub transport_extension: Option<String>, // variant of the metadata that downloaded last time
+}
+
+#[derive(Default, Deserialize, Serialize, Debug)]
+#[serde(default)]
+pub struct RemoteSourcesState {
+    pub sources: HashMap<String, RemoteSourceState>,
+}
+
+/// Criteria used by `PackageMetadata::find_games`, unset fields match every game.
+#[derive(Default, Debug, Clone)]
+pub struct GameFilter {
+    pub engine_name: Option<String>,
+    pub name: Option<String>,
+    pub cloud_supported: bool,
+    pub controller_supported: bool,
+    pub notice_key: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RemoteSourceStatus {
+    Ok,
+    StaleCache(String),
+    Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct RemoteSourceResult {
+    pub url: String,
+    pub status: RemoteSourceStatus,
+}
+
 impl PackageMetadata {
     pub fn from_packages_file() -> PackageMetadata {
         let packages_json_file = PackageMetadata::path_to_packages_file();
@@ -229,46 +515,22 @@ impl PackageMetadata {
                 url_str
             );
 
-            let parsed_url = match Url::parse(url_str) {
-                Ok(u) => u,
+            let local_packages_path = match PackageMetadata::path_to_remote_packages_file(url_str) {
+                Ok(path) => path,
                 Err(err) => {
-                    error!("from_remote_packages_cache. url parse err: {:?}", err);
-                    return metadata;
+                    error!("from_remote_packages_cache. {}", err);
+                    continue;
                 }
             };
 
-            let filename = match parsed_url.path_segments() {
-                Some(mut segments) => match segments.next_back() {
-                    Some(segment) => segment,
-                    None => {
-                        error!("from_remote_packages_cache. url last not found");
-                        return metadata;
-                    }
-                },
-                None => {
-                    error!("from_remote_packages_cache. url path_segments not found");
-                    return metadata;
+            match PackageMetadata::read_remote_packages_file(&local_packages_path) {
+                Ok(mut cached_metadata) => {
+                    info!("merging cached remote package of {}", url_str);
+                    metadata.games.append(&mut cached_metadata.games);
+                    metadata.engines.append(&mut cached_metadata.engines);
                 }
-            };
-
-            let local_packages_path =
-                PackageMetadata::path_to_packages_file().with_file_name(filename);
-
-            match fs::read_to_string(local_packages_path) {
-                Ok(s) => match serde_json::from_str::<PackageMetadata>(&s) {
-                    Ok(mut cached_metadata) => {
-                        info!("merging cached remote package of {}", filename);
-                        metadata.games.append(&mut cached_metadata.games);
-                        metadata.engines.append(&mut cached_metadata.engines);
-                    }
-                    Err(err) => {
-                        error!("error parsing from_remote_packages_cache: {:?}", err);
-                        return metadata;
-                    }
-                },
                 Err(err) => {
-                    error!("error reading from_remote_packages_cache: {:?}", err);
-                    return metadata;
+                    error!("from_remote_packages_cache. {} skipped: {}", url_str, err);
                 }
             }
         }
@@ -284,6 +546,97 @@ impl PackageMetadata {
         self.engines.iter().find(|x| x.engine_name == name).cloned()
     }
 
+    pub fn list_games(&self) -> Vec<&Game> {
+        let mut games: Vec<&Game> = self.games.iter().collect();
+        games.sort_by_key(|game| game.game_name.to_lowercase());
+        games
+    }
+
+    pub fn find_games(&self, filter: &GameFilter) -> Vec<&Game> {
+        self.list_games()
+            .into_iter()
+            .filter(|game| self.game_matches_filter(game, filter))
+            .collect()
+    }
+
+    pub fn find_engines_for_app_id(&self, app_id: &str) -> Vec<Engine> {
+        match self.find_game_by_app_id(app_id) {
+            Some(game) => game
+                .engine_names()
+                .iter()
+                .filter_map(|engine_name| self.find_engine_by_name(engine_name))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn game_has_controller_support(&self, game: &Game) -> bool {
+        game.engine_names().iter().any(|engine_name| {
+            self.find_engine_by_name(engine_name).is_some_and(|engine| {
+                engine.controller_supported || engine.controller_supported_manual_game
+            })
+        })
+    }
+
+    pub fn game_has_notice_key(&self, game: &Game, key: &str) -> bool {
+        let matches_key = |notices: &Option<Vec<Notice>>| {
+            notices.as_ref().is_some_and(|notices| {
+                notices.iter().any(|notice| {
+                    notice.key.as_deref() == Some(key) || notice.value.as_deref() == Some(key)
+                })
+            })
+        };
+
+        if matches_key(&game.notices) {
+            return true;
+        }
+
+        if let Some(choices) = &game.choices {
+            if choices.iter().any(|choice| matches_key(&choice.notices)) {
+                return true;
+            }
+        }
+
+        game.engine_names().iter().any(|engine_name| {
+            self.find_engine_by_name(engine_name)
+                .is_some_and(|engine| matches_key(&engine.notices))
+        })
+    }
+
+    fn game_matches_filter(&self, game: &Game, filter: &GameFilter) -> bool {
+        if let Some(engine_name) = &filter.engine_name {
+            if !game
+                .engine_names()
+                .iter()
+                .any(|x| x.eq_ignore_ascii_case(engine_name))
+            {
+                return false;
+            }
+        }
+
+        if let Some(name) = &filter.name {
+            if !game.game_name.to_lowercase().contains(&name.to_lowercase()) {
+                return false;
+            }
+        }
+
+        if filter.cloud_supported && !(game.cloud_available && game.cloud_supported) {
+            return false;
+        }
+
+        if filter.controller_supported && !self.game_has_controller_support(game) {
+            return false;
+        }
+
+        if let Some(notice_key) = &filter.notice_key {
+            if !self.game_has_notice_key(game, notice_key) {
+                return false;
+            }
+        }
+
+        true
+    }
+
     pub fn find_notice_translation_by_key(&self, key: &str) -> Option<NoticeTranslation> {
         self.notice_translation
             .iter()
@@ -313,89 +666,146 @@ impl PackageMetadata {
         notice
     }
 
-    pub fn update_packages_json() -> io::Result<()> {
+    pub fn update_packages_json() -> io::Result<Vec<RemoteSourceResult>> {
         let config = config::Config::from_config_file();
-        if !config.should_do_update {
-            return PackageMetadata::download_additional_remote_packages(&config);
+        let client = reqwest::blocking::Client::new();
+        let mut sources_state = RemoteSourcesState::from_state_file();
+
+        if config.should_do_update {
+            PackageMetadata::update_main_packages_json(&config, &client, &mut sources_state)?;
         }
 
-        let packages_json_file = PackageMetadata::path_to_packages_file();
-        let mut should_download = true;
-        let mut remote_hash_str: String = String::new();
+        let results = PackageMetadata::download_additional_remote_packages(
+            &config,
+            &client,
+            &mut sources_state,
+        );
+        sources_state.save();
+        Ok(results)
+    }
 
+    fn update_main_packages_json(
+        config: &config::Config,
+        client: &reqwest::blocking::Client,
+        sources_state: &mut RemoteSourcesState,
+    ) -> io::Result<()> {
+        let packages_json_file = PackageMetadata::path_to_packages_file();
         let remote_path = PACKAGE_METADATA_FILENAME;
-
         let remote_hash_url = std::format!("{0}/{1}.hash256", config.host_url, remote_path);
-        match PackageMetadata::get_remote_packages_hash(&remote_hash_url) {
-            Some(tmp_hash_str) => {
-                remote_hash_str = tmp_hash_str;
-            }
+
+        let packages_json_exists = Path::new(&packages_json_file).exists();
+        let mut hash_state = sources_state.get(&remote_hash_url);
+
+        if packages_json_exists && hash_state.is_fresh(config.metadata_refresh_interval_in_minutes)
+        {
+            info!("update_packages_json. checked recently, skipping remote hash check");
+            return Ok(());
+        }
+
+        let remote_hash_str = match PackageMetadata::get_remote_packages_hash(
+            client,
+            &remote_hash_url,
+            &mut hash_state,
+        ) {
+            Some(tmp_hash_str) => tmp_hash_str,
             None => {
                 info!("update_packages_json in get_remote_packages_hash call. received none");
-                should_download = false;
+                return Ok(());
             }
-        }
+        };
 
-        if should_download {
-            if !Path::new(&packages_json_file).exists() {
-                should_download = true;
-                info!(
-                    "update_packages_json. {:?} does not exist",
-                    packages_json_file
-                );
+        let mut should_download = true;
+        if !packages_json_exists {
+            info!(
+                "update_packages_json. {:?} does not exist",
+                packages_json_file
+            );
+        } else {
+            let hash_str = package::generate_hash_from_file_path(&packages_json_file)?;
+            info!(
+                "update_packages_json. found hash and remote hash: {0} {1}",
+                hash_str, remote_hash_str
+            );
+            if hash_str != remote_hash_str {
+                info!("update_packages_json. hash does not match. downloading");
             } else {
-                let hash_str = package::generate_hash_from_file_path(&packages_json_file)?;
-                info!("update_packages_json. found hash: {}", hash_str);
-
-                info!(
-                    "update_packages_json. found hash and remote hash: {0} {1}",
-                    hash_str, remote_hash_str
-                );
-                if hash_str != remote_hash_str {
-                    info!("update_packages_json. hash does not match. downloading");
-                    should_download = true;
-                } else {
-                    should_download = false;
-                }
+                should_download = false;
             }
         }
 
         if should_download {
             info!("update_packages_json. downloading new {}.json", remote_path);
 
-            let remote_packages_url = std::format!("{0}/{1}.json", config.host_url, remote_path);
-            let mut download_complete = false;
             let local_packages_temp_path = PackageMetadata::path_to_packages_file()
                 .with_file_name(std::format!("{}-temp.json", remote_path));
 
-            match reqwest::blocking::get(remote_packages_url) {
-                Ok(mut response) => {
-                    let mut dest = fs::File::create(&local_packages_temp_path)?;
-                    io::copy(&mut response, &mut dest)?;
-                    download_complete = true;
-                }
-                Err(err) => {
-                    error!("update_packages_json. download err: {:?}", err);
+            let mut download_complete = false;
+            for extension in hash_state.transport_extensions() {
+                let remote_packages_url =
+                    std::format!("{0}/{1}.{2}", config.host_url, remote_path, extension);
+
+                match PackageMetadata::download_packages_variant(
+                    client,
+                    &remote_packages_url,
+                    extension,
+                    &local_packages_temp_path,
+                ) {
+                    Ok(()) => {}
+                    Err(err) => {
+                        info!(
+                            "update_packages_json. download of {} failed: {:?}",
+                            remote_packages_url, err
+                        );
+                        continue;
+                    }
                 }
-            }
 
-            if download_complete {
                 let new_hash_str =
                     package::generate_hash_from_file_path(&local_packages_temp_path)?;
                 if new_hash_str == remote_hash_str {
-                    info!("update_packages_json. new downloaded hash matches");
-                    fs::rename(local_packages_temp_path, packages_json_file)?;
+                    info!(
+                        "update_packages_json. new downloaded hash matches for {}",
+                        remote_packages_url
+                    );
+                    fs::rename(&local_packages_temp_path, &packages_json_file)?;
+                    hash_state.transport_extension = Some(extension.to_string());
+                    download_complete = true;
+                    break;
                 } else {
-                    info!("update_packages_json. new downloaded hash does not match");
-                    fs::remove_file(local_packages_temp_path)?;
+                    info!(
+                        "update_packages_json. new downloaded hash does not match for {}",
+                        remote_packages_url
+                    );
                 }
             }
+
+            let _ = fs::remove_file(&local_packages_temp_path);
+
+            if !download_complete {
+                // Do not remember the hash validators, otherwise the next launch would
+                // get a 304 for the hash and never retry the failed download.
+                error!(
+                    "update_packages_json. no usable {}.json downloaded",
+                    remote_path
+                );
+                return Ok(());
+            }
         }
 
-        PackageMetadata::download_additional_remote_packages(&config)
+        hash_state.content_hash = Some(remote_hash_str);
+        hash_state.last_checked = Local::now().timestamp();
+        sources_state.set(&remote_hash_url, hash_state);
+
+        Ok(())
     }
 
-    pub fn download_additional_remote_packages(config: &config::Config) -> io::Result<()> {
+    fn download_additional_remote_packages(
+        config: &config::Config,
+        client: &reqwest::blocking::Client,
+        sources_state: &mut RemoteSourcesState,
+    ) -> Vec<RemoteSourceResult> {
+        let mut results = Vec::new();
+
         if let Some(additional_remote_packages) = &config.additional_remote_packages {
             for url_str in additional_remote_packages {
                 info!(
@@ -403,74 +813,233 @@ impl PackageMetadata {
                     url_str
                 );
 
-                let parsed_url = match Url::parse(url_str) {
-                    Ok(u) => u,
-                    Err(err) => {
-                        let error_str = format!(
-                            "download_additional_remote_packages. url parse err: {:?}",
-                            err
-                        );
-                        error!("{}", error_str);
-                        return Err(Error::other(error_str));
-                    }
-                };
-
-                let filename = match parsed_url.path_segments() {
-                    Some(mut segments) => match segments.next_back() {
-                        Some(segment) => segment,
-                        None => {
-                            let error_str =
-                                "download_additional_remote_packages. url last not found";
-                            error!("{}", error_str);
-                            return Err(Error::other(error_str));
-                        }
-                    },
-                    None => {
-                        let error_str =
-                            "download_additional_remote_packages. url path_segments not found";
-                        error!("{}", error_str);
-                        return Err(Error::other(error_str));
-                    }
-                };
-
-                let local_packages_path =
-                    PackageMetadata::path_to_packages_file().with_file_name(filename);
-
-                match reqwest::blocking::get(url_str) {
-                    Ok(mut response) => {
-                        let mut dest = fs::File::create(&local_packages_path)?;
-                        io::copy(&mut response, &mut dest)?;
-                        info!(
-                            "download_additional_remote_packages {} is saved to {:?}",
-                            url_str, local_packages_path
+                let status = PackageMetadata::download_additional_remote_package(
+                    config,
+                    client,
+                    sources_state,
+                    url_str,
+                );
+                match &status {
+                    RemoteSourceStatus::Ok => {}
+                    RemoteSourceStatus::StaleCache(err) => {
+                        warn!(
+                            "download_additional_remote_packages. {} using cached copy: {}",
+                            url_str, err
                         );
                     }
-                    Err(err) => {
-                        let error_str = format!(
-                            "download_additional_remote_packages. download err: {:?}",
-                            err
+                    RemoteSourceStatus::Failed(err) => {
+                        error!(
+                            "download_additional_remote_packages. {} failed: {}",
+                            url_str, err
                         );
-                        error!("{}", error_str);
-                        return Err(Error::other(error_str));
                     }
                 }
+
+                results.push(RemoteSourceResult {
+                    url: url_str.to_string(),
+                    status,
+                });
             }
         } else {
             info!("download_additional_remote_packages, no remote packages list given");
         }
 
-        Ok(())
+        results
     }
 
-    pub fn get_remote_packages_hash(remote_hash_url: &str) -> Option<String> {
-        let remote_hash_response = match reqwest::blocking::get(remote_hash_url) {
-            Ok(s) => s,
-            Err(err) => {
-                error!("get_remote_packages_hash error in get: {:?}", err);
-                return None;
+    fn download_additional_remote_package(
+        config: &config::Config,
+        client: &reqwest::blocking::Client,
+        sources_state: &mut RemoteSourcesState,
+        url_str: &str,
+    ) -> RemoteSourceStatus {
+        let local_packages_path = match PackageMetadata::path_to_remote_packages_file(url_str) {
+            Ok(path) => path,
+            Err(err) => return RemoteSourceStatus::Failed(err.to_string()),
+        };
+        let local_packages_temp_path = local_packages_path.with_extension("json-temp");
+        let cache_usable = PackageMetadata::read_remote_packages_file(&local_packages_path).is_ok();
+
+        let failed_status = |err: String| {
+            if cache_usable {
+                RemoteSourceStatus::StaleCache(err)
+            } else {
+                RemoteSourceStatus::Failed(err)
+            }
+        };
+
+        let mut source_state = sources_state.get(url_str);
+        if !cache_usable {
+            source_state = RemoteSourceState::default();
+        } else if source_state.is_fresh(config.metadata_refresh_interval_in_minutes) {
+            info!(
+                "download_additional_remote_packages {} checked recently, skipping",
+                url_str
+            );
+            return RemoteSourceStatus::Ok;
+        }
+
+        let mut response = match PackageMetadata::conditional_get(client, url_str, &source_state) {
+            Ok(response) => response,
+            Err(err) => return failed_status(format!("download error: {}", err)),
+        };
+
+        source_state.update_from_response(&response);
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            info!(
+                "download_additional_remote_packages {} not modified",
+                url_str
+            );
+            sources_state.set(url_str, source_state);
+            return RemoteSourceStatus::Ok;
+        }
+
+        let write_result = fs::File::create(&local_packages_temp_path)
+            .and_then(|mut dest| io::copy(&mut response, &mut dest));
+        if let Err(err) = write_result {
+            let _ = fs::remove_file(&local_packages_temp_path);
+            return failed_status(format!("write error: {}", err));
+        }
+
+        // Only replace the cached copy with a file that can actually be used.
+        if let Err(err) = PackageMetadata::read_remote_packages_file(&local_packages_temp_path) {
+            let _ = fs::remove_file(&local_packages_temp_path);
+            return failed_status(err.to_string());
+        }
+
+        if let Err(err) = fs::rename(&local_packages_temp_path, &local_packages_path) {
+            let _ = fs::remove_file(&local_packages_temp_path);
+            return failed_status(format!("rename error: {}", err));
+        }
+
+        info!(
+            "download_additional_remote_packages {} is saved to {:?}",
+            url_str, local_packages_path
+        );
+        sources_state.set(url_str, source_state);
+        RemoteSourceStatus::Ok
+    }
+
+    fn read_remote_packages_file(path: &Path) -> io::Result<PackageMetadata> {
+        let s = fs::read_to_string(path)?;
+        serde_json::from_str::<PackageMetadata>(&s)
+            .map_err(|err| Error::other(format!("parse error: {}", err)))
+    }
+
+    fn path_to_remote_packages_file(url_str: &str) -> io::Result<PathBuf> {
+        let parsed_url = Url::parse(url_str)
+            .map_err(|err| Error::other(format!("url parse error for {}: {}", url_str, err)))?;
+        let url_hash = package::generate_hash_from_string(&parsed_url.to_string())?;
+        let path = PackageMetadata::path_to_packages_file()
+            .with_file_name(format!("remote-packages-{}.json", url_hash));
+        PackageMetadata::migrate_legacy_remote_packages_file(&parsed_url, &path);
+        Ok(path)
+    }
+
+    // Remote packages used to be cached under the last segment of their url. The first time a
+    // source is used after the rename, its old cache file is moved to the new name, or removed
+    // when the new one already exists.
+    fn migrate_legacy_remote_packages_file(parsed_url: &Url, path: &Path) {
+        let Some(filename) = parsed_url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .filter(|filename| !filename.is_empty() && !filename.starts_with("remote-packages-"))
+        else {
+            return;
+        };
+
+        let packages_path = PackageMetadata::path_to_packages_file();
+        let legacy_path = packages_path.with_file_name(filename);
+        // The main packages file is never touched, even when a source url ends with its name.
+        if legacy_path == packages_path || !legacy_path.is_file() {
+            return;
+        }
+
+        if path.exists() {
+            match fs::remove_file(&legacy_path) {
+                Ok(()) => info!(
+                    "migrate_legacy_remote_packages_file. removed {:?}",
+                    legacy_path
+                ),
+                Err(err) => error!(
+                    "migrate_legacy_remote_packages_file. remove {:?} err: {:?}",
+                    legacy_path, err
+                ),
             }
+        } else {
+            match fs::rename(&legacy_path, path) {
+                Ok(()) => info!(
+                    "migrate_legacy_remote_packages_file. moved {:?} to {:?}",
+                    legacy_path, path
+                ),
+                Err(err) => error!(
+                    "migrate_legacy_remote_packages_file. rename {:?} err: {:?}",
+                    legacy_path, err
+                ),
+            }
+        }
+    }
+
+    fn download_packages_variant(
+        client: &reqwest::blocking::Client,
+        url: &str,
+        extension: &str,
+        dest_path: &Path,
+    ) -> io::Result<()> {
+        let response = client
+            .get(url)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(Error::other)?;
+
+        let mut reader: Box<dyn io::Read> = match extension {
+            "json.zst" => Box::new(zstd::stream::read::Decoder::new(response)?),
+            "json.gz" => Box::new(GzDecoder::new(response)),
+            _ => Box::new(response),
         };
 
+        let mut dest = fs::File::create(dest_path)?;
+        io::copy(&mut reader, &mut dest)?;
+        Ok(())
+    }
+
+    fn conditional_get(
+        client: &reqwest::blocking::Client,
+        url: &str,
+        source_state: &RemoteSourceState,
+    ) -> reqwest::Result<reqwest::blocking::Response> {
+        let mut request = client.get(url);
+        if let Some(etag) = &source_state.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &source_state.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        request.send()?.error_for_status()
+    }
+
+    fn get_remote_packages_hash(
+        client: &reqwest::blocking::Client,
+        remote_hash_url: &str,
+        hash_state: &mut RemoteSourceState,
+    ) -> Option<String> {
+        let remote_hash_response =
+            match PackageMetadata::conditional_get(client, remote_hash_url, hash_state) {
+                Ok(s) => s,
+                Err(err) => {
+                    error!("get_remote_packages_hash error in get: {:?}", err);
+                    return None;
+                }
+            };
+
+        if remote_hash_response.status() == StatusCode::NOT_MODIFIED {
+            info!("get_remote_packages_hash. remote hash not modified");
+            return hash_state.content_hash.clone();
+        }
+
+        hash_state.update_from_response(&remote_hash_response);
+
         let remote_hash_str = match remote_hash_response.text() {
             Ok(s) => s,
             Err(err) => {
@@ -491,6 +1060,26 @@ impl PackageMetadata {
 }
 
 impl Game {
+    /// Names of every engine the game can run with, including the ones from its choices.
+    pub fn engine_names(&self) -> Vec<String> {
+        let mut engine_names: Vec<String> = Vec::new();
+
+        if !self.engine_name.is_empty() {
+            engine_names.push(self.engine_name.to_string());
+        }
+
+        if let Some(choices) = &self.choices {
+            for choice in choices {
+                let engine_name = choice.engine_name.as_ref().unwrap_or(&choice.name);
+                if !engine_name.is_empty() && !engine_names.contains(engine_name) {
+                    engine_names.push(engine_name.to_string());
+                }
+            }
+        }
+
+        engine_names
+    }
+
     pub fn choices_with_notices(&mut self) -> Vec<SimpleEngineChoice> {
         let mut simple_choices: Vec<SimpleEngineChoice> = vec![];
         let mut add_choose_proton: bool = false;
@@ -608,6 +1197,15 @@ impl Game {
                 },
             );
         }
+
+        simple_choices.push(SimpleEngineChoice {
+            name: LAUNCH_OPTIONS_CHOICE.to_string(),
+            notices: vec![
+                "Environment variables, wrapper commands and extra arguments for this game."
+                    .to_string(),
+                "Example: DXVK_HUD=fps gamemoderun %command% -windowed".to_string(),
+            ],
+        });
         simple_choices
     }
 
@@ -645,7 +1243,15 @@ impl Game {
         None
     }
 
+    /// Name the setup state is recorded under: the engine choice that was applied, or the
+    /// engine name for games without choices.
+    pub fn choice_name(&self) -> &str {
+        self.engine_choice.as_deref().unwrap_or(&self.engine_name)
+    }
+
     pub fn update_from_choice(&mut self, engine_choice: &EngineChoice) {
+        self.engine_choice = Some(engine_choice.name.clone());
+
         if let Some(engine_name) = &engine_choice.engine_name {
             self.engine_name = engine_name.to_string();
         }
@@ -678,6 +1284,14 @@ impl Game {
             self.default_proton_choice = engine_choice.default_proton_choice.clone();
         }
 
+        if engine_choice.proton_constraints.is_some() {
+            self.proton_constraints = engine_choice.proton_constraints.clone();
+        }
+
+        if engine_choice.runner.is_some() {
+            self.runner = engine_choice.runner.clone();
+        }
+
         if engine_choice.command_current_dir.is_some() {
             self.command_current_dir = engine_choice.command_current_dir.clone();
         }
@@ -685,3 +1299,138 @@ impl Game {
         self.command_relative_path = engine_choice.command_relative_path;
     }
 }
+
+impl RemoteSourceState {
+    pub fn is_fresh(&self, refresh_interval_in_minutes: u32) -> bool {
+        if refresh_interval_in_minutes == 0 || self.last_checked == 0 {
+            return false;
+        }
+
+        let elapsed = Local::now().timestamp() - self.last_checked;
+        elapsed >= 0 && elapsed < i64::from(refresh_interval_in_minutes) * 60
+    }
+
+    // The metadata variants to try, starting with the one that worked last time, so hosts
+    // without compressed variants are not asked for them on every download.
+    fn transport_extensions(&self) -> Vec<&'static str> {
+        let mut extensions = METADATA_TRANSPORT_EXTENSIONS.to_vec();
+        if let Some(idx) = extensions
+            .iter()
+            .position(|extension| Some(*extension) == self.transport_extension.as_deref())
+        {
+            let extension = extensions.remove(idx);
+            extensions.insert(0, extension);
+        }
+        extensions
+    }
+
+    fn update_from_response(&mut self, response: &reqwest::blocking::Response) {
+        if response.status() != StatusCode::NOT_MODIFIED {
+            self.etag = response
+                .headers()
+                .get(ETAG)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string());
+            self.last_modified = response
+                .headers()
+                .get(LAST_MODIFIED)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string());
+        }
+        self.last_checked = Local::now().timestamp();
+    }
+}
+
+impl RemoteSourcesState {
+    pub fn from_state_file() -> RemoteSourcesState {
+        let state_file_path = RemoteSourcesState::state_file_path();
+        match fs::read_to_string(state_file_path) {
+            Ok(s) => match serde_json::from_str::<RemoteSourcesState>(&s) {
+                Ok(state) => state,
+                Err(err) => {
+                    error!("error parsing remote sources state: {:?}", err);
+                    Default::default()
+                }
+            },
+            Err(_) => Default::default(),
+        }
+    }
+
+    pub fn get(&self, url: &str) -> RemoteSourceState {
+        self.sources.get(url).cloned().unwrap_or_default()
+    }
+
+    pub fn set(&mut self, url: &str, source_state: RemoteSourceState) {
+        self.sources.insert(url.to_string(), source_state);
+    }
+
+    pub fn save(&self) {
+        let state_file_path = RemoteSourcesState::state_file_path();
+        match serde_json::to_string_pretty(self) {
+            Ok(state_json) => {
+                if let Err(err) = fs::write(&state_file_path, state_json) {
+                    error!(
+                        "error writing remote sources state to {:?}: {:?}",
+                        state_file_path, err
+                    );
+                }
+            }
+            Err(err) => {
+                error!("error serializing remote sources state: {:?}", err);
+            }
+        }
+    }
+
+    fn state_file_path() -> PathBuf {
+        PackageMetadata::path_to_packages_file().with_file_name(REMOTE_SOURCES_STATE_FILENAME)
+    }
+}
+
+impl RemoteSourceResult {
+    pub fn warning_message(&self) -> Option<String> {
+        match &self.status {
+            RemoteSourceStatus::Ok => None,
+            RemoteSourceStatus::StaleCache(err) => Some(format!(
+                "{} could not be updated, using cached copy ({})",
+                self.url, err
+            )),
+            RemoteSourceStatus::Failed(err) => Some(format!(
+                "{} could not be loaded and was skipped ({})",
+                self.url, err
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transport_extensions_start_with_the_last_one_used() {
+        let mut source_state = RemoteSourceState::default();
+        assert_eq!(
+            source_state.transport_extensions(),
+            vec!["json.zst", "json.gz", "json"]
+        );
+
+        source_state.transport_extension = Some("json".to_string());
+        assert_eq!(
+            source_state.transport_extensions(),
+            vec!["json", "json.zst", "json.gz"]
+        );
+
+        source_state.transport_extension = Some("json.gz".to_string());
+        assert_eq!(
+            source_state.transport_extensions(),
+            vec!["json.gz", "json.zst", "json"]
+        );
+
+        // A variant that is no longer known is ignored.
+        source_state.transport_extension = Some("json.xz".to_string());
+        assert_eq!(
+            source_state.transport_extensions(),
+            vec!["json.zst", "json.gz", "json"]
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use chrono::{DateTime, Local};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::info;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::config;
+use crate::package;
+
+static PROTON_PREFIXES_DIR: &str = "proton-prefixes";
+static WINE_PREFIXES_DIR: &str = "wine-prefixes";
+static PREFIX_BACKUPS_DIR: &str = "prefix-backups";
+static PROTON_VERSION_FILE: &str = "version";
+
+pub static STEAM_COMPAT_DATA_PATH: &str = "STEAM_COMPAT_DATA_PATH";
+pub static LUX_MANAGE_PROTON_PREFIX: &str = "LUX_MANAGE_PROTON_PREFIX";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PrefixKind {
+    Proton,
+    Wine,
+}
+
+impl PrefixKind {
+    fn dir_name(&self) -> &'static str {
+        match self {
+            PrefixKind::Proton => PROTON_PREFIXES_DIR,
+            PrefixKind::Wine => WINE_PREFIXES_DIR,
+        }
+    }
+}
+
+pub struct PrefixInfo {
+    pub path: PathBuf,
+    pub exists: bool,
+    pub size_in_bytes: u64,
+    pub modified: Option<DateTime<Local>>,
+    pub proton_version: Option<String>,
+}
+
+// Whether engine and setup executables run through Proton get their own compat data directory,
+// instead of sharing the one Steam set up for the original game.
+pub fn should_manage_proton_prefix() -> bool {
+    match env::var(LUX_MANAGE_PROTON_PREFIX) {
+        Ok(val) if val == "1" => {
+            info!("should_manage_proton_prefix. enabled because of env");
+            return true;
+        }
+        Ok(val) if val == "0" => {
+            info!("should_manage_proton_prefix. disabled because of env");
+            return false;
+        }
+        _ => {}
+    }
+
+    let config = config::Config::from_config_file();
+    config.manage_proton_prefix
+}
+
+fn prefixes_path(kind: PrefixKind) -> io::Result<PathBuf> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("luxtorpeda");
+    let data_home = xdg_dirs
+        .get_data_home()
+        .ok_or_else(|| Error::other("no data home directory"))?;
+    Ok(data_home.join(kind.dir_name()))
+}
+
+pub fn prefix_path(kind: PrefixKind, app_id: &str) -> io::Result<PathBuf> {
+    if app_id.is_empty() {
+        return Err(Error::other("app_id is required"));
+    }
+    Ok(prefixes_path(kind)?.join(app_id))
+}
+
+// Returns the prefix directory, creating it if needed.
+pub fn place_prefix(kind: PrefixKind, app_id: &str) -> io::Result<PathBuf> {
+    let path = prefix_path(kind, app_id)?;
+    package::create_dir_or_show_error(&path);
+    Ok(path)
+}
+
+pub fn prefix_info(kind: PrefixKind, app_id: &str) -> io::Result<PrefixInfo> {
+    let path = prefix_path(kind, app_id)?;
+    let exists = path.is_dir();
+
+    let mut size_in_bytes = 0;
+    let mut modified = None;
+    let mut proton_version = None;
+
+    if exists {
+        for entry in WalkDir::new(&path).into_iter().filter_map(|e| e.ok()) {
+            if let Ok(metadata) = entry.metadata() {
+                if metadata.is_file() {
+                    size_in_bytes += metadata.len();
+                }
+                if let Ok(entry_modified) = metadata.modified() {
+                    let entry_modified: DateTime<Local> = entry_modified.into();
+                    if Some(entry_modified) > modified {
+                        modified = Some(entry_modified);
+                    }
+                }
+            }
+        }
+
+        // Proton records the version that last updated the prefix.
+        proton_version = fs::read_to_string(path.join(PROTON_VERSION_FILE))
+            .ok()
+            .map(|version| version.trim().to_string())
+            .filter(|version| !version.is_empty());
+    }
+
+    Ok(PrefixInfo {
+        path,
+        exists,
+        size_in_bytes,
+        modified,
+        proton_version,
+    })
+}
+
+pub fn reset_prefix(kind: PrefixKind, app_id: &str) -> io::Result<()> {
+    let path = prefix_path(kind, app_id)?;
+    if path.exists() {
+        info!("reset_prefix. removing {:?}", path);
+        fs::remove_dir_all(&path)?;
+    }
+    Ok(())
+}
+
+// Writes the prefix to a .tar.gz file, in the backups directory unless a destination is given.
+pub fn backup_prefix(
+    kind: PrefixKind,
+    app_id: &str,
+    destination: Option<&Path>,
+) -> io::Result<PathBuf> {
+    let path = prefix_path(kind, app_id)?;
+    if !path.is_dir() {
+        return Err(Error::other(format!(
+            "no prefix found at {}",
+            path.display()
+        )));
+    }
+
+    let backup_path = match destination {
+        Some(destination) => destination.to_path_buf(),
+        None => {
+            let backups_path = prefixes_path(kind)?
+                .parent()
+                .ok_or_else(|| Error::other("no data home directory"))?
+                .join(PREFIX_BACKUPS_DIR);
+            package::create_dir_or_show_error(&backups_path);
+            backups_path.join(format!(
+                "{}-{}-{}.tar.gz",
+                kind.dir_name(),
+                app_id,
+                Local::now().format("%Y%m%d-%H%M%S")
+            ))
+        }
+    };
+
+    info!("backup_prefix. writing {:?} to {:?}", path, backup_path);
+
+    let backup_file = fs::File::create(&backup_path)?;
+    let encoder = GzEncoder::new(backup_file, Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    // Prefixes link drive letters to the host, those links are kept as links.
+    builder.follow_symlinks(false);
+    builder.append_dir_all(app_id, &path)?;
+    builder.into_inner()?.finish()?;
+
+    Ok(backup_path)
+}
@@ -0,0 +1,303 @@
+use log::{error, info};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::collections::VecDeque;
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::client;
+
+// How many lines of output are kept for the error shown when a process fails.
+const LAST_LINES_KEPT: usize = 20;
+const MAX_LINE_LENGTH: usize = 500;
+// Processes started by the child, such as wineserver, can keep the pipes open after it exits.
+const READER_WAIT: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// Time between SIGTERM and SIGKILL when stopping a process group.
+const TERMINATE_GRACE: Duration = Duration::from_secs(5);
+
+// Process groups of the children that are running, each child leads its own group so that
+// everything it starts (such as Proton and wine processes) can be stopped together.
+static RUNNING_GROUPS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+// Set while cancel_all is stopping process groups in the background.
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// A child process whose stdout and stderr are read line by line, written to the log and sent
+/// to the progress screen as log lines.
+pub struct OutputProcess {
+    child: Child,
+    readers: Vec<JoinHandle<()>>,
+    last_lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+pub struct ProcessResult {
+    pub status: ExitStatus,
+    pub last_lines: Vec<String>,
+    pub timed_out: bool,
+    pub cancelled: bool,
+}
+
+impl ProcessResult {
+    /// Error message for a failed process, ending with the last lines it printed.
+    pub fn failure_message(&self, context: &str) -> String {
+        let mut message = if self.cancelled {
+            std::format!("{} was cancelled", context)
+        } else if self.timed_out {
+            std::format!("{} was stopped after reaching its timeout", context)
+        } else {
+            std::format!("{} failed with {}", context, self.status)
+        };
+        if !self.last_lines.is_empty() {
+            message.push_str("\nLast output:\n");
+            message.push_str(&self.last_lines.join("\n"));
+        }
+        message
+    }
+}
+
+impl OutputProcess {
+    pub fn spawn(
+        command: &mut Command,
+        name: &str,
+        sender: &Sender<String>,
+    ) -> io::Result<OutputProcess> {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let mut process = OutputProcess::spawn_in_group(command)?;
+
+        if let Some(stdout) = process.child.stdout.take() {
+            let reader = read_lines(stdout, name, sender, &process.last_lines);
+            process.readers.push(reader);
+        }
+        if let Some(stderr) = process.child.stderr.take() {
+            let reader = read_lines(stderr, name, sender, &process.last_lines);
+            process.readers.push(reader);
+        }
+
+        Ok(process)
+    }
+
+    /// Starts the command with the output going where the output of luxtorpeda goes.
+    pub fn spawn_without_output(command: &mut Command) -> io::Result<OutputProcess> {
+        OutputProcess::spawn_in_group(command)
+    }
+
+    fn spawn_in_group(command: &mut Command) -> io::Result<OutputProcess> {
+        // Held until the group is added, so cancel_all either sees the new group or the child
+        // is never started.
+        let mut running_groups = RUNNING_GROUPS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if is_cancelled() {
+            return Err(io::Error::other("cancelled"));
+        }
+
+        let child = command.process_group(0).spawn()?;
+        running_groups.push(child.id());
+        drop(running_groups);
+
+        Ok(OutputProcess {
+            child,
+            readers: Vec::new(),
+            last_lines: Arc::new(Mutex::new(VecDeque::new())),
+        })
+    }
+
+    /// Waits for the process to exit. When the timeout is reached, the process group is
+    /// stopped and the result is marked as timed out.
+    pub fn wait(mut self, timeout: Option<Duration>) -> io::Result<ProcessResult> {
+        let group = self.child.id();
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut timed_out = false;
+
+        let status = loop {
+            if let Some(status) = self.child.try_wait()? {
+                break status;
+            }
+            if !timed_out && deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                error!("process. group {} reached its timeout, stopping it", group);
+                timed_out = true;
+                terminate_groups(&[group]);
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+
+        if let Ok(mut running_groups) = RUNNING_GROUPS.lock() {
+            running_groups.retain(|running_group| *running_group != group);
+        }
+
+        // The readers finish once the process and anything it started close the pipes. Readers
+        // still running after a short wait are left behind to log the rest.
+        let deadline = Instant::now() + READER_WAIT;
+        while Instant::now() < deadline && self.readers.iter().any(|reader| !reader.is_finished()) {
+            thread::sleep(Duration::from_millis(20));
+        }
+        for reader in self.readers.drain(..) {
+            if reader.is_finished() {
+                let _ = reader.join();
+            }
+        }
+
+        let last_lines = match self.last_lines.lock() {
+            Ok(last_lines) => last_lines.iter().cloned().collect(),
+            Err(_) => Vec::new(),
+        };
+        Ok(ProcessResult {
+            status,
+            last_lines,
+            timed_out,
+            cancelled: is_cancelled(),
+        })
+    }
+}
+
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Whether process groups stopped by `cancel_all` may still be running.
+pub fn is_stopping() -> bool {
+    STOPPING.load(Ordering::SeqCst)
+}
+
+/// Stops every running child process group and keeps new ones from starting. The groups are
+/// stopped on a separate thread, so this returns right away; `is_stopping` tells when it is done.
+pub fn cancel_all() {
+    let running_groups = cancel_groups();
+    if running_groups.is_empty() {
+        return;
+    }
+
+    STOPPING.store(true, Ordering::SeqCst);
+    thread::spawn(move || {
+        terminate_groups(&running_groups);
+        STOPPING.store(false, Ordering::SeqCst);
+    });
+}
+
+// Marks everything as cancelled and returns the process groups to stop.
+fn cancel_groups() -> Vec<u32> {
+    // Set under the lock, so a child being spawned is either listed here or not started.
+    let running_groups = RUNNING_GROUPS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    CANCELLED.store(true, Ordering::SeqCst);
+    let running_groups = running_groups.clone();
+    if !running_groups.is_empty() {
+        info!("process. cancelling process groups {:?}", running_groups);
+    }
+    running_groups
+}
+
+// Sends SIGTERM to the process groups, then SIGKILL to the ones still running after the grace
+// period.
+fn terminate_groups(groups: &[u32]) {
+    let group_exists = |group: u32| unsafe { libc::kill(-(group as i32), 0) == 0 };
+
+    for group in groups {
+        unsafe {
+            libc::kill(-(*group as i32), libc::SIGTERM);
+        }
+    }
+
+    let deadline = Instant::now() + TERMINATE_GRACE;
+    while Instant::now() < deadline && groups.iter().any(|group| group_exists(*group)) {
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    for group in groups {
+        if group_exists(*group) {
+            info!("process. group {} did not stop, killing it", group);
+            unsafe {
+                libc::kill(-(*group as i32), libc::SIGKILL);
+            }
+        }
+    }
+}
+
+/// Stops the child processes when luxtorpeda itself is asked to stop, for example when the
+/// game is stopped from Steam, then exits.
+pub fn handle_termination_signals() -> io::Result<()> {
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            info!(
+                "process. received signal {}, stopping child processes",
+                signal
+            );
+            // Nothing else is waiting for the groups here, so they are stopped before exiting.
+            terminate_groups(&cancel_groups());
+            std::process::exit(128 + signal);
+        }
+    });
+    Ok(())
+}
+
+/// Runs a command to completion with its output streamed, see `OutputProcess`.
+pub fn run_with_output(
+    command: &mut Command,
+    name: &str,
+    sender: &Sender<String>,
+    timeout: Option<Duration>,
+) -> io::Result<ProcessResult> {
+    OutputProcess::spawn(command, name, sender)?.wait(timeout)
+}
+
+fn read_lines<R: Read + Send + 'static>(
+    stream: R,
+    name: &str,
+    sender: &Sender<String>,
+    last_lines: &Arc<Mutex<VecDeque<String>>>,
+) -> JoinHandle<()> {
+    let name = name.to_string();
+    let sender = sender.clone();
+    let last_lines = Arc::clone(last_lines);
+
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stream);
+        let mut buf = Vec::new();
+
+        loop {
+            buf.clear();
+            match reader.read_until(b'\n', &mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+
+            // Progress bars redraw with \r, only the last state of the line is kept.
+            let line = String::from_utf8_lossy(&buf);
+            let line = line.trim_end_matches(['\n', '\r']);
+            let line = line.rsplit('\r').next().unwrap_or(line).trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            let line: String = line.chars().take(MAX_LINE_LENGTH).collect();
+
+            info!("{}: {}", name, line);
+
+            let status_obj = client::StatusObj {
+                log_line: Some(std::format!("{}: {}", name, line)),
+                ..Default::default()
+            };
+            if let Ok(status_str) = serde_json::to_string(&status_obj) {
+                // The client may already be closed while the engine keeps running.
+                let _ = sender.send(status_str);
+            }
+
+            if let Ok(mut last_lines) = last_lines.lock() {
+                if last_lines.len() == LAST_LINES_KEPT {
+                    last_lines.pop_front();
+                }
+                last_lines.push_back(line);
+            }
+        }
+    })
+}
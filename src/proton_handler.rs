@@ -1,12 +1,36 @@
 use keyvalues_serde::from_str_with_key;
+use log::{error, info};
 use new_vdf_parser::appinfo_vdf_parser::open_appinfo_vdf;
 use serde_json::{Map, Value};
+use std::cmp::Ordering;
+use std::env;
+use std::fmt;
 use std::fs;
+use std::io;
+use std::io::{Error, Read};
 use std::path::{Path, PathBuf};
-extern crate steamlocate;
-use steamlocate::SteamDir;
 
-const EXCLUDED_TOOLS: [&'static str; 7] = [
+use crate::package_metadata::ProtonConstraints;
+
+const STEAM_EXTRA_COMPAT_TOOLS_PATHS: &str = "STEAM_EXTRA_COMPAT_TOOLS_PATHS";
+
+// compatibilitytools.d locations besides the one in the running Steam install, in order of
+// precedence. Relative paths are below the home directory.
+const USER_COMPATIBILITYTOOLSD_PATHS: [&str; 3] = [
+    ".steam/root/compatibilitytools.d",
+    ".local/share/Steam/compatibilitytools.d",
+    ".var/app/com.valvesoftware.Steam/data/Steam/compatibilitytools.d",
+];
+const SYSTEM_COMPATIBILITYTOOLSD_PATHS: [&str; 2] = [
+    "/usr/local/share/steam/compatibilitytools.d",
+    "/usr/share/steam/compatibilitytools.d",
+];
+
+// appinfo.vdf header magic of the formats the parser supports.
+const APPINFO_MAGIC_V28: u32 = 0x07564428;
+const APPINFO_MAGIC_V29: u32 = 0x07564429;
+
+const EXCLUDED_TOOLS: [&str; 7] = [
     "legacyruntime",
     "boxtron",
     "roberta",
@@ -30,7 +54,7 @@ impl JsonExt for serde_json::Value {
     }
 }
 
-fn get_app_info<'a>(appinfo_json: &'a Map<String, Value>, target_appid: u64) -> Option<&'a Value> {
+fn get_app_info(appinfo_json: &Map<String, Value>, target_appid: u64) -> Option<&Value> {
     appinfo_json
         .get("entries")?
         .as_array()?
@@ -38,7 +62,7 @@ fn get_app_info<'a>(appinfo_json: &'a Map<String, Value>, target_appid: u64) ->
         .find(|entry| entry.u64_at("appid") == Some(target_appid))
 }
 
-fn compat_tools<'a>(manifest: &'a Value) -> impl Iterator<Item = (&'a str, &'a Value)> {
+fn compat_tools(manifest: &Value) -> impl Iterator<Item = (&str, &Value)> {
     manifest["extended"]
         .get("compat_tools")
         .and_then(|v| v.as_object())
@@ -53,6 +77,303 @@ pub struct Tool {
     pub alias: String,        // example: "proton_9"
     pub display_name: String, // example: "Proton 9.0-4"
     pub commandline: String, // example: "/home/mv/.steam/debian-installation/steamapps/common/Proton 9.0 (Beta)/proton"
+    pub source: ToolSource,
+    pub version: Option<ToolVersion>, // example: 9.0.4
+    pub vendor: ToolVendor,
+    pub install_path: PathBuf, // example: "/home/mv/.steam/debian-installation/steamapps/common/Proton 9.0 (Beta)"
+    pub official: bool,        // distributed by Valve through Steam
+}
+
+impl Tool {
+    fn new(
+        alias: &str,
+        display_name: &str,
+        install_path: &Path,
+        commandline: String,
+        source: ToolSource,
+    ) -> Tool {
+        let official = matches!(source, ToolSource::SteamLibrary(_));
+        let vendor = ToolVendor::from_tool(alias, display_name, official);
+
+        Tool {
+            alias: alias.to_string(),
+            display_name: display_name.to_string(),
+            commandline,
+            source,
+            version: ToolVersion::parse(display_name).or_else(|| ToolVersion::parse(alias)),
+            vendor,
+            install_path: install_path.to_path_buf(),
+            official,
+        }
+    }
+
+    // A numbered release, as opposed to Experimental, Hotfix or beta builds.
+    pub fn is_stable(&self) -> bool {
+        self.version
+            .as_ref()
+            .map(|version| !version.prerelease)
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ToolVendor {
+    Valve,
+    GE,
+    Custom,
+}
+
+impl ToolVendor {
+    fn from_tool(alias: &str, display_name: &str, official: bool) -> ToolVendor {
+        if official {
+            return ToolVendor::Valve;
+        }
+
+        let names = format!("{} {}", alias, display_name).to_lowercase();
+        if names.contains("ge-proton") || names.contains("proton-ge") {
+            ToolVendor::GE
+        } else {
+            ToolVendor::Custom
+        }
+    }
+}
+
+impl fmt::Display for ToolVendor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ToolVendor::Valve => write!(f, "Valve"),
+            ToolVendor::GE => write!(f, "GE"),
+            ToolVendor::Custom => write!(f, "Custom"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ToolVersion {
+    pub components: Vec<u32>, // example: [9, 0, 4] for "Proton 9.0-4", [9, 20] for "GE-Proton9-20"
+    pub prerelease: bool,
+}
+
+impl ToolVersion {
+    // Reads the first run of numbers separated by '.', '-' or '_' in a tool name.
+    pub fn parse(name: &str) -> Option<ToolVersion> {
+        let start = name.find(|c: char| c.is_ascii_digit())?;
+        let version_str: String = name[start..]
+            .chars()
+            .take_while(|c| c.is_ascii_digit() || *c == '.' || *c == '-' || *c == '_')
+            .collect();
+
+        let components: Vec<u32> = version_str
+            .split(['.', '-', '_'])
+            .map_while(|part| part.parse::<u32>().ok())
+            .collect();
+        if components.is_empty() {
+            return None;
+        }
+
+        let lower = name.to_lowercase();
+        let prerelease = ["beta", "experimental", "hotfix", "-rc"]
+            .iter()
+            .any(|tag| lower.contains(tag));
+
+        Some(ToolVersion {
+            components,
+            prerelease,
+        })
+    }
+}
+
+impl Ord for ToolVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.components
+            .cmp(&other.components)
+            .then_with(|| other.prerelease.cmp(&self.prerelease))
+    }
+}
+
+impl PartialOrd for ToolVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for ToolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let components: Vec<String> = self.components.iter().map(|c| c.to_string()).collect();
+        write!(f, "{}", components.join("."))
+    }
+}
+
+// Groups tools by vendor, newest version first within each vendor.
+pub fn sort_tools(tools: &mut [Tool]) {
+    tools.sort_by(|a, b| {
+        a.vendor
+            .cmp(&b.vendor)
+            .then_with(|| b.version.cmp(&a.version))
+            .then_with(|| a.display_name.cmp(&b.display_name))
+    });
+}
+
+fn tool_matches(tool: &Tool, name: &str) -> bool {
+    tool.alias == name || tool.display_name == name
+}
+
+// Returns why a tool is outside the Proton versions supported by a game, if it is.
+pub fn check_tool_constraints(tool: &Tool, constraints: &ProtonConstraints) -> Option<String> {
+    if constraints
+        .blocked
+        .iter()
+        .any(|blocked| tool_matches(tool, blocked))
+    {
+        return Some(format!("{} is known not to work", tool.display_name));
+    }
+
+    if let Some(allowed) = &constraints.allowed {
+        if !allowed.is_empty() && !allowed.iter().any(|allowed| tool_matches(tool, allowed)) {
+            return Some(format!(
+                "{} is not one of the supported versions: {}",
+                tool.display_name,
+                allowed.join(", ")
+            ));
+        }
+    }
+
+    if let Some(minimum_version_str) = &constraints.minimum_version {
+        // Tools without a version number, such as Proton Experimental, cannot be compared.
+        if let (Some(minimum_version), Some(version)) =
+            (ToolVersion::parse(minimum_version_str), &tool.version)
+        {
+            if version.components < minimum_version.components {
+                return Some(format!(
+                    "{} is older than the minimum supported version {}",
+                    tool.display_name, minimum_version_str
+                ));
+            }
+        }
+    }
+
+    None
+}
+
+// Picks the installed tool that best fits the constraints: the first installed entry of the
+// allowed list, otherwise the newest stable Valve Proton, otherwise the newest matching tool.
+pub fn find_best_tool<'a>(tools: &'a [Tool], constraints: &ProtonConstraints) -> Option<&'a Tool> {
+    let matching: Vec<&Tool> = tools
+        .iter()
+        .filter(|tool| check_tool_constraints(tool, constraints).is_none())
+        .collect();
+
+    if let Some(allowed) = &constraints.allowed {
+        for allowed in allowed {
+            if let Some(tool) = matching.iter().find(|tool| tool_matches(tool, allowed)) {
+                return Some(*tool);
+            }
+        }
+    }
+
+    matching
+        .iter()
+        .filter(|tool| tool.vendor == ToolVendor::Valve && tool.is_stable())
+        .max_by(|a, b| a.version.cmp(&b.version))
+        .or_else(|| matching.iter().find(|tool| tool.is_stable()))
+        .or_else(|| matching.first())
+        .copied()
+}
+
+pub fn find_newest_stable_valve_tool(tools: &[Tool]) -> Option<&Tool> {
+    tools
+        .iter()
+        .filter(|tool| tool.vendor == ToolVendor::Valve && tool.is_stable())
+        .max_by(|a, b| a.version.cmp(&b.version))
+}
+
+// Where a tool was found.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ToolSource {
+    SteamLibrary(PathBuf),        // example: "/home/mv/.steam/debian-installation"
+    CompatibilityToolsD(PathBuf), // example: "/usr/share/steam/compatibilitytools.d"
+}
+
+impl fmt::Display for ToolSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ToolSource::SteamLibrary(path) => write!(f, "Steam library {}", path.display()),
+            ToolSource::CompatibilityToolsD(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+// Outcome of looking for tools in one place, so a missing or unreadable source can be reported
+// without hiding the tools found elsewhere.
+#[derive(Clone, Debug)]
+pub struct ToolSourceDiagnostic {
+    pub source: String,
+    pub path: PathBuf,
+    pub tools_found: usize,
+    pub error: Option<String>,
+}
+
+impl ToolSourceDiagnostic {
+    pub fn message(&self) -> String {
+        match &self.error {
+            Some(err) => format!("{} ({}): {}", self.source, self.path.display(), err),
+            None => format!(
+                "{} ({}): {} tools found",
+                self.source,
+                self.path.display(),
+                self.tools_found
+            ),
+        }
+    }
+}
+
+pub struct ProtonDiscovery {
+    pub tools: Vec<Tool>,
+    pub diagnostics: Vec<ToolSourceDiagnostic>,
+}
+
+impl ProtonDiscovery {
+    pub fn diagnostics_message(&self) -> String {
+        self.diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.message())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn add_source(
+        &mut self,
+        source: &str,
+        path: PathBuf,
+        result: Result<Vec<Tool>, Box<dyn std::error::Error>>,
+    ) {
+        let mut diagnostic = ToolSourceDiagnostic {
+            source: source.to_string(),
+            path,
+            tools_found: 0,
+            error: None,
+        };
+
+        match result {
+            Ok(tools) => {
+                for tool in tools {
+                    let duplicate = self.tools.iter().any(|existing| {
+                        existing.alias == tool.alias || existing.commandline == tool.commandline
+                    });
+                    if !duplicate {
+                        diagnostic.tools_found += 1;
+                        self.tools.push(tool);
+                    }
+                }
+            }
+            Err(err) => {
+                diagnostic.error = Some(err.to_string());
+            }
+        }
+
+        info!("proton discovery. {}", diagnostic.message());
+        self.diagnostics.push(diagnostic);
+    }
 }
 
 fn get_commandline(path: &impl AsRef<Path>) -> Result<Option<String>, Box<dyn std::error::Error>> {
@@ -80,12 +401,52 @@ pub fn find_tool_by_name<'a>(tools: &'a [Tool], display_name: &str) -> Option<&'
     tools.iter().find(|t| t.display_name == display_name)
 }
 
-// List tools from .steam/steam/compatibilitytools.d
-pub fn list_compatibilitytoolsd(steam_path: &str) -> Result<Vec<Tool>, Box<dyn std::error::Error>> {
+// All compatibilitytools.d directories to scan, starting with .steam/steam/compatibilitytools.d
+pub fn list_compatibilitytoolsd_paths(steam_path: &str) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(steam_path).join("compatibilitytools.d")];
+
+    if let Some(home) = env::var_os("HOME") {
+        let home = PathBuf::from(home);
+        for path in USER_COMPATIBILITYTOOLSD_PATHS {
+            paths.push(home.join(path));
+        }
+    }
+
+    if let Ok(extra_paths) = env::var(STEAM_EXTRA_COMPAT_TOOLS_PATHS) {
+        for path in extra_paths.split(':').filter(|path| !path.is_empty()) {
+            paths.push(PathBuf::from(path));
+        }
+    }
+
+    for path in SYSTEM_COMPATIBILITYTOOLSD_PATHS {
+        paths.push(PathBuf::from(path));
+    }
+
+    // ~/.steam/root is usually a symlink to the Steam install, so compare resolved paths.
+    let mut resolved_paths: Vec<PathBuf> = Vec::new();
+    paths.retain(|path| {
+        let resolved = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+        if resolved_paths.contains(&resolved) {
+            false
+        } else {
+            resolved_paths.push(resolved);
+            true
+        }
+    });
+
+    paths
+}
+
+// List tools from a compatibilitytools.d directory
+pub fn list_compatibilitytoolsd(
+    compattoolsd: &Path,
+) -> Result<Vec<Tool>, Box<dyn std::error::Error>> {
     let mut tools = Vec::new();
 
-    let compattoolsd = PathBuf::from(steam_path).join("compatibilitytools.d");
-    let paths = fs::read_dir(&compattoolsd)?;
+    if !compattoolsd.exists() {
+        return Err("directory not found".into());
+    }
+    let paths = fs::read_dir(compattoolsd)?;
 
     for path in paths {
         let tool_dir = path?.path();
@@ -97,55 +458,73 @@ pub fn list_compatibilitytoolsd(steam_path: &str) -> Result<Vec<Tool>, Box<dyn s
             }
         }
 
-        let compatvdfpath = tool_dir.join("compatibilitytool.vdf");
-        if !compatvdfpath.exists() {
-            continue;
+        // A single broken tool should not hide the others.
+        match read_compatibilitytool(compattoolsd, &tool_dir) {
+            Ok(dir_tools) => tools.extend(dir_tools),
+            Err(err) => {
+                error!("list_compatibilitytoolsd. skipping {:?}: {}", tool_dir, err);
+            }
         }
+    }
 
-        let vdf = fs::read_to_string(&compatvdfpath)?;
-        let (root, _key) = from_str_with_key::<serde_json::Map<String, serde_json::Value>>(&vdf)?;
-
-        // Look inside compat_tools
-        if let Some(compat_tools) = root.get("compat_tools").and_then(|v| v.as_object()) {
-            for (alias, entry) in compat_tools {
-                if let Some(entry) = entry.as_object() {
-                    // Filter out non-Windows tools
-                    if entry
-                        .get("from_oslist")
-                        .and_then(|v| v.as_str())
-                        .map(|s| s != "windows")
-                        .unwrap_or(true)
-                    {
-                        continue;
-                    }
+    Ok(tools)
+}
 
-                    // Get display name (what is shown in Steam GUI)
-                    let display_name = entry
-                        .get("display_name")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or(alias);
+fn read_compatibilitytool(
+    compattoolsd: &Path,
+    tool_dir: &Path,
+) -> Result<Vec<Tool>, Box<dyn std::error::Error>> {
+    let mut tools = Vec::new();
 
-                    // Get path to the Proton tool
-                    let install_path = entry
-                        .get("install_path")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or(".");
+    let compatvdfpath = tool_dir.join("compatibilitytool.vdf");
+    if !compatvdfpath.exists() {
+        return Ok(tools);
+    }
 
-                    let resolved_path = tool_dir.join(install_path);
+    let vdf = fs::read_to_string(&compatvdfpath)?;
+    let (root, _key) = from_str_with_key::<serde_json::Map<String, serde_json::Value>>(&vdf)?;
 
-                    let Some(commandline) = get_commandline(&resolved_path)? else {
-                        continue;
-                    };
+    // Look inside compat_tools
+    if let Some(compat_tools) = root.get("compat_tools").and_then(|v| v.as_object()) {
+        for (alias, entry) in compat_tools {
+            if let Some(entry) = entry.as_object() {
+                // Filter out non-Windows tools
+                if entry
+                    .get("from_oslist")
+                    .and_then(|v| v.as_str())
+                    .map(|s| s != "windows")
+                    .unwrap_or(true)
+                {
+                    continue;
+                }
 
-                    let finish_cmdline =
-                        format!("{}{}", resolved_path.display().to_string(), commandline);
+                // Get display name (what is shown in Steam GUI)
+                let display_name = entry
+                    .get("display_name")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or(alias);
 
-                    tools.push(Tool {
-                        alias: alias.clone(),
-                        display_name: display_name.to_string(),
-                        commandline: finish_cmdline,
-                    });
-                }
+                // Get path to the Proton tool
+                let install_path = entry
+                    .get("install_path")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or(".");
+
+                let resolved_path = tool_dir.join(install_path);
+
+                let Some(commandline) = get_commandline(&resolved_path)? else {
+                    continue;
+                };
+
+                let finish_cmdline = format!("{}{}", resolved_path.display(), commandline);
+
+                tools.push(Tool::new(
+                    alias,
+                    display_name,
+                    &resolved_path,
+                    finish_cmdline,
+                    ToolSource::CompatibilityToolsD(compattoolsd.to_path_buf()),
+                ));
             }
         }
     }
@@ -153,12 +532,109 @@ pub fn list_compatibilitytoolsd(steam_path: &str) -> Result<Vec<Tool>, Box<dyn s
     Ok(tools)
 }
 
+// Library folders from steamapps/libraryfolders.vdf, always including the Steam install itself.
+pub fn list_library_folders(steam_path: &str) -> Vec<PathBuf> {
+    let mut libraries = vec![PathBuf::from(steam_path)];
+
+    let libraryfolders_path = PathBuf::from(steam_path).join("steamapps/libraryfolders.vdf");
+    let root = match fs::read_to_string(&libraryfolders_path)
+        .map_err(|err| err.to_string())
+        .and_then(|vdf| {
+            from_str_with_key::<serde_json::Map<String, serde_json::Value>>(&vdf)
+                .map(|(root, _key)| root)
+                .map_err(|err| err.to_string())
+        }) {
+        Ok(root) => root,
+        Err(err) => {
+            info!(
+                "list_library_folders. could not read {:?}: {}",
+                libraryfolders_path, err
+            );
+            return libraries;
+        }
+    };
+
+    for folder in root.values() {
+        let Some(path) = folder.str_at("path") else {
+            continue;
+        };
+        let path = PathBuf::from(path);
+        if !libraries.contains(&path) {
+            libraries.push(path);
+        }
+    }
+
+    libraries
+}
+
+// Returns the library folder and install path of an installed Steam app.
+fn find_app_install_dir(libraries: &[PathBuf], appid: u64) -> Option<(PathBuf, PathBuf)> {
+    for library in libraries {
+        let manifest_path = library
+            .join("steamapps")
+            .join(format!("appmanifest_{}.acf", appid));
+        let Ok(acf) = fs::read_to_string(&manifest_path) else {
+            continue;
+        };
+        let Ok((root, _key)) =
+            from_str_with_key::<serde_json::Map<String, serde_json::Value>>(&acf)
+        else {
+            error!("find_app_install_dir. could not parse {:?}", manifest_path);
+            continue;
+        };
+
+        if let Some(installdir) = root.get("installdir").and_then(|v| v.as_str()) {
+            let install_path = library.join("steamapps/common").join(installdir);
+            if install_path.exists() {
+                return Some((library.clone(), install_path));
+            }
+        }
+    }
+    None
+}
+
+// The parser panics on files it cannot read, so the header is checked before handing the file
+// to it. Unknown appinfo formats are reported as errors.
+fn read_appinfo_vdf(path: &Path) -> io::Result<Map<String, Value>> {
+    let mut header = [0u8; 16];
+    let mut file = fs::File::open(path)?;
+    file.read_exact(&mut header)?;
+    let file_len = file.metadata()?.len();
+
+    let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    match magic {
+        APPINFO_MAGIC_V28 => {}
+        APPINFO_MAGIC_V29 => {
+            let mut offset = [0u8; 8];
+            offset.copy_from_slice(&header[8..16]);
+            if u64::from_le_bytes(offset) >= file_len {
+                return Err(Error::other("appinfo.vdf string table is out of bounds"));
+            }
+        }
+        _ => {
+            return Err(Error::other(format!(
+                "appinfo.vdf format {:#x} is not supported",
+                magic
+            )))
+        }
+    }
+
+    Ok(open_appinfo_vdf(&path.to_path_buf(), Some(false)))
+}
+
 pub fn list_valve_proton_tools(steam_path: &str) -> Result<Vec<Tool>, Box<dyn std::error::Error>> {
     let path = PathBuf::from(steam_path).join("appcache/appinfo.vdf");
-    let appinfo_json = open_appinfo_vdf(&path, Some(false));
+    if !path.exists() {
+        return Err("appinfo.vdf not found".into());
+    }
+
+    let appinfo_json = read_appinfo_vdf(&path)?;
 
-    let manifests = get_app_info(&appinfo_json, 891390).unwrap();
+    let Some(manifests) = get_app_info(&appinfo_json, 891390) else {
+        return Err("compatibility tool manifests not found in appinfo.vdf".into());
+    };
 
+    let libraries = list_library_folders(steam_path);
     let mut tools = Vec::new();
 
     for (internal, tool) in compat_tools(manifests) {
@@ -166,24 +642,31 @@ pub fn list_valve_proton_tools(steam_path: &str) -> Result<Vec<Tool>, Box<dyn st
             continue;
         };
 
-        let Some(mut steam_dir) = SteamDir::locate() else {
-            continue;
-        };
-
-        let Some(app) = steam_dir.app(&appid.try_into().unwrap()) else {
+        let Some((library, proton_path)) = find_app_install_dir(&libraries, appid) else {
             continue;
         };
 
-        let proton_path = app.path.clone();
-
         if let Some(display) = tool.str_at("display_name") {
-            if let Some(cmd) = get_commandline(&proton_path)? {
-                let finish_cmdline = format!("{}{}", proton_path.display().to_string(), cmd);
-                tools.push(Tool {
-                    alias: internal.to_string(),
-                    display_name: display.to_string(),
-                    commandline: finish_cmdline,
-                });
+            // A single broken Proton install should not hide the others.
+            let commandline = match get_commandline(&proton_path) {
+                Ok(commandline) => commandline,
+                Err(err) => {
+                    error!(
+                        "list_valve_proton_tools. skipping {:?}: {}",
+                        proton_path, err
+                    );
+                    continue;
+                }
+            };
+            if let Some(cmd) = commandline {
+                let finish_cmdline = format!("{}{}", proton_path.display(), cmd);
+                tools.push(Tool::new(
+                    internal,
+                    display,
+                    &proton_path,
+                    finish_cmdline,
+                    ToolSource::SteamLibrary(library),
+                ));
             }
         }
     }
@@ -191,14 +674,429 @@ pub fn list_valve_proton_tools(steam_path: &str) -> Result<Vec<Tool>, Box<dyn st
     Ok(tools)
 }
 
-pub fn list_proton_tools(steam_path: &str) -> Result<Vec<Tool>, Box<dyn std::error::Error>> {
+// Steam's internal names for its own Proton builds, derived from the install folder name
+// when appinfo.vdf is not available. Example: "Proton 9.0 (Beta)" -> "proton_9".
+fn alias_from_tool_dir(dir_name: &str) -> String {
+    let lower = dir_name.to_lowercase();
+    if lower.contains("experimental") {
+        return "proton_experimental".to_string();
+    }
+    if lower.contains("hotfix") {
+        return "proton_hotfix".to_string();
+    }
+
+    let version = lower
+        .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .find(|part| part.starts_with(|c: char| c.is_ascii_digit()));
+    if let Some(version) = version {
+        let mut parts = version.split('.').filter(|part| !part.is_empty());
+        let major = parts.next().unwrap_or_default();
+        let minor = parts.next().unwrap_or("0");
+        if minor == "0" {
+            return format!("proton_{}", major);
+        }
+        return format!("proton_{}{}", major, minor);
+    }
+
+    lower
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+// Proton builds installed as Steam apps, found by scanning steamapps/common of every library.
+pub fn list_library_proton_tools(
+    steam_path: &str,
+) -> Result<Vec<Tool>, Box<dyn std::error::Error>> {
     let mut tools = Vec::new();
 
-    let official_tools = list_valve_proton_tools(steam_path)?;
-    let unofficial_tools = list_compatibilitytoolsd(steam_path)?;
+    for library in list_library_folders(steam_path) {
+        let common_path = library.join("steamapps/common");
+        let Ok(paths) = fs::read_dir(&common_path) else {
+            info!(
+                "list_library_proton_tools. could not read {:?}",
+                common_path
+            );
+            continue;
+        };
 
-    tools.extend(official_tools);
-    tools.extend(unofficial_tools);
+        for path in paths.flatten() {
+            let tool_dir = path.path();
+            if !tool_dir.join("toolmanifest.vdf").exists() || !tool_dir.join("proton").exists() {
+                continue;
+            }
+
+            let Some(dir_name) = tool_dir.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+
+            let cmd = match get_commandline(&tool_dir) {
+                Ok(Some(cmd)) => cmd,
+                Ok(None) => continue,
+                Err(err) => {
+                    error!(
+                        "list_library_proton_tools. skipping {:?}: {}",
+                        tool_dir, err
+                    );
+                    continue;
+                }
+            };
+
+            tools.push(Tool::new(
+                &alias_from_tool_dir(dir_name),
+                dir_name,
+                &tool_dir,
+                format!("{}{}", tool_dir.display(), cmd),
+                ToolSource::SteamLibrary(library.clone()),
+            ));
+        }
+    }
 
     Ok(tools)
 }
+
+pub fn list_proton_tools(steam_path: &str) -> ProtonDiscovery {
+    discover_proton_tools(steam_path, list_compatibilitytoolsd_paths(steam_path))
+}
+
+// Looks for tools below one Steam root and the given compatibilitytools.d directories only, so
+// discovery can run against a fixture Steam tree.
+fn discover_proton_tools(steam_path: &str, compattoolsd_paths: Vec<PathBuf>) -> ProtonDiscovery {
+    let mut discovery = ProtonDiscovery {
+        tools: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+
+    let steam_dir = PathBuf::from(steam_path);
+
+    discovery.add_source(
+        "appinfo.vdf",
+        steam_dir.join("appcache/appinfo.vdf"),
+        list_valve_proton_tools(steam_path),
+    );
+    discovery.add_source(
+        "Steam libraries",
+        steam_dir.join("steamapps/libraryfolders.vdf"),
+        list_library_proton_tools(steam_path),
+    );
+
+    for (idx, compattoolsd) in compattoolsd_paths.into_iter().enumerate() {
+        // Only the directory of the running Steam install is expected to exist.
+        if idx > 0 && !compattoolsd.exists() {
+            continue;
+        }
+        let result = list_compatibilitytoolsd(&compattoolsd);
+        discovery.add_source("compatibilitytools.d", compattoolsd, result);
+    }
+
+    sort_tools(&mut discovery.tools);
+    discovery
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::FixtureDir;
+
+    // A Steam root built in a fixture directory for one test.
+    struct FixtureSteam {
+        dir: FixtureDir,
+    }
+
+    impl FixtureSteam {
+        fn new(name: &str) -> FixtureSteam {
+            FixtureSteam {
+                dir: FixtureDir::new("proton", name),
+            }
+        }
+
+        fn path(&self) -> &str {
+            self.dir.root.to_str().unwrap()
+        }
+
+        fn write(&self, relative_path: impl AsRef<Path>, contents: &str) -> PathBuf {
+            self.dir.write(relative_path, contents)
+        }
+
+        fn add_compat_tool(&self, dir_name: &str, alias: &str, from_oslist: &str) {
+            self.write(
+                format!("compatibilitytools.d/{}/compatibilitytool.vdf", dir_name),
+                &format!(
+                    r#""compatibilitytools"
+{{
+  "compat_tools"
+  {{
+    "{alias}"
+    {{
+      "install_path" "."
+      "display_name" "{alias}"
+      "from_oslist" "{from_oslist}"
+      "to_oslist" "linux"
+    }}
+  }}
+}}
+"#
+                ),
+            );
+            self.write(
+                format!("compatibilitytools.d/{}/toolmanifest.vdf", dir_name),
+                &toolmanifest("\"commandline\" \"/proton %verb%\""),
+            );
+        }
+
+        fn add_library_proton(&self, library: &str, dir_name: &str) {
+            // Joined as paths, so an empty library name stays inside the fixture root.
+            let tool_dir = Path::new(library).join("steamapps/common").join(dir_name);
+            self.write(
+                tool_dir.join("toolmanifest.vdf"),
+                &toolmanifest(
+                    "\"commandline\" \"/proton %verb%\"\n  \"commandline_waitforexitandrun\" \"/proton waitforexitandrun\"",
+                ),
+            );
+            self.write(tool_dir.join("proton"), "#!/usr/bin/env python3\n");
+        }
+
+        fn write_libraryfolders(&self, libraries: &[&str]) {
+            let folders: Vec<String> = libraries
+                .iter()
+                .enumerate()
+                .map(|(idx, library)| {
+                    format!(
+                        "  \"{}\"\n  {{\n    \"path\" \"{}\"\n  }}\n",
+                        idx,
+                        self.dir.root.join(library).display()
+                    )
+                })
+                .collect();
+            self.write(
+                "steamapps/libraryfolders.vdf",
+                &format!("\"libraryfolders\"\n{{\n{}}}\n", folders.concat()),
+            );
+        }
+
+        fn discover(&self) -> ProtonDiscovery {
+            discover_proton_tools(
+                self.path(),
+                vec![self.dir.root.join("compatibilitytools.d")],
+            )
+        }
+    }
+
+    fn toolmanifest(commandlines: &str) -> String {
+        format!(
+            "\"manifest\"\n{{\n  \"version\" \"2\"\n  {}\n}}\n",
+            commandlines
+        )
+    }
+
+    fn aliases(tools: &[Tool]) -> Vec<&str> {
+        tools.iter().map(|tool| tool.alias.as_str()).collect()
+    }
+
+    #[test]
+    fn get_commandline_prefers_waitforexitandrun() {
+        let steam = FixtureSteam::new("commandline-waitforexitandrun");
+        steam.write(
+            "tool/toolmanifest.vdf",
+            &toolmanifest(
+                "\"commandline\" \"/proton %verb%\"\n  \"commandline_waitforexitandrun\" \"/proton-wait waitforexitandrun\"",
+            ),
+        );
+
+        let commandline = get_commandline(&steam.dir.root.join("tool")).unwrap();
+        assert_eq!(commandline, Some("/proton-wait".to_string()));
+    }
+
+    #[test]
+    fn get_commandline_falls_back_to_commandline() {
+        let steam = FixtureSteam::new("commandline-verb");
+        steam.write(
+            "tool/toolmanifest.vdf",
+            &toolmanifest("\"commandline\" \"/proton %verb%\""),
+        );
+
+        let commandline = get_commandline(&steam.dir.root.join("tool")).unwrap();
+        assert_eq!(commandline, Some("/proton".to_string()));
+    }
+
+    #[test]
+    fn get_commandline_without_commandline() {
+        let steam = FixtureSteam::new("commandline-none");
+        steam.write(
+            "tool/toolmanifest.vdf",
+            &toolmanifest("\"require_tool_appid\" \"1628350\""),
+        );
+
+        let commandline = get_commandline(&steam.dir.root.join("tool")).unwrap();
+        assert_eq!(commandline, None);
+    }
+
+    #[test]
+    fn get_commandline_missing_manifest() {
+        let steam = FixtureSteam::new("commandline-missing");
+        assert!(get_commandline(&steam.dir.root.join("tool")).is_err());
+    }
+
+    #[test]
+    fn compatibilitytoolsd_lists_windows_tools() {
+        let steam = FixtureSteam::new("compattoolsd-windows");
+        steam.add_compat_tool("GE-Proton9-20", "GE-Proton9-20", "windows");
+        steam.add_compat_tool("linux-runtime", "linux-runtime", "linux");
+
+        let compattoolsd = steam.dir.root.join("compatibilitytools.d");
+        let tools = list_compatibilitytoolsd(&compattoolsd).unwrap();
+
+        assert_eq!(aliases(&tools), vec!["GE-Proton9-20"]);
+        let tool = &tools[0];
+        assert_eq!(
+            tool.commandline,
+            format!("{}/proton", compattoolsd.join("GE-Proton9-20/.").display())
+        );
+        assert_eq!(tool.source, ToolSource::CompatibilityToolsD(compattoolsd));
+        assert_eq!(tool.vendor, ToolVendor::GE);
+        assert!(!tool.official);
+        assert_eq!(tool.version.as_ref().unwrap().components, vec![9, 20]);
+    }
+
+    #[test]
+    fn compatibilitytoolsd_skips_excluded_tools() {
+        let steam = FixtureSteam::new("compattoolsd-excluded");
+        steam.add_compat_tool("Luxtorpeda", "luxtorpeda", "windows");
+        steam.add_compat_tool("boxtron", "boxtron", "windows");
+        steam.add_compat_tool("Proton-tkg", "proton_tkg", "windows");
+
+        let tools = list_compatibilitytoolsd(&steam.dir.root.join("compatibilitytools.d")).unwrap();
+        assert_eq!(aliases(&tools), vec!["proton_tkg"]);
+    }
+
+    #[test]
+    fn compatibilitytoolsd_skips_broken_tools() {
+        let steam = FixtureSteam::new("compattoolsd-broken");
+        steam.add_compat_tool("GE-Proton8-32", "GE-Proton8-32", "windows");
+        steam.write(
+            "compatibilitytools.d/broken/compatibilitytool.vdf",
+            "\"compatibilitytools\"\n{\n",
+        );
+
+        let tools = list_compatibilitytoolsd(&steam.dir.root.join("compatibilitytools.d")).unwrap();
+        assert_eq!(aliases(&tools), vec!["GE-Proton8-32"]);
+    }
+
+    #[test]
+    fn compatibilitytoolsd_missing_directory() {
+        let steam = FixtureSteam::new("compattoolsd-missing");
+        assert!(list_compatibilitytoolsd(&steam.dir.root.join("compatibilitytools.d")).is_err());
+    }
+
+    #[test]
+    fn library_folders_include_steam_root() {
+        let steam = FixtureSteam::new("libraryfolders");
+        steam.write_libraryfolders(&["", "games"]);
+
+        let libraries = list_library_folders(steam.path());
+        assert_eq!(
+            libraries,
+            vec![steam.dir.root.clone(), steam.dir.root.join("games")]
+        );
+    }
+
+    #[test]
+    fn library_proton_tools_from_every_library() {
+        let steam = FixtureSteam::new("library-tools");
+        steam.write_libraryfolders(&["", "games"]);
+        steam.add_library_proton("", "Proton 8.0");
+        steam.add_library_proton("games", "Proton 9.0 (Beta)");
+        steam.add_library_proton("games", "Proton - Experimental");
+        steam.write("games/steamapps/common/Some Game/game.exe", "");
+
+        let mut tools = list_library_proton_tools(steam.path()).unwrap();
+        sort_tools(&mut tools);
+
+        assert_eq!(
+            aliases(&tools),
+            vec!["proton_9", "proton_8", "proton_experimental"]
+        );
+        assert!(tools.iter().all(|tool| tool.official));
+        assert!(tools.iter().all(|tool| tool.vendor == ToolVendor::Valve));
+        assert_eq!(
+            tools[0].commandline,
+            format!(
+                "{}/proton",
+                steam
+                    .dir
+                    .root
+                    .join("games/steamapps/common/Proton 9.0 (Beta)")
+                    .display()
+            )
+        );
+        assert_eq!(
+            tools[0].source,
+            ToolSource::SteamLibrary(steam.dir.root.join("games"))
+        );
+    }
+
+    #[test]
+    fn discovery_without_appinfo_or_compatibilitytoolsd() {
+        let steam = FixtureSteam::new("discovery-empty");
+
+        let discovery = steam.discover();
+        assert!(discovery.tools.is_empty());
+        assert_eq!(discovery.diagnostics.len(), 3);
+        assert!(discovery.diagnostics[0].error.is_some());
+        assert!(discovery.diagnostics[2].error.is_some());
+    }
+
+    #[test]
+    fn discovery_combines_sources() {
+        let steam = FixtureSteam::new("discovery-combined");
+        steam.add_library_proton("", "Proton 9.0");
+        steam.add_compat_tool("GE-Proton9-20", "GE-Proton9-20", "windows");
+        steam.add_compat_tool("proton_9", "proton_9", "windows");
+
+        let discovery = steam.discover();
+        assert_eq!(aliases(&discovery.tools), vec!["proton_9", "GE-Proton9-20"]);
+        assert_eq!(discovery.diagnostics[1].tools_found, 1);
+        assert_eq!(discovery.diagnostics[2].tools_found, 1);
+        assert_eq!(
+            find_newest_stable_valve_tool(&discovery.tools).map(|tool| tool.alias.as_str()),
+            Some("proton_9")
+        );
+    }
+
+    #[test]
+    fn tool_versions() {
+        let version = ToolVersion::parse("Proton 9.0-4").unwrap();
+        assert_eq!(version.components, vec![9, 0, 4]);
+        assert!(!version.prerelease);
+
+        assert!(ToolVersion::parse("Proton 9.0 (Beta)").unwrap().prerelease);
+        assert!(ToolVersion::parse("Proton Experimental").is_none());
+        assert!(ToolVersion::parse("Proton 8.0-5").unwrap() < version);
+
+        assert_eq!(alias_from_tool_dir("Proton 6.3"), "proton_63");
+        assert_eq!(alias_from_tool_dir("Proton Hotfix"), "proton_hotfix");
+    }
+
+    #[test]
+    fn tool_constraints() {
+        let steam = FixtureSteam::new("constraints");
+        steam.add_library_proton("", "Proton 7.0");
+        steam.add_library_proton("", "Proton 9.0");
+        steam.add_compat_tool("GE-Proton9-20", "GE-Proton9-20", "windows");
+        let discovery = steam.discover();
+
+        let constraints = ProtonConstraints {
+            allowed: None,
+            minimum_version: Some("8.0".to_string()),
+            blocked: vec!["proton_9".to_string()],
+        };
+
+        let proton_7 = find_tool(&discovery.tools, "proton_7").unwrap();
+        assert!(check_tool_constraints(proton_7, &constraints).is_some());
+        let proton_9 = find_tool(&discovery.tools, "proton_9").unwrap();
+        assert!(check_tool_constraints(proton_9, &constraints).is_some());
+
+        let best = find_best_tool(&discovery.tools, &constraints).unwrap();
+        assert_eq!(best.alias, "GE-Proton9-20");
+    }
+}
@@ -0,0 +1,201 @@
+use log::info;
+use std::fs;
+use std::io;
+use std::io::Error;
+use std::path::PathBuf;
+
+use crate::command::get_proton_alias;
+use crate::config;
+use crate::package;
+use crate::package_metadata;
+use crate::prefix;
+use crate::prefix::PrefixKind;
+use crate::proton_handler;
+use crate::proton_handler::Tool;
+use crate::user_env;
+
+static RUNNER_CHOICE_FILE: &str = "runner_choice.txt";
+static WINEPREFIX: &str = "WINEPREFIX";
+
+pub static PROTON_RUNNER: &str = "proton";
+pub static WINE_RUNNER: &str = "wine";
+pub static WINE_CHOICE_NAME: &str = "Wine (System)";
+
+// How a Windows executable gets started.
+pub enum Runner {
+    Proton {
+        tool: Tool,
+        compat_data_path: Option<PathBuf>,
+    },
+    Wine {
+        binary: PathBuf,
+        prefix: PathBuf,
+    },
+}
+
+impl Runner {
+    pub fn name(&self) -> String {
+        match self {
+            Runner::Proton { tool, .. } => tool.display_name.to_string(),
+            Runner::Wine { binary, .. } => format!("Wine ({})", binary.display()),
+        }
+    }
+
+    // Program to run and the arguments that go before the executable's own arguments.
+    pub fn command(&self, exe: String) -> (String, Vec<String>) {
+        match self {
+            Runner::Proton { tool, .. } => (
+                tool.commandline.clone(),
+                vec!["waitforexitandrun".to_string(), exe],
+            ),
+            Runner::Wine { binary, .. } => (binary.display().to_string(), vec![exe]),
+        }
+    }
+
+    pub fn envs(&self) -> Vec<(String, String)> {
+        match self {
+            Runner::Proton {
+                compat_data_path, ..
+            } => compat_data_path
+                .iter()
+                .map(|path| {
+                    (
+                        prefix::STEAM_COMPAT_DATA_PATH.to_string(),
+                        path.display().to_string(),
+                    )
+                })
+                .collect(),
+            Runner::Wine { prefix, .. } => {
+                vec![(WINEPREFIX.to_string(), prefix.display().to_string())]
+            }
+        }
+    }
+}
+
+// The runner picked by the user for a game: "wine", or the path to a wine binary.
+pub fn find_user_runner_choice(app_id: &str) -> Option<String> {
+    let runner_choice_path = package::place_config_file(app_id, RUNNER_CHOICE_FILE).ok()?;
+    if !runner_choice_path.exists() {
+        return None;
+    }
+
+    let runner_choice = fs::read_to_string(runner_choice_path).ok()?;
+    let runner_choice = runner_choice.trim();
+    if runner_choice.is_empty() || runner_choice == PROTON_RUNNER {
+        None
+    } else {
+        Some(runner_choice.to_string())
+    }
+}
+
+pub fn save_user_runner_choice(app_id: &str, runner_choice: Option<&str>) -> io::Result<()> {
+    let runner_choice_path = package::place_config_file(app_id, RUNNER_CHOICE_FILE)?;
+    match runner_choice {
+        Some(runner_choice) => {
+            info!(
+                "save_user_runner_choice. {} will use {}",
+                app_id, runner_choice
+            );
+            fs::write(runner_choice_path, runner_choice)
+        }
+        None => {
+            if runner_choice_path.exists() {
+                fs::remove_file(runner_choice_path)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+// Resolves "wine" to the configured or system wine, anything else is a path to a wine binary.
+pub fn find_wine_binary(runner_choice: &str) -> Option<PathBuf> {
+    if runner_choice != WINE_RUNNER {
+        let binary = PathBuf::from(runner_choice);
+        return if binary.is_file() { Some(binary) } else { None };
+    }
+
+    let config = config::Config::from_config_file();
+    if let Some(wine_path) = config.wine_path {
+        let binary = PathBuf::from(wine_path);
+        if binary.is_file() {
+            return Some(binary);
+        }
+        info!(
+            "find_wine_binary. configured wine_path {:?} not found",
+            binary
+        );
+    }
+
+    which::which(WINE_RUNNER).ok()
+}
+
+fn wine_runner(app_id: &str, runner_choice: &str) -> io::Result<Runner> {
+    let Some(binary) = find_wine_binary(runner_choice) else {
+        return Err(Error::other(std::format!(
+            "Wine was selected to run this game, but {} could not be found. Install wine or set wine_path in the config and try again.",
+            runner_choice
+        )));
+    };
+
+    Ok(Runner::Wine {
+        binary,
+        prefix: prefix::place_prefix(PrefixKind::Wine, app_id)?,
+    })
+}
+
+/// Picks the runner for a Windows executable: the user's choice for the game, then the runner
+/// requested by the package metadata, then Proton. Wine is used when no Proton is installed.
+/// Returns None when the executable should be started as is.
+pub fn find_runner(game_info: &package_metadata::Game) -> io::Result<Option<Runner>> {
+    let app_id = user_env::steam_app_id();
+
+    if let Some(runner_choice) = find_user_runner_choice(&app_id) {
+        info!(
+            "find_runner. using runner picked by user: {}",
+            runner_choice
+        );
+        return wine_runner(&app_id, &runner_choice).map(Some);
+    }
+
+    if game_info.runner.as_deref() == Some(WINE_RUNNER) {
+        info!("find_runner. using wine as requested by the package");
+        return wine_runner(&app_id, WINE_RUNNER).map(Some);
+    }
+
+    let Some(steam_path) = user_env::steam_install_path() else {
+        if find_wine_binary(WINE_RUNNER).is_some() {
+            info!("find_runner. not running from steam, using wine");
+            return wine_runner(&app_id, WINE_RUNNER).map(Some);
+        }
+        return Ok(None);
+    };
+
+    let discovery = proton_handler::list_proton_tools(&steam_path);
+    if discovery.tools.is_empty() && find_wine_binary(WINE_RUNNER).is_some() {
+        info!("find_runner. no proton installed, using wine");
+        return wine_runner(&app_id, WINE_RUNNER).map(Some);
+    }
+
+    let Ok(proton_version) = get_proton_alias(game_info) else {
+        return Ok(None);
+    };
+
+    match proton_handler::find_tool(&discovery.tools, &proton_version) {
+        Some(tool) => {
+            let mut compat_data_path = None;
+            if prefix::should_manage_proton_prefix() {
+                compat_data_path = Some(prefix::place_prefix(PrefixKind::Proton, &app_id)?);
+            }
+
+            Ok(Some(Runner::Proton {
+                tool: tool.clone(),
+                compat_data_path,
+            }))
+        }
+        None => Err(Error::other(std::format!(
+            "Error finding the requested proton version of {}. Check to see if it is installed and try again.\n{}",
+            proton_version,
+            discovery.diagnostics_message()
+        ))),
+    }
+}
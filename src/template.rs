@@ -0,0 +1,222 @@
+use log::info;
+use std::env;
+use std::io;
+use std::io::Error;
+
+use crate::package;
+use crate::user_env;
+
+static DEPPATH_PREFIX: &str = "DEPPATH_";
+static DIALOG_PREFIX: &str = "DIALOG:";
+static DIALOGRESPONSE_PREFIX: &str = "DIALOGRESPONSE_";
+
+/// Resolves `${NAME}` variables in package commands, remembering the ones that could not be
+/// resolved so they can be reported together.
+///
+/// Supported variables:
+/// - `${GAME_DIR}` - the install directory of the game
+/// - `${APP_ID}` - the Steam app id of the game
+/// - `${CACHE_DIR}` - the luxtorpeda cache directory of the game
+/// - `${DEPPATH_<appid>}` - the install directory of a game listed in `app_ids_deps`
+/// - `${DIALOG:<key>}` - the answer to a setup dialog
+///
+/// Other `${...}` text is left as it is, since it can be meant for the shell or the engine.
+#[derive(Default)]
+pub struct TemplateResolver {
+    unresolved: Vec<String>,
+}
+
+impl TemplateResolver {
+    pub fn new() -> TemplateResolver {
+        Default::default()
+    }
+
+    pub fn resolve(&mut self, value: &str) -> String {
+        let mut resolved = String::new();
+        let mut rest = value;
+
+        while let Some(start) = rest.find("${") {
+            resolved.push_str(&rest[..start]);
+            let after_start = &rest[start + 2..];
+
+            let Some(end) = after_start.find('}') else {
+                if is_known_variable(after_start) {
+                    self.add_unresolved(&rest[start..]);
+                }
+                resolved.push_str(&rest[start..]);
+                rest = "";
+                break;
+            };
+
+            let name = &after_start[..end];
+            match lookup_variable(name) {
+                Some(variable_value) => resolved.push_str(&variable_value),
+                None => {
+                    if is_known_variable(name) {
+                        self.add_unresolved(&std::format!("${{{}}}", name));
+                    }
+                    resolved.push_str(&rest[start..start + end + 3]);
+                }
+            }
+            rest = &after_start[end + 1..];
+        }
+
+        resolved.push_str(rest);
+        resolved
+    }
+
+    pub fn resolve_all(&mut self, values: &[String]) -> Vec<String> {
+        values.iter().map(|value| self.resolve(value)).collect()
+    }
+
+    pub fn resolve_option(&mut self, value: &Option<String>) -> Option<String> {
+        value.as_ref().map(|value| self.resolve(value))
+    }
+
+    /// Returns an error naming every variable that could not be resolved.
+    pub fn finish(self, context: &str) -> io::Result<()> {
+        if self.unresolved.is_empty() {
+            return Ok(());
+        }
+
+        Err(Error::other(std::format!(
+            "Could not resolve {} in the {}.",
+            self.unresolved.join(", "),
+            context
+        )))
+    }
+
+    fn add_unresolved(&mut self, variable: &str) {
+        if !self.unresolved.iter().any(|existing| existing == variable) {
+            info!("template. could not resolve {}", variable);
+            self.unresolved.push(variable.to_string());
+        }
+    }
+}
+
+fn is_known_variable(name: &str) -> bool {
+    ["GAME_DIR", "APP_ID", "CACHE_DIR"].contains(&name)
+        || name.starts_with(DEPPATH_PREFIX)
+        || name.starts_with(DIALOG_PREFIX)
+}
+
+fn lookup_variable(name: &str) -> Option<String> {
+    match name {
+        "GAME_DIR" => match user_env::lux_pwd() {
+            Ok(game_dir) => Some(game_dir),
+            Err(_) => env::current_dir()
+                .ok()
+                .map(|game_dir| game_dir.display().to_string()),
+        },
+        "APP_ID" => Some(user_env::steam_app_id()),
+        "CACHE_DIR" => {
+            let xdg_dirs = xdg::BaseDirectories::with_prefix("luxtorpeda");
+            xdg_dirs
+                .get_cache_home()
+                .map(|cache_home| cache_home.join(user_env::steam_app_id()))
+                .map(|cache_dir| cache_dir.display().to_string())
+        }
+        _ if name.starts_with(DEPPATH_PREFIX) => env::var(name).ok(),
+        _ => {
+            // Answers from an earlier setup are used when the dialog was not shown this launch.
+            let key = name.strip_prefix(DIALOG_PREFIX)?;
+            env::var(std::format!("{}{}", DIALOGRESPONSE_PREFIX, key))
+                .ok()
+                .or_else(|| package::find_dialog_answers(&user_env::steam_app_id()).remove(key))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_without_variables() {
+        let mut resolver = TemplateResolver::new();
+        assert_eq!(resolver.resolve("setup.exe /S"), "setup.exe /S");
+        assert_eq!(resolver.resolve("$HOME and $ stay"), "$HOME and $ stay");
+        assert!(resolver.finish("test").is_ok());
+    }
+
+    #[test]
+    fn builtin_variables() {
+        let mut resolver = TemplateResolver::new();
+        let game_dir = user_env::lux_pwd()
+            .unwrap_or_else(|_| env::current_dir().unwrap().display().to_string());
+        assert_eq!(
+            resolver.resolve("${GAME_DIR}/data"),
+            std::format!("{}/data", game_dir)
+        );
+        assert_eq!(resolver.resolve("${APP_ID}"), user_env::steam_app_id());
+        assert!(resolver
+            .resolve("${CACHE_DIR}")
+            .ends_with(&user_env::steam_app_id()));
+        assert!(resolver.finish("test").is_ok());
+    }
+
+    #[test]
+    fn deppath_and_dialog_variables() {
+        env::set_var("DEPPATH_TEMPLATETEST", "/games/dependency");
+        env::set_var("DIALOGRESPONSE_templatetestkey", "answer");
+
+        let mut resolver = TemplateResolver::new();
+        assert_eq!(
+            resolver.resolve_all(&[
+                "${DEPPATH_TEMPLATETEST}/base".to_string(),
+                "--lang=${DIALOG:templatetestkey}".to_string(),
+            ]),
+            vec!["/games/dependency/base", "--lang=answer"]
+        );
+        assert_eq!(
+            resolver.resolve_option(&Some("${DIALOG:templatetestkey}".to_string())),
+            Some("answer".to_string())
+        );
+        assert!(resolver.finish("test").is_ok());
+    }
+
+    #[test]
+    fn unresolved_variables_are_reported_once() {
+        let mut resolver = TemplateResolver::new();
+        assert_eq!(
+            resolver.resolve("${DIALOG:templatetestmissing}/${DIALOG:templatetestmissing}"),
+            "${DIALOG:templatetestmissing}/${DIALOG:templatetestmissing}"
+        );
+        assert_eq!(
+            resolver.resolve("${DEPPATH_TEMPLATETESTMISSING}"),
+            "${DEPPATH_TEMPLATETESTMISSING}"
+        );
+
+        let err = resolver.finish("setup command").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Could not resolve ${DIALOG:templatetestmissing}, ${DEPPATH_TEMPLATETESTMISSING} in the setup command."
+        );
+    }
+
+    #[test]
+    fn other_variables_pass_through() {
+        let mut resolver = TemplateResolver::new();
+        assert_eq!(
+            resolver.resolve("sh -c 'cd ${HOME} && ./run ${GAME_ARGS:-} ${1'"),
+            "sh -c 'cd ${HOME} && ./run ${GAME_ARGS:-} ${1'"
+        );
+        assert_eq!(
+            resolver.resolve("${XDG_DATA_HOME}/${APP_ID}"),
+            std::format!("${{XDG_DATA_HOME}}/{}", user_env::steam_app_id())
+        );
+        assert!(resolver.finish("engine arguments").is_ok());
+    }
+
+    #[test]
+    fn unterminated_variable() {
+        let mut resolver = TemplateResolver::new();
+        assert_eq!(resolver.resolve("run ${GAME_DIR"), "run ${GAME_DIR");
+
+        let err = resolver.finish("engine arguments").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Could not resolve ${GAME_DIR in the engine arguments."
+        );
+    }
+}
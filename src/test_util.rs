@@ -0,0 +1,37 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A directory in the temp directory for one test, removed when dropped.
+pub struct FixtureDir {
+    pub root: PathBuf,
+}
+
+impl FixtureDir {
+    // The area names the module the test is in, so tests with the same name do not collide.
+    pub fn new(area: &str, name: &str) -> FixtureDir {
+        let root = env::temp_dir().join(std::format!(
+            "luxtorpeda-{}-test-{}-{}",
+            area,
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        FixtureDir { root }
+    }
+
+    // Writes a file below the root, creating the directories leading to it.
+    pub fn write(&self, relative_path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.root.join(relative_path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for FixtureDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}